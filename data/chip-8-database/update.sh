@@ -0,0 +1,10 @@
+#!/bin/sh
+# Replaces the embedded programs.json with upstream's full catalogue, with its MIT licence.
+# Rebuild afterwards to embed it.
+set -e
+
+UPSTREAM=https://raw.githubusercontent.com/chip-8/chip-8-database/master
+cd "$(dirname "$0")"
+curl -fsSL "$UPSTREAM/database/programs.json" -o programs.json
+curl -fsSL "$UPSTREAM/LICENSE" -o LICENSE
+echo "Updated programs.json with $(grep -c '"title"' programs.json) programs"
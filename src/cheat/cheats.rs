@@ -0,0 +1,243 @@
+// cheats.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::MEMORY_SIZE;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+/**
+What a cheat holds at a value.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(u16),
+    Register(usize),
+    DelayTimer,
+    SoundTimer,
+}
+
+
+/**
+A cheat code, `ADDR=VALUE` to hold a byte of memory or `VX=VALUE`, `DT=VALUE` and
+`ST=VALUE` to hold a register or timer, all in hex, e.g. `3F0=03` or `VA=FF`.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Code {
+    pub target: Target,
+    pub value: u8,
+}
+
+impl Code {
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cheat code {:?}, expected ADDR=VALUE, VX=VALUE, DT=VALUE or ST=VALUE in hex", code);
+        let (target, value) = code.split_once('=').ok_or_else(invalid)?;
+        let target = target.trim().to_uppercase();
+        let value = u8::from_str_radix(value.trim(), 16).map_err(|_| invalid())?;
+
+        let target = match target.as_str() {
+            "DT" => Target::DelayTimer,
+            "ST" => Target::SoundTimer,
+            _ if target.len() == 2 && target.starts_with('V') => {
+                Target::Register(usize::from_str_radix(&target[1..], 16).map_err(|_| invalid())?)
+            },
+            _ => match u16::from_str_radix(&target, 16) {
+                Ok(addr) if (addr as usize) < MEMORY_SIZE => Target::Memory(addr),
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(Code { target, value })
+    }
+
+
+    /**
+    Puts the value back if the game changed it.
+    */
+    pub fn apply(&self, chip8: &mut Chip8) {
+        match self.target {
+            Target::Memory(addr) => {
+                // Writing invalidates decoded code, so only write when it changed
+                if chip8.memory()[addr as usize] != self.value {
+                    chip8.write_memory(addr as usize, self.value);
+                }
+            },
+            Target::Register(register) => chip8.set_v(register, self.value),
+            Target::DelayTimer => chip8.delay_timer = self.value,
+            Target::SoundTimer => chip8.sound_timer = self.value,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.target {
+            Target::Memory(addr) => write!(f, "{:03X}", addr)?,
+            Target::Register(register) => write!(f, "V{:X}", register)?,
+            Target::DelayTimer => write!(f, "DT")?,
+            Target::SoundTimer => write!(f, "ST")?,
+        }
+        write!(f, "={:02X}", self.value)
+    }
+}
+
+impl TryFrom<String> for Code {
+    type Error = String;
+
+    fn try_from(code: String) -> Result<Self, String> {
+        Self::parse(&code)
+    }
+}
+
+impl From<Code> for String {
+    fn from(code: Code) -> Self {
+        code.to_string()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cheat {
+    pub name: String,
+    pub code: Code,
+    /// Disabled cheats are kept but not applied
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+
+/**
+Applies the enabled cheats, once per frame so the game never sees other values for long.
+*/
+pub fn apply(cheats: &[Cheat], chip8: &mut Chip8) {
+    for cheat in cheats.iter().filter(|cheat| cheat.enabled) {
+        cheat.code.apply(chip8);
+    }
+}
+
+
+/**
+The cheats of every ROM, by SHA-1, kept in a TOML file with a list per ROM:
+
+```toml
+[[roms.0c1a3b...]]
+name = "Infinite lives"
+code = "3F0=03"
+enabled = true
+```
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheatFile {
+    pub roms: BTreeMap<String, Vec<Cheat>>,
+}
+
+impl CheatFile {
+    /**
+    Reads the cheats at `path`. A missing file has none.
+    */
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(CheatFile::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+        }
+        let text = toml::to_string(self).expect("Cheats are always serializable");
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+
+    pub fn cheats(&self, sha1: &str) -> Vec<Cheat> {
+        self.roms.get(sha1).cloned().unwrap_or_default()
+    }
+
+
+    pub fn set(&mut self, sha1: &str, cheats: &[Cheat]) {
+        if cheats.is_empty() {
+            self.roms.remove(sha1);
+        } else {
+            self.roms.insert(sha1.to_string(), cheats.to_vec());
+        }
+    }
+}
+
+
+/**
+`cheats.toml` next to the config file.
+*/
+pub fn default_path() -> Option<PathBuf> {
+    crate::config::default_path().and_then(|path| path.parent().map(|dir| dir.join("cheats.toml")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_code() {
+        assert_eq!(Code::parse("3f0=3"), Ok(Code { target: Target::Memory(0x3F0), value: 3 }));
+        assert_eq!(Code::parse("va = FF"), Ok(Code { target: Target::Register(0xA), value: 0xFF }));
+        assert_eq!(Code::parse("DT=0").unwrap().target, Target::DelayTimer);
+        assert_eq!(Code::parse("st=10").unwrap().target, Target::SoundTimer);
+        for code in ["3F0", "1000=1", "3F0=100", "VG=1", "=1", "x=1"] {
+            assert!(Code::parse(code).is_err(), "{}", code);
+        }
+        for code in ["3F0=03", "VA=FF", "DT=00", "ST=10"] {
+            assert_eq!(Code::parse(code).unwrap().to_string(), code);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        let cheats: Vec<Cheat> = [("3F0=03", true), ("V5=09", true), ("DT=20", true), ("3F1=07", false)].iter()
+            .map(|&(code, enabled)| Cheat { name: code.to_string(), code: Code::parse(code).unwrap(), enabled })
+            .collect();
+
+        apply(&cheats, &mut chip8);
+        assert_eq!(chip8.memory()[0x3F0..0x3F2], [3, 0]);
+        assert_eq!(chip8.v()[5], 9);
+        assert_eq!(chip8.delay_timer, 0x20);
+    }
+
+    #[test]
+    fn test_file_round_trips() {
+        let path = std::env::temp_dir().join(format!("octorust-cheats-{}", std::process::id())).join("cheats.toml");
+        assert_eq!(CheatFile::load(&path), Ok(CheatFile::default()));
+
+        let mut file = CheatFile::default();
+        let lives = Cheat { name: "Infinite lives".to_string(), code: Code::parse("3F0=03").unwrap(), enabled: false };
+        file.set("abc", std::slice::from_ref(&lives));
+        file.set("def", &[]);
+        file.save(&path).unwrap();
+        let loaded = CheatFile::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded, file);
+        assert_eq!(loaded.cheats("abc"), [lives]);
+        assert!(loaded.cheats("def").is_empty());
+
+        let parsed: CheatFile = toml::from_str("[[roms.abc]]\nname = \"Speed\"\ncode = \"V3=10\"\n").unwrap();
+        assert!(parsed.cheats("abc")[0].enabled, "enabled by default");
+        assert!(toml::from_str::<CheatFile>("[[roms.abc]]\nname = \"Bad\"\ncode = \"V3\"\n").is_err());
+    }
+}
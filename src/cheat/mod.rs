@@ -0,0 +1,2 @@
+pub mod cheats;
+pub mod search;
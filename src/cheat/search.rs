@@ -0,0 +1,129 @@
+// search.rs
+
+use std::fmt;
+
+
+/**
+How a byte has to compare with the last snapshot to stay a candidate. Searches are
+narrowed from the pause menu of the windowed frontends.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// Holds this value now, whatever it held before
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, before: u8, now: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => now == value,
+            Comparison::Changed => now != before,
+            Comparison::Unchanged => now == before,
+            Comparison::Increased => now > before,
+            Comparison::Decreased => now < before,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Equal(value) => write!(f, "equal to {:02X}", value),
+            Comparison::Changed => write!(f, "changed"),
+            Comparison::Unchanged => write!(f, "unchanged"),
+            Comparison::Increased => write!(f, "increased"),
+            Comparison::Decreased => write!(f, "decreased"),
+        }
+    }
+}
+
+
+/**
+Finds where a game keeps a value, e.g. the lives left, by narrowing down the addresses of
+the memory step by step: start with every address, then keep those that changed the way
+the value did since the last step, playing in between, until few are left.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamSearch {
+    /// The memory at the last step
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    /**
+    Starts a search over the whole of `memory`.
+    */
+    pub fn new(memory: &[u8]) -> Self {
+        RamSearch { snapshot: memory.to_vec(), candidates: (0..memory.len() as u16).collect() }
+    }
+
+
+    /**
+    Keeps the candidates whose byte compares with the last snapshot as asked, then takes
+    a new snapshot.
+    */
+    pub fn filter(&mut self, memory: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| comparison.matches(snapshot[addr as usize], memory[addr as usize]));
+        self.snapshot.copy_from_slice(memory);
+    }
+
+
+    /**
+    Addresses that matched every step so far, in order.
+    */
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_narrowing() {
+        let mut memory = vec![0u8; 16];
+        memory[3] = 5;
+        memory[9] = 5;
+        let mut search = RamSearch::new(&memory);
+        assert_eq!(search.candidates().len(), 16);
+
+        search.filter(&memory, Comparison::Equal(5));
+        assert_eq!(search.candidates(), [3, 9]);
+
+        // Lives lost at 3, something else counting up at 9
+        memory[3] = 4;
+        memory[9] = 6;
+        memory[0] = 1;
+        let mut changed = search.clone();
+        changed.filter(&memory, Comparison::Changed);
+        assert_eq!(changed.candidates(), [3, 9], "only the candidates left are looked at");
+        search.filter(&memory, Comparison::Decreased);
+        assert_eq!(search.candidates(), [3]);
+
+        search.filter(&memory, Comparison::Unchanged);
+        assert_eq!(search.candidates(), [3]);
+        memory[3] = 3;
+        search.filter(&memory, Comparison::Increased);
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_moves_on() {
+        let mut memory = vec![0u8; 4];
+        let mut search = RamSearch::new(&memory);
+        memory[1] = 1;
+        search.filter(&memory, Comparison::Increased);
+        assert_eq!(search.candidates(), [1]);
+        // Compared with the last step, not the start
+        search.filter(&memory, Comparison::Increased);
+        assert!(search.candidates().is_empty());
+    }
+}
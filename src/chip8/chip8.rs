@@ -1,6 +1,11 @@
+use crate::chip8::decode_cache::DecodeCache;
 use crate::chip8::instruction::Instruction;
+use crate::chip8::recompiler::{Handler, Recompiler};
 use crate::chip8::low_level_operations::get_nibble;
 use crate::chip8::stack::Stack;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::state::{SaveState, screen_rows, parse_screen_rows};
+use crate::chip8::trace::{Registers, Step, Tracer};
 
 use crate::chip8::constants::{
     MIN_ADDRESS,
@@ -12,23 +17,38 @@ use crate::chip8::constants::{
     HEIGHT,
 };
 
-use crate::chip8::errors::{SubroutineError, RegisterError};
+use crate::chip8::errors::{JumpError, SubroutineError, RegisterError};
+
+use sha1::{Digest, Sha1};
 
 use std::fs::File;
 use std::io::Read;
 
 
-// const ROM_PATH: &str ="/Users/fas/dev/octorust/roms/3-corax+.ch8";
-const ROM_PATH: &str ="/Users/fas/dev/octorust/roms/IBMLogo.ch8";
-
 pub struct Chip8 {
     pub pixel_array: [[bool; WIDTH]; HEIGHT],
+    pub keypad: [bool; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub quirks: Quirks,
+    /// Set by `DXYN` with the vblank quirk; the runner ends the frame early when it is
+    pub waiting_for_vblank: bool,
+    /// Logs every executed instruction when set
+    pub tracer: Option<Tracer>,
+    /// Instructions decoded so far, reused while their memory isn't written to. Interpreting
+    /// without it is only useful to compare
+    pub decode_cache: Option<DecodeCache>,
+    /// Compiled basic blocks for `run_block`, which interprets one instruction at a time
+    /// when this isn't set
+    pub recompiler: Option<Recompiler>,
+    /// Why the machine stopped, if an instruction couldn't run. Nothing runs until a reset
+    fault: Option<String>,
     memory: [u8; MEMORY_SIZE],
+    /// The loaded ROM, to restore memory on `reset`
+    rom: Vec<u8>,
     index: u16,
     pc: u16,
     stack: Stack<u16>,
-    // delay_timer: u8,
-    // sound_timer: u8,
     v: [u8; 16]
 }
 
@@ -36,190 +56,294 @@ impl Chip8 {
     pub fn new() -> Self {
         let mut chip8 = Chip8 {
             pixel_array: [[false; WIDTH]; HEIGHT],
+            keypad: [false; 16],
+            delay_timer: 0,
+            sound_timer: 0,
+            quirks: Quirks::default(),
+            waiting_for_vblank: false,
+            tracer: None,
+            decode_cache: Some(DecodeCache::new()),
+            recompiler: None,
+            fault: None,
             memory: [0; MEMORY_SIZE],
+            rom: Vec::new(),
             index: 0,
             pc: ROM_OFFSET,
             stack: Stack::new(),
             v: [0; 16],
-            // delay: 0,
-            // sound: 0,
         };
 
         chip8.load_font();
-        chip8.load_rom(ROM_PATH).expect("Failed loading rom");
         chip8
     }
 
-    
+
+    /**
+    Restarts the loaded ROM as if just powered on: clears the registers, screen, stack,
+    timers and keypad, and restores memory to the font and the ROM as loaded, undoing
+    any writes. Quirks and the tracer are kept.
+    */
+    pub fn reset(&mut self) {
+        self.pixel_array = [[false; WIDTH]; HEIGHT];
+        self.keypad = [false; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.waiting_for_vblank = false;
+        self.fault = None;
+        self.memory = [0; MEMORY_SIZE];
+        self.index = 0;
+        self.pc = ROM_OFFSET;
+        self.stack.clear();
+        self.v = [0; 16];
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.clear();
+        }
+
+        self.load_font();
+        let start = ROM_OFFSET as usize;
+        self.memory[start..start + self.rom.len()].copy_from_slice(&self.rom);
+    }
+
+
+    /**
+    Why the machine stopped, if it did: a jump or call out of memory, a return with an
+    empty stack or a stack overflow, or running off the end of memory. The program counter
+    is left on the instruction that failed, and `cycle` and `run_block` do nothing until
+    the machine is reset or a state is loaded.
+    */
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+
+    pub fn set_v(&mut self, register: usize, value: u8) {
+        self.v[register] = value;
+    }
+
+
+    /**
+    Return addresses of the subroutines being run, outermost first.
+    */
+    pub fn stack(&self) -> &[u16] {
+        self.stack.as_slice()
+    }
+
+
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+
+    /**
+    The ROM as loaded, before the program changed anything.
+    */
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            pc: self.pc,
+            index: self.index,
+            v: self.v,
+            stack: self.stack().to_vec(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory: self.memory.to_vec(),
+            screen: screen_rows(&self.pixel_array),
+        }
+    }
+
+
+    /**
+    Restores a state saved by `save_state`, leaving the machine untouched if it's invalid.
+    */
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        state.validate()?;
+        self.pixel_array = parse_screen_rows(&state.screen)?;
+        self.pc = state.pc;
+        self.index = state.index;
+        self.v = state.v;
+        self.stack.clear();
+        for &addr in &state.stack {
+            self.stack.push(addr)?;
+        }
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.waiting_for_vblank = false;
+        self.fault = None;
+        self.memory.copy_from_slice(&state.memory);
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.clear();
+        }
+        Ok(())
+    }
+
+
     pub fn fetch(&mut self) -> u16 {
-        println!("fet::PC: {}", self.pc);
-        
         let instruction = Chip8::read_memory_address(self.memory, self.pc);
-        println!("fet::INST READ: 0x{:04X}", instruction);
-        
         self.pc += 0x02;
         
         instruction
     }
 
 
-    pub fn decode(&mut self, instruction: u16) -> Instruction {
-        let first_nibble = get_nibble(instruction, 1);
+    pub fn decode(&self, instruction: u16) -> Instruction {
+        Instruction::decode(instruction)
+    }
 
-        match first_nibble {
-            0x0 => {
-                match instruction {
-                    0x00E0 =>  Instruction::ClearScreen,
-                    0x00EE => Instruction::ReturnSubroutine,
-                    _ => Instruction::ClearScreen,
-                }
-            },
-            0x1 => {
-                let addr: u16 = instruction % 0x1000;
-                Instruction::Jump { addr }
-            },
-            0x2 => {
-                let addr: u16 = instruction % 0x1000;
-                Instruction::CallSubroutine { addr }
-            },
-            0x3 => {
-                let register_x: usize = (instruction & 0x0100) as usize;
-                let value: u8 = (instruction & 0x0011) as u8;
-                Instruction::SkipIfEqual { register_x, value }
-            },
-            0x4 => {
-                let register_x: usize = (instruction & 0x0100) as usize;
-                let value: u8 = (instruction & 0x0011) as u8;
-                Instruction::SkipIfNotEqual { register_x, value }
-            },
-            0x5 => {
-                if get_nibble(instruction, 4) != 0 {
-                    panic!("Invalid instruction: {}", instruction);
-                }
-                let register_x: usize = (instruction & 0x0100) as usize;
-                let register_y: usize = (instruction & 0x0010) as usize;
-                Instruction::SkipIfRegistersEqual { register_x, register_y }
-            },
-            0x9 => {
-                if get_nibble(instruction, 4) != 0 {
-                    panic!("Invalid instruction: {}", instruction);
-                }
-                let register_x: usize = (instruction & 0x0100) as usize;
-                let register_y: usize = (instruction & 0x0010) as usize;
-                Instruction::SkipIfRegistersNotEqual { register_x, register_y }
-            },
-            0x6 => {
-                let register = get_nibble(instruction, 2) as usize;
-                let value: u8 = (instruction % 0x0100) as u8;
-                Instruction::Set { register, value }
-            },
-            0x7 => {
-                let register = get_nibble(instruction, 2) as usize;
-                let value: u8 = (instruction % 0x0100) as u8;
-                Instruction::Add { register, value }
-            },
-            0xA => {
-                let value: u16 = instruction % 0x1000;
-                Instruction::SetI { value }
-            },
-            0xD => {
-                let register_x = get_nibble(instruction, 2);
-                let register_y = get_nibble(instruction, 3);
-                let n = get_nibble(instruction, 4);
-                Instruction::DisplayDraw { register_x, register_y, n }
-            },
-            0xE => {
-                let keycode = get_nibble(instruction, 2);
-
-                match get_nibble(instruction, 4) {
-
-                    0xE => {
-                        Instruction::SkipIfKeyPressed { keycode }
-                    },
-                    0x1 => {
-                        Instruction::SkipIfKeyNotPressed { keycode }
-                    },
-                    _ => {
-                        Instruction::Nop
-                    },
-                }
-            },
-            0xF => {
-                let register_x = get_nibble(instruction, 2);
-                Instruction::AddI { register_x: register_x.into() }
-            },
-            0x8 => {
-                let register_x = get_nibble(instruction, 2);
-                let register_y = get_nibble(instruction, 3);
-
-                match get_nibble(instruction, 4) {
-                    0x0 => {
-                        Instruction::Nop
-                    },
-                    0x1 => {
-                        Instruction::BinaryOrVX { register_x, register_y }
-                    },
-                    0x2 => {
-                        Instruction::BinaryAndVX { register_x, register_y }
-                    },
-                    0x3 => {
-                        Instruction::BinaryXorVX { register_x, register_y }
-                    },
-                    0x4 => {
-                        Instruction::AddVX { register_x, register_y }
-                    },
-                    0x5 => {
-                        Instruction::SubstractVXVY { register_x: register_x.into(), register_y: register_y.into() }
-                    },
-                    0x6 => {
-                        Instruction::ShiftRight { register_x: register_x.into(), register_y: register_y.into() }
-                    },
-                    0x7 => {
-                        Instruction::SubstractVYVX { register_x: register_x.into(), register_y: register_y.into() }
-                    },
-                    0xE => {
-                        Instruction::ShiftLeft { register_x: register_x.into(), register_y: register_y.into() }
-                    },
-                    _ => Instruction::Nop
-                }
-            }
-            _ => Instruction::Nop
+
+    /**
+    Runs a fetched instruction, stopping the machine with a `fault` if it can't run.
+    */
+    pub fn execute(&mut self, instruction: Instruction) {
+        if let Err(fault) = self.try_execute(instruction) {
+            self.fault = Some(fault);
         }
     }
 
-    pub fn execute(&mut self, instruction: Instruction) {
+
+    fn try_execute(&mut self, instruction: Instruction) -> Result<(), String> {
         match instruction {
             Instruction::ClearScreen => Chip8::clear_screen(&mut self.pixel_array),
             Instruction::FillScreen => Chip8::fill_screen(&mut self.pixel_array),
-            Instruction::Jump { addr } => Chip8::jump(&mut self.pc, addr),
-            Instruction::CallSubroutine { addr } => { 
-                match Chip8::call_subroutine(&mut self.pc, &mut self.stack, addr) {
-                    Ok(_) => (),
-                    Err(e) => println!("Error: {:?}", e),
-                }
-            },
-            Instruction::ReturnSubroutine => Chip8::return_subroutine(&mut self.pc, &mut self.stack),
+            Instruction::Jump { addr } => Chip8::jump(&mut self.pc, addr).map_err(|e| e.to_string())?,
+            Instruction::JumpWithOffset { addr } => Chip8::jump_with_offset(self, addr).map_err(|e| e.to_string())?,
+            Instruction::CallSubroutine { addr } => Chip8::call_subroutine(&mut self.pc, &mut self.stack, addr).map_err(|e| e.to_string())?,
+            Instruction::ReturnSubroutine => Chip8::return_subroutine(&mut self.pc, &mut self.stack).map_err(|e| e.to_string())?,
             Instruction::Set { register, value } => Chip8::set(&mut self.v, register, value),
-            Instruction::Add { register, value } => Chip8::add(&mut self.v, register, value).expect("ADD error"),
+            Instruction::Add { register, value } => Chip8::add(&mut self.v, register, value).map_err(|e| e.to_string())?,
             Instruction::SetI { value } => Chip8::set_i(&mut self.index, value),
             Instruction::DisplayDraw { register_x, register_y, n } => Chip8::display(self, register_x as usize, register_y as usize, n),
+            Instruction::SetVX { register_x, register_y } => self.v[register_x as usize] = self.v[register_y as usize],
             Instruction::BinaryOrVX { register_x, register_y } => Chip8::binary_or_vx(self, register_x as usize, register_y as usize),
             Instruction::BinaryAndVX { register_x, register_y } => Chip8::binary_and_vx(self, register_x as usize, register_y as usize),
             Instruction::BinaryXorVX { register_x, register_y } => Chip8::binary_xor_vx(self, register_x as usize, register_y as usize),
             Instruction::AddVX { register_x, register_y } => Chip8::add_vx(self, register_x as usize, register_y as usize),
-            Instruction::Nop => println!("Nop"),
-            Instruction::SkipIfEqual { register_x, value} => Chip8::skip_if_equal(self, register_x, value).expect("SkipIfEqual error"),
-            Instruction::SkipIfNotEqual { register_x, value} => Chip8::skip_if_not_equal(self, register_x, value).expect("SkipIfNotEqual error"),
-            Instruction::SkipIfRegistersEqual { register_x, register_y } => Chip8::skip_if_registers_equal(self, register_x, register_y).expect("SkipIfRegistersEqual error"),
-            Instruction::SkipIfRegistersNotEqual { register_x, register_y } => Chip8::skip_if_registers_not_equal(self, register_x, register_y).expect("SkipIfRegistersNotEqual error"),
+            Instruction::Nop => (),
+            Instruction::SkipIfEqual { register_x, value} => Chip8::skip_if_equal(self, register_x, value).map_err(|e| e.to_string())?,
+            Instruction::SkipIfNotEqual { register_x, value} => Chip8::skip_if_not_equal(self, register_x, value).map_err(|e| e.to_string())?,
+            Instruction::SkipIfRegistersEqual { register_x, register_y } => Chip8::skip_if_registers_equal(self, register_x, register_y).map_err(|e| e.to_string())?,
+            Instruction::SkipIfRegistersNotEqual { register_x, register_y } => Chip8::skip_if_registers_not_equal(self, register_x, register_y).map_err(|e| e.to_string())?,
             Instruction::SubstractVXVY { register_x, register_y } => Chip8::substract_vx_vy(self, register_x, register_y),
             Instruction::SubstractVYVX { register_x, register_y } => Chip8::substract_vy_vx(self, register_x, register_y),
             Instruction::ShiftRight { register_x, register_y } => Chip8::shift_right(self, register_x, register_y),
             Instruction::ShiftLeft { register_x, register_y } => Chip8::shift_left(self, register_x, register_y),
             Instruction::AddI { register_x } => Chip8::add_i(self, register_x),
+            Instruction::GetDelayTimer { register_x } => self.v[register_x] = self.delay_timer,
+            Instruction::SetDelayTimer { register_x } => self.delay_timer = self.v[register_x],
+            Instruction::SetSoundTimer { register_x } => self.sound_timer = self.v[register_x],
+            Instruction::StoreBcd { register_x } => Chip8::store_bcd(self, register_x),
+            Instruction::StoreRegisters { register_x } => Chip8::store_registers(self, register_x),
+            Instruction::LoadRegisters { register_x } => Chip8::load_registers(self, register_x),
             Instruction::SkipIfKeyPressed { keycode } => Chip8::skip_if_key_pressed(self, keycode),
             Instruction::SkipIfKeyNotPressed { keycode } => Chip8::skip_if_key_not_pressed(self, keycode),
         }
+        Ok(())
+    }
+
+    /**
+    Compiles `instruction` for the recompiler into its handler, a closure with the operands
+    bound that does what `execute` does for it, so running a block doesn't match on each
+    instruction again.
+    */
+    pub(crate) fn compile(instruction: Instruction) -> Handler {
+        fn infallible(handler: impl Fn(&mut Chip8) + Send + Sync + 'static) -> Handler {
+            Box::new(move |chip8| {
+                handler(chip8);
+                Ok(())
+            })
+        }
+
+        fn fallible<E: ToString>(handler: impl Fn(&mut Chip8) -> Result<(), E> + Send + Sync + 'static) -> Handler {
+            Box::new(move |chip8| handler(chip8).map_err(|e| e.to_string()))
+        }
+
+        match instruction {
+            Instruction::ClearScreen => infallible(|chip8| Chip8::clear_screen(&mut chip8.pixel_array)),
+            Instruction::FillScreen => infallible(|chip8| Chip8::fill_screen(&mut chip8.pixel_array)),
+            Instruction::Jump { addr } => fallible(move |chip8| Chip8::jump(&mut chip8.pc, addr)),
+            Instruction::JumpWithOffset { addr } => fallible(move |chip8| chip8.jump_with_offset(addr)),
+            Instruction::CallSubroutine { addr } => fallible(move |chip8| Chip8::call_subroutine(&mut chip8.pc, &mut chip8.stack, addr)),
+            Instruction::ReturnSubroutine => fallible(|chip8| Chip8::return_subroutine(&mut chip8.pc, &mut chip8.stack)),
+            Instruction::Set { register, value } => infallible(move |chip8| Chip8::set(&mut chip8.v, register, value)),
+            Instruction::Add { register, value } => fallible(move |chip8| Chip8::add(&mut chip8.v, register, value)),
+            Instruction::SetI { value } => infallible(move |chip8| Chip8::set_i(&mut chip8.index, value)),
+            Instruction::DisplayDraw { register_x, register_y, n } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.display(x, y, n))
+            },
+            Instruction::SetVX { register_x, register_y } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.v[x] = chip8.v[y])
+            },
+            Instruction::BinaryOrVX { register_x, register_y } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.binary_or_vx(x, y))
+            },
+            Instruction::BinaryAndVX { register_x, register_y } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.binary_and_vx(x, y))
+            },
+            Instruction::BinaryXorVX { register_x, register_y } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.binary_xor_vx(x, y))
+            },
+            Instruction::AddVX { register_x, register_y } => {
+                let (x, y) = (register_x as usize, register_y as usize);
+                infallible(move |chip8| chip8.add_vx(x, y))
+            },
+            Instruction::Nop => infallible(|_| ()),
+            Instruction::SkipIfEqual { register_x, value } => fallible(move |chip8| chip8.skip_if_equal(register_x, value)),
+            Instruction::SkipIfNotEqual { register_x, value } => fallible(move |chip8| chip8.skip_if_not_equal(register_x, value)),
+            Instruction::SkipIfRegistersEqual { register_x, register_y } => fallible(move |chip8| chip8.skip_if_registers_equal(register_x, register_y)),
+            Instruction::SkipIfRegistersNotEqual { register_x, register_y } => fallible(move |chip8| chip8.skip_if_registers_not_equal(register_x, register_y)),
+            Instruction::SubstractVXVY { register_x, register_y } => infallible(move |chip8| chip8.substract_vx_vy(register_x, register_y)),
+            Instruction::SubstractVYVX { register_x, register_y } => infallible(move |chip8| chip8.substract_vy_vx(register_x, register_y)),
+            Instruction::ShiftRight { register_x, register_y } => infallible(move |chip8| chip8.shift_right(register_x, register_y)),
+            Instruction::ShiftLeft { register_x, register_y } => infallible(move |chip8| chip8.shift_left(register_x, register_y)),
+            Instruction::AddI { register_x } => infallible(move |chip8| chip8.add_i(register_x)),
+            Instruction::GetDelayTimer { register_x } => infallible(move |chip8| chip8.v[register_x] = chip8.delay_timer),
+            Instruction::SetDelayTimer { register_x } => infallible(move |chip8| chip8.delay_timer = chip8.v[register_x]),
+            Instruction::SetSoundTimer { register_x } => infallible(move |chip8| chip8.sound_timer = chip8.v[register_x]),
+            Instruction::StoreBcd { register_x } => infallible(move |chip8| chip8.store_bcd(register_x)),
+            Instruction::StoreRegisters { register_x } => infallible(move |chip8| chip8.store_registers(register_x)),
+            Instruction::LoadRegisters { register_x } => infallible(move |chip8| chip8.load_registers(register_x)),
+            Instruction::SkipIfKeyPressed { keycode } => infallible(move |chip8| chip8.skip_if_key_pressed(keycode)),
+            Instruction::SkipIfKeyNotPressed { keycode } => infallible(move |chip8| chip8.skip_if_key_not_pressed(keycode)),
+        }
     }
 
     fn load_font(&mut self) {
@@ -246,24 +370,45 @@ impl Chip8 {
         }
     }
 
-    fn load_rom(&mut self, path: &str) -> std::io::Result<()>{
+    /**
+    Copies the ROM at `path` into memory at `ROM_OFFSET`.
+
+    # Returns
+    Returns the lowercase hex SHA-1 of the ROM, which identifies it in the ROM database.
+    */
+    pub fn load_rom(&mut self, path: &str) -> std::io::Result<String> {
         let mut rom_file = File::open(path)?;
         let mut rom_buffer = Vec::new();
         
         rom_file.read_to_end(&mut rom_buffer)?;
 
-        if ROM_OFFSET as usize + rom_buffer.len() > self.memory.len() {
+        self.load_rom_bytes(&rom_buffer)
+    }
+
+
+    /**
+    Same as `load_rom`, for a ROM already in memory.
+    */
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> std::io::Result<String> {
+        if ROM_OFFSET as usize + rom.len() > self.memory.len() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "ROM exceeds memory",
             ));
         }
 
-        for (i, &byte) in rom_buffer.iter().enumerate() {
+        for (i, &byte) in rom.iter().enumerate() {
             self.memory[ROM_OFFSET as usize + i] = byte;
         }
+        self.rom = rom.to_vec();
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.clear();
+        }
 
-        Ok(())
+        Ok(sha1_hex(rom))
     }
 
     /**
@@ -272,15 +417,16 @@ impl Chip8 {
     # Parameters
 
     - `memory`: The 4K memory array of the Chip-8.
-    - `addr`: The memory address at which the instruction starts.
+    - `addr`: The memory address at which the instruction starts, wrapping around the end
+      of memory.
 
     # Returns
 
     Returns a 16-bit instruction combining the bytes stored at `addr` and `addr + 1`.
     */
     pub fn read_memory_address(memory: [u8; 4096], addr: u16) -> u16 {
-        let bigger_byte: u8 = memory[addr as usize];
-        let smaller_byte: u8 = memory[addr as usize + 1];
+        let bigger_byte: u8 = memory[addr as usize % MEMORY_SIZE];
+        let smaller_byte: u8 = memory[(addr as usize + 1) % MEMORY_SIZE];
 
         ((bigger_byte as u16) << 8) | (smaller_byte as u16)
     }
@@ -300,10 +446,174 @@ impl Chip8 {
         (MIN_ADDRESS..=MAX_ADDRESS).contains(&addr)
     }
 
+
+    /**
+    Whether the program counter can be set to `addr` from outside, e.g. by a debugger: a
+    valid address with a whole instruction before the end of memory, so the next fetch
+    can't fail.
+    */
+    pub fn is_valid_pc(addr: u16) -> bool {
+        Self::is_valid_address(addr) && (addr as usize) + 1 < MEMORY_SIZE
+    }
+
+    /**
+    Fetches, decodes and executes the instruction at `pc`, unless the machine has a
+    `fault`. There must be a whole instruction there, else that's a fault too.
+    */
     pub fn cycle(&mut self) {
-        let instruction = self.fetch();
-        let decoded = self.decode(instruction);
-        self.execute(decoded);
+        if !self.can_run() {
+            return;
+        }
+        if self.tracer.is_some() {
+            return self.traced_cycle();
+        }
+
+        let pc = self.pc;
+        let cached = self.decode_cache.as_ref().and_then(|cache| cache.get(pc));
+        let instruction = match cached {
+            Some(instruction) => {
+                self.pc += 0x02;
+                instruction
+            },
+            None => {
+                let opcode = self.fetch();
+                let instruction = self.decode(opcode);
+                if let Some(cache) = &mut self.decode_cache {
+                    cache.insert(pc, instruction);
+                }
+                instruction
+            },
+        };
+        self.execute(instruction);
+        self.stay_on_fault(pc);
+    }
+
+
+    /**
+    Whether there's no `fault` and `pc` is on a whole instruction, stopping the machine
+    with a fault if not.
+    */
+    fn can_run(&mut self) -> bool {
+        if self.fault.is_none() && !Self::is_valid_pc(self.pc) {
+            self.fault = Some(format!("no instruction at 0x{:03X}, the end of memory", self.pc));
+        }
+        self.fault.is_none()
+    }
+
+
+    /**
+    Moves the program counter back to `pc`, the instruction just executed, if it failed.
+    */
+    fn stay_on_fault(&mut self, pc: u16) {
+        if self.fault.is_some() {
+            self.pc = pc;
+        }
+    }
+
+
+    /**
+    Writes `byte` at `addr`, wrapping around the end of memory. Every write to memory, by an
+    instruction or a debugger, goes through here so the decode cache stays valid.
+    */
+    pub fn write_memory(&mut self, addr: usize, byte: u8) {
+        let addr = addr % MEMORY_SIZE;
+        self.memory[addr] = byte;
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(addr);
+        }
+        if let Some(recompiler) = &mut self.recompiler {
+            recompiler.invalidate(addr);
+        }
+    }
+
+
+    /**
+    Runs the basic block at `pc`, compiling it first if needed, stopping early after
+    `budget` instructions or a draw waiting for vblank. Without a recompiler, or while
+    tracing, runs a single `cycle` instead. Stops at a `fault` like `cycle`.
+
+    # Returns
+    Returns how many instructions were run, at least one unless the machine has a fault.
+    */
+    pub fn run_block(&mut self, budget: u32) -> u32 {
+        if !self.can_run() {
+            return 0;
+        }
+        let block = match &mut self.recompiler {
+            Some(recompiler) if self.tracer.is_none() => Some(recompiler.block(self.pc, &self.memory)),
+            _ => None,
+        };
+        let Some(block) = block else {
+            self.cycle();
+            return u32::from(self.fault.is_none());
+        };
+
+        let mut executed = 0;
+        for handler in block.code.iter().take(budget.max(1) as usize) {
+            let pc = self.pc;
+            self.pc += 0x02;
+            if let Err(fault) = handler(self) {
+                self.fault = Some(fault);
+                self.stay_on_fault(pc);
+                break;
+            }
+            executed += 1;
+            if self.waiting_for_vblank {
+                break;
+            }
+        }
+        executed
+    }
+
+
+    /**
+    `cycle` that also hands the instruction and the registers it changed to the tracer.
+    Tracing stops if the trace can't be written.
+    */
+    fn traced_cycle(&mut self) {
+        let pc = self.pc;
+        let before = self.registers();
+
+        let opcode = self.fetch();
+        let instruction = self.decode(opcode);
+        self.execute(instruction);
+        self.stay_on_fault(pc);
+
+        let step = Step { pc, opcode, instruction, before, after: self.registers() };
+        if let Some(Err(e)) = self.tracer.as_mut().map(|tracer| tracer.record(&step)) {
+            eprintln!("Error writing trace, tracing stopped: {}", e);
+            self.tracer = None;
+        }
+    }
+
+
+    fn registers(&self) -> Registers {
+        Registers { v: self.v, index: self.index }
+    }
+
+
+    /**
+    Decrements the delay and sound timers. Must be called at 60 Hz, independently of
+    how many instructions are executed per frame.
+    */
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
+
+/**
+Lowercase hex SHA-1 of `bytes`, the format used by the chip-8-database.
+*/
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -311,14 +621,23 @@ impl Chip8 {
     // Instruction methods
 
 
-    pub fn jump(pc: &mut u16, addr: u16) {
-        if Self::is_valid_address(addr) {
-            *pc = addr;
-        }
-        else {
-            // TODO: handle this correctly
-            panic!("JUMP: INVALID JUMP ADDRESS");
+    pub fn jump(pc: &mut u16, addr: u16) -> Result<(), JumpError> {
+        if !Self::is_valid_address(addr) {
+            return Err(JumpError::InvalidAddress(addr));
         }
+
+        *pc = addr;
+        Ok(())
+    }
+
+
+    fn jump_with_offset(&mut self, addr: u16) -> Result<(), JumpError> { // BNNN
+        let offset = if self.quirks.jump {
+            self.v[get_nibble(addr, 2) as usize]
+        } else {
+            self.v[0]
+        };
+        Self::jump(&mut self.pc, (addr + offset as u16) & 0x0FFF)
     }
 
 
@@ -336,9 +655,9 @@ impl Chip8 {
     }
 
 
-    pub fn return_subroutine(pc: &mut u16, stack: &mut Stack<u16>) {
-        let return_addr = stack.pop().expect("Error: Empty stack");
-        *pc = return_addr;
+    pub fn return_subroutine(pc: &mut u16, stack: &mut Stack<u16>) -> Result<(), SubroutineError> {
+        *pc = stack.pop().ok_or(SubroutineError::StackUnderflow)?;
+        Ok(())
     }
 
 
@@ -376,16 +695,30 @@ impl Chip8 {
     fn display(&mut self, register_x: usize, register_y: usize, n: u8) {
         let x: usize = (self.v[register_x] as usize) % WIDTH;
         let y: usize = (self.v[register_y] as usize) % HEIGHT;
+        let wrap = self.quirks.wrap;
         self.v[0xF] = 0;
 
-        for row in 0..n {
-            let sprite_row: u8 = self.memory[(self.index + row as u16) as usize];
+        // Screen position of the sprite pixel at (column, row), None if it's clipped
+        let position = |column: usize, row: usize| -> Option<(usize, usize)> {
+            if wrap {
+                Some(((x + column) % WIDTH, (y + row) % HEIGHT))
+            } else if x + column < WIDTH && y + row < HEIGHT {
+                Some((x + column, y + row))
+            } else {
+                None
+            }
+        };
+
+        for row in 0..n as usize {
+            let sprite_row: u8 = self.memory[(self.index as usize + row) % MEMORY_SIZE];
             
             let mut pixel_row: u8 = 0;
             // convert pixel array row to u8 var
             for i in 0..8 {
-                if y + (row as usize) < HEIGHT && x + (i as usize) < WIDTH && self.pixel_array[y + row as usize][x + i as usize] {
-                    pixel_row |= 1 << (7 - i);
+                if let Some((px, py)) = position(i, row) {
+                    if self.pixel_array[py][px] {
+                        pixel_row |= 1 << (7 - i);
+                    }
                 }
             }
 
@@ -395,29 +728,43 @@ impl Chip8 {
                 self.v[0xF] = 1;
             }
 
-            for i in 0..8 {
-                if (x + i) < WIDTH &&
-                        (y + row as usize) < HEIGHT &&
-                        final_pixel_row != pixel_row {
-                    self.pixel_array[y + row as usize][x + i] = ((final_pixel_row >> (7 - i)) & 0b0000_0001) == 1;
+            if final_pixel_row != pixel_row {
+                for i in 0..8 {
+                    if let Some((px, py)) = position(i, row) {
+                        self.pixel_array[py][px] = ((final_pixel_row >> (7 - i)) & 0b0000_0001) == 1;
+                    }
                 }
             }
         }
+
+        if self.quirks.vblank {
+            self.waiting_for_vblank = true;
+        }
     }
 
 
     fn binary_or_vx(&mut self, register_x: usize, register_y: usize) {
         self.v[register_x] |= self.v[register_y];
+        self.reset_vf_after_logic();
     }
 
 
     fn binary_and_vx(&mut self, register_x: usize, register_y: usize) {
         self.v[register_x] &= self.v[register_y];
+        self.reset_vf_after_logic();
     }
 
 
     fn binary_xor_vx(&mut self, register_x: usize, register_y: usize) {
         self.v[register_x] ^= self.v[register_y];
+        self.reset_vf_after_logic();
+    }
+
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
     }
 
 
@@ -447,6 +794,39 @@ impl Chip8 {
     }
 
 
+    fn store_bcd(&mut self, register_x: usize) { // FX33
+        let value = self.v[register_x];
+        for (i, digit) in [value / 100, value / 10 % 10, value % 10].into_iter().enumerate() {
+            self.write_memory(self.index as usize + i, digit);
+        }
+    }
+
+
+    fn store_registers(&mut self, register_x: usize) { // FX55
+        for i in 0..=register_x {
+            self.write_memory(self.index as usize + i, self.v[i]);
+        }
+        self.advance_i_after_memory_access(register_x);
+    }
+
+
+    fn load_registers(&mut self, register_x: usize) { // FX65
+        for i in 0..=register_x {
+            self.v[i] = self.memory[(self.index as usize + i) % MEMORY_SIZE];
+        }
+        self.advance_i_after_memory_access(register_x);
+    }
+
+
+    fn advance_i_after_memory_access(&mut self, register_x: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x { register_x } else { register_x + 1 };
+        self.index = self.index.wrapping_add(increment as u16);
+    }
+
+
     fn substract_vx_vy(&mut self, register_x: usize, register_y: usize) { // 8XY5
         if self.v[register_x] > self.v[register_y] {
             self.v[0xF] = 1;
@@ -472,9 +852,9 @@ impl Chip8 {
 
 
     fn shift_right(&mut self, register_x: usize, register_y: usize) { // 8XY6
-        // if chip8.mode != SUPER_CHIP && chip8.mode != CHIP_48 {
-        self.v[register_x] = self.v[register_y];
-        //}
+        if !self.quirks.shift {
+            self.v[register_x] = self.v[register_y];
+        }
 
         if self.v[register_x] & 0b0000_0001 == 0b0000_0001 {
             self.v[0xF] = 1;
@@ -487,9 +867,9 @@ impl Chip8 {
 
 
     fn shift_left(&mut self, register_x: usize, register_y: usize) { // 8XYE
-        // if chip8.mode != SUPER_CHIP && chip8.mode != CHIP_48 {
-        self.v[register_x] = self.v[register_y];
-        //}
+        if !self.quirks.shift {
+            self.v[register_x] = self.v[register_y];
+        }
 
         if self.v[register_x] & 0b1000_0000 == 0b1000_0000 {
             self.v[0xF] = 1;
@@ -561,17 +941,17 @@ impl Chip8 {
     }
 
 
-    fn skip_if_key_pressed (&mut self, _keycode: u8) {
-        let is_key_pressed = true; // TODO: input handling
-        if is_key_pressed {
+    fn skip_if_key_pressed (&mut self, register_x: u8) { // EX9E
+        let key = self.v[register_x as usize] & 0x0F;
+        if self.keypad[key as usize] {
             self.pc += 0x02;
         }
     }
 
     
-    fn skip_if_key_not_pressed (&mut self, _keycode: u8) {
-        let is_key_pressed = true;
-        if !is_key_pressed {
+    fn skip_if_key_not_pressed (&mut self, register_x: u8) { // EXA1
+        let key = self.v[register_x as usize] & 0x0F;
+        if !self.keypad[key as usize] {
             self.pc += 0x02;
         }
     }
@@ -580,6 +960,7 @@ impl Chip8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chip8::trace::TraceFilter;
 
     #[test]
     fn test_get_nibble() {
@@ -625,4 +1006,242 @@ mod tests {
 
         assert_eq!(i, 5);
     }
+
+    #[test]
+    fn test_tick_timers() {
+        let mut chip8 = Chip8::new();
+        chip8.delay_timer = 2;
+        chip8.sound_timer = 1;
+
+        chip8.tick_timers();
+        assert_eq!(chip8.delay_timer, 1);
+        assert_eq!(chip8.sound_timer, 0);
+
+        chip8.tick_timers();
+        assert_eq!(chip8.delay_timer, 0);
+        assert_eq!(chip8.sound_timer, 0);
+    }
+
+    #[test]
+    fn test_skip_if_key_pressed() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0x3] = 0xA;
+
+        chip8.skip_if_key_pressed(0x3);
+        assert_eq!(chip8.pc, ROM_OFFSET);
+
+        chip8.keypad[0xA] = true;
+        chip8.skip_if_key_pressed(0x3);
+        assert_eq!(chip8.pc, ROM_OFFSET + 2);
+
+        chip8.skip_if_key_not_pressed(0x3);
+        assert_eq!(chip8.pc, ROM_OFFSET + 2);
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0x1] = 0b0000_0011;
+        chip8.v[0x2] = 0b1000_0000;
+
+        chip8.shift_right(0x1, 0x2);
+        assert_eq!(chip8.v[0x1], 0b0100_0000);
+        assert_eq!(chip8.v[0xF], 0);
+
+        chip8.quirks.shift = true;
+        chip8.v[0x1] = 0b0000_0011;
+        chip8.shift_right(0x1, 0x2);
+        assert_eq!(chip8.v[0x1], 0b0000_0001);
+        assert_eq!(chip8.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_logic_quirk() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0xF] = 1;
+        chip8.binary_or_vx(0x1, 0x2);
+        assert_eq!(chip8.v[0xF], 1);
+
+        chip8.quirks.logic = true;
+        chip8.binary_or_vx(0x1, 0x2);
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let chip8 = Chip8::new();
+        assert_eq!(chip8.decode(0x00E0).to_string(), "CLS");
+        assert_eq!(chip8.decode(0x6A05).to_string(), "LD VA, 0x05");
+        assert_eq!(chip8.decode(0xA2F0).to_string(), "LD I, 0x2F0");
+        assert_eq!(chip8.decode(0xD01F).to_string(), "DRW V0, V1, 15");
+        assert_eq!(chip8.decode(0xF355).to_string(), "LD [I], V3");
+        assert_eq!(chip8.decode(0x5121).to_string(), "NOP");
+        assert_eq!(chip8.decode(0x8AB0).to_string(), "LD VA, VB");
+    }
+
+    #[test]
+    fn test_store_bcd() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0x3] = 157;
+        chip8.index = 0x300;
+
+        let instruction = chip8.decode(0xF333);
+        chip8.execute(instruction);
+        assert_eq!(&chip8.memory[0x300..0x303], &[1, 5, 7]);
+        assert_eq!(chip8.index, 0x300);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.vblank = true;
+        // 6A05 A202 F055 D011 2200 (V0 stored over the first byte of the ROM)
+        chip8.load_rom_bytes(&[0x6A, 0x05, 0xA2, 0x00, 0xF0, 0x55, 0xD0, 0x11, 0x22, 0x00]).unwrap();
+        for _ in 0..5 {
+            chip8.cycle();
+        }
+        chip8.delay_timer = 3;
+        chip8.keypad[4] = true;
+        assert_eq!(chip8.memory[0x200], 0x00);
+        assert_eq!(chip8.stack(), [0x20A]);
+
+        chip8.reset();
+        assert_eq!(chip8.pc, ROM_OFFSET);
+        assert_eq!((chip8.index, chip8.v, chip8.delay_timer), (0, [0; 16], 0));
+        assert!(chip8.stack().is_empty());
+        assert!(chip8.pixel_array.iter().flatten().all(|&pixel| !pixel));
+        assert_eq!(chip8.keypad, [false; 16]);
+        assert_eq!(&chip8.memory[0x200..0x20A], &[0x6A, 0x05, 0xA2, 0x00, 0xF0, 0x55, 0xD0, 0x11, 0x22, 0x00]);
+        assert_eq!(chip8.memory[FONT_OFFSET as usize], 0xF0);
+        assert!(chip8.quirks.vblank);
+    }
+
+    #[test]
+    fn test_decode_cache_invalidated_by_writes() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x6A, 0x05]).unwrap();
+        chip8.cycle();
+        assert_eq!(chip8.v[0xA], 0x05);
+
+        // FX55 rewrites the cached 6A05 into 6A07
+        chip8.v[..2].copy_from_slice(&[0x6A, 0x07]);
+        chip8.index = 0x200;
+        chip8.store_registers(0x1);
+        chip8.pc = 0x200;
+        chip8.cycle();
+        assert_eq!(chip8.v[0xA], 0x07);
+
+        // FX33 writes the hundreds digit over its second byte, making it 6A01
+        chip8.v[0x3] = 100;
+        chip8.index = 0x201;
+        chip8.store_bcd(0x3);
+        chip8.pc = 0x200;
+        chip8.cycle();
+        assert_eq!(chip8.v[0xA], 0x01);
+
+        // Back to the ROM as loaded
+        chip8.reset();
+        chip8.cycle();
+        assert_eq!(chip8.v[0xA], 0x05);
+    }
+
+    #[test]
+    fn test_store_and_load_registers() {
+        let mut chip8 = Chip8::new();
+        chip8.v[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.index = 0x300;
+
+        chip8.store_registers(0x2);
+        assert_eq!(&chip8.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(chip8.index, 0x303);
+
+        chip8.quirks.memory_increment_by_x = true;
+        chip8.index = 0x300;
+        chip8.v = [0; 16];
+        chip8.load_registers(0x2);
+        assert_eq!(&chip8.v[..3], &[1, 2, 3]);
+        assert_eq!(chip8.index, 0x302);
+
+        chip8.quirks.memory_leave_i_unchanged = true;
+        chip8.store_registers(0x2);
+        assert_eq!(chip8.index, 0x302);
+    }
+
+    #[test]
+    fn test_trace() {
+        let path = std::env::temp_dir().join(format!("octorust-trace-{}.log", std::process::id()));
+        let filter = TraceFilter { addresses: None, classes: Some(TraceFilter::parse_classes("6,A").unwrap()) };
+
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x200..0x206].copy_from_slice(&[0x6A, 0x05, 0x00, 0xE0, 0xA2, 0xF0]);
+        chip8.tracer = Some(Tracer::create(path.to_str().unwrap(), filter).unwrap());
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        chip8.tracer = None;
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(trace, "0200 6A05 LD VA, 0x05      VA=05\n\
+                           0204 A2F0 LD I, 0x2F0      I=02F0\n");
+    }
+
+    #[test]
+    fn test_jump_with_offset() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0x0] = 0x10;
+        chip8.v[0x3] = 0x20;
+
+        chip8.jump_with_offset(0x340).unwrap();
+        assert_eq!(chip8.pc, 0x350);
+
+        chip8.quirks.jump = true;
+        chip8.jump_with_offset(0x340).unwrap();
+        assert_eq!(chip8.pc, 0x360);
+    }
+
+    #[test]
+    fn test_faults() {
+        // RET with an empty stack, JP 000, B000 with V0 = 0 and calling itself forever
+        let roms: [(&[u8], &str); 4] = [
+            (&[0x00, 0xEE], "return with an empty stack"),
+            (&[0x10, 0x00], "invalid jump address 0x000"),
+            (&[0x60, 0x00, 0xB0, 0x00], "invalid jump address 0x000"),
+            (&[0x22, 0x00], "stack overflow"),
+        ];
+        for (rom, fault) in roms {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(rom).unwrap();
+            for _ in 0..20 {
+                chip8.cycle();
+            }
+            assert_eq!(chip8.fault(), Some(fault));
+            assert_eq!(chip8.pc(), ROM_OFFSET + rom.len() as u16 - 2, "stopped on the failing instruction");
+
+            chip8.reset();
+            assert_eq!((chip8.pc(), chip8.fault()), (ROM_OFFSET, None));
+        }
+    }
+
+    #[test]
+    fn test_display_quirks() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x300] = 0b1100_0000;
+        chip8.index = 0x300;
+        chip8.v[0x0] = (WIDTH - 1) as u8;
+        chip8.v[0x1] = 0;
+
+        chip8.display(0x0, 0x1, 1);
+        assert!(chip8.pixel_array[0][WIDTH - 1]);
+        assert!(!chip8.pixel_array[0][0]);
+
+        Chip8::clear_screen(&mut chip8.pixel_array);
+        chip8.quirks.wrap = true;
+        chip8.quirks.vblank = true;
+        chip8.display(0x0, 0x1, 1);
+        assert!(chip8.pixel_array[0][WIDTH - 1]);
+        assert!(chip8.pixel_array[0][0]);
+        assert!(chip8.waiting_for_vblank);
+    }
+
 }
\ No newline at end of file
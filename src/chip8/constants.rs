@@ -8,4 +8,7 @@ pub const ROM_OFFSET: u16 = 0x200;
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
 pub const SCALE_FACTOR: u8 = 10;
-pub const PADDING: u8 = SCALE_FACTOR / 8;
\ No newline at end of file
+pub const PADDING: u8 = SCALE_FACTOR / 8;
+
+pub const FRAME_RATE: u32 = 60;
+pub const CYCLES_PER_FRAME: u32 = 11;
\ No newline at end of file
@@ -0,0 +1,79 @@
+// decode_cache.rs
+
+use crate::chip8::constants::MEMORY_SIZE;
+use crate::chip8::instruction::Instruction;
+
+
+/**
+Instructions already decoded, by the address they start at, so hot loops skip fetching
+and decoding. Anything writing to memory must `invalidate` the bytes it wrote, else the
+old instruction keeps running.
+*/
+pub struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache { entries: vec![None; MEMORY_SIZE] }
+    }
+
+
+    pub fn get(&self, addr: u16) -> Option<Instruction> {
+        self.entries.get(addr as usize).copied().flatten()
+    }
+
+
+    pub fn insert(&mut self, addr: u16, instruction: Instruction) {
+        if let Some(entry) = self.entries.get_mut(addr as usize) {
+            *entry = Some(instruction);
+        }
+    }
+
+
+    /**
+    Forgets the instructions containing the byte at `addr`: the one starting there and
+    the one starting the byte before.
+    */
+    pub fn invalidate(&mut self, addr: usize) {
+        self.entries[addr % MEMORY_SIZE] = None;
+        self.entries[(addr + MEMORY_SIZE - 1) % MEMORY_SIZE] = None;
+    }
+
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache = DecodeCache::new();
+        for addr in [0x200, 0x202, 0x204, 0x000] {
+            cache.insert(addr, Instruction::ClearScreen);
+        }
+        assert_eq!(cache.get(0x202), Some(Instruction::ClearScreen));
+
+        // The second byte of 0x202 and the first of 0x204
+        cache.invalidate(0x203);
+        cache.invalidate(0x204);
+        assert_eq!(cache.get(0x200), Some(Instruction::ClearScreen));
+        assert_eq!(cache.get(0x202), None);
+        assert_eq!(cache.get(0x204), None);
+
+        cache.clear();
+        assert_eq!(cache.get(0x200), None);
+        assert_eq!(cache.get(0x1000), None);
+    }
+}
@@ -1,7 +1,35 @@
+use std::fmt;
+
+
 #[derive(Debug)]
 pub enum SubroutineError {
     InvalidAddress(u16),
     StackOverflow,
+    StackUnderflow,
+}
+
+impl fmt::Display for SubroutineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubroutineError::InvalidAddress(addr) => write!(f, "invalid subroutine address 0x{:03X}", addr),
+            SubroutineError::StackOverflow => write!(f, "stack overflow"),
+            SubroutineError::StackUnderflow => write!(f, "return with an empty stack"),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum JumpError {
+    InvalidAddress(u16),
+}
+
+impl fmt::Display for JumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JumpError::InvalidAddress(addr) => write!(f, "invalid jump address 0x{:03X}", addr),
+        }
+    }
 }
 
 
@@ -9,3 +37,11 @@ pub enum SubroutineError {
 pub enum RegisterError {
     InvalidRegister(usize),
 }
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::InvalidRegister(register) => write!(f, "invalid register V{:X}", register),
+        }
+    }
+}
@@ -1,13 +1,21 @@
+use crate::chip8::low_level_operations::get_nibble;
+
+use std::fmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     ClearScreen,
     FillScreen,
     Jump { addr: u16},
+    JumpWithOffset { addr: u16 },
     CallSubroutine { addr: u16},
     ReturnSubroutine,
     Set { register: usize, value: u8},
     Add { register: usize, value: u8},
     SetI { value: u16 },
     DisplayDraw { register_x: u8, register_y: u8, n: u8},
+    SetVX { register_x: u8, register_y: u8},
     BinaryOrVX { register_x: u8, register_y: u8},
     BinaryAndVX { register_x: u8, register_y: u8},
     BinaryXorVX { register_x: u8, register_y: u8},
@@ -22,6 +30,284 @@ pub enum Instruction {
     ShiftLeft {register_x: usize, register_y: usize},
     ShiftRight {register_x: usize, register_y: usize},
     AddI { register_x: usize},
+    GetDelayTimer { register_x: usize },
+    SetDelayTimer { register_x: usize },
+    SetSoundTimer { register_x: usize },
+    StoreBcd { register_x: usize },
+    StoreRegisters { register_x: usize },
+    LoadRegisters { register_x: usize },
     SkipIfKeyPressed { keycode: u8},
     SkipIfKeyNotPressed { keycode: u8},
-}
\ No newline at end of file
+}
+
+
+impl fmt::Display for Instruction {
+    /**
+    Disassembles the instruction with the common CHIP-8 mnemonics, e.g. `LD VA, 0x05`.
+    */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::FillScreen => write!(f, "FILL"),
+            Instruction::Jump { addr } => write!(f, "JP 0x{:03X}", addr),
+            Instruction::JumpWithOffset { addr } => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::CallSubroutine { addr } => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::ReturnSubroutine => write!(f, "RET"),
+            Instruction::Set { register, value } => write!(f, "LD V{:X}, 0x{:02X}", register, value),
+            Instruction::Add { register, value } => write!(f, "ADD V{:X}, 0x{:02X}", register, value),
+            Instruction::SetI { value } => write!(f, "LD I, 0x{:03X}", value),
+            Instruction::DisplayDraw { register_x, register_y, n } => write!(f, "DRW V{:X}, V{:X}, {}", register_x, register_y, n),
+            Instruction::SetVX { register_x, register_y } => write!(f, "LD V{:X}, V{:X}", register_x, register_y),
+            Instruction::BinaryOrVX { register_x, register_y } => write!(f, "OR V{:X}, V{:X}", register_x, register_y),
+            Instruction::BinaryAndVX { register_x, register_y } => write!(f, "AND V{:X}, V{:X}", register_x, register_y),
+            Instruction::BinaryXorVX { register_x, register_y } => write!(f, "XOR V{:X}, V{:X}", register_x, register_y),
+            Instruction::AddVX { register_x, register_y } => write!(f, "ADD V{:X}, V{:X}", register_x, register_y),
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::SkipIfEqual { register_x, value } => write!(f, "SE V{:X}, 0x{:02X}", register_x, value),
+            Instruction::SkipIfNotEqual { register_x, value } => write!(f, "SNE V{:X}, 0x{:02X}", register_x, value),
+            Instruction::SkipIfRegistersEqual { register_x, register_y } => write!(f, "SE V{:X}, V{:X}", register_x, register_y),
+            Instruction::SkipIfRegistersNotEqual { register_x, register_y } => write!(f, "SNE V{:X}, V{:X}", register_x, register_y),
+            Instruction::SubstractVXVY { register_x, register_y } => write!(f, "SUB V{:X}, V{:X}", register_x, register_y),
+            Instruction::SubstractVYVX { register_x, register_y } => write!(f, "SUBN V{:X}, V{:X}", register_x, register_y),
+            Instruction::ShiftLeft { register_x, register_y } => write!(f, "SHL V{:X}, V{:X}", register_x, register_y),
+            Instruction::ShiftRight { register_x, register_y } => write!(f, "SHR V{:X}, V{:X}", register_x, register_y),
+            Instruction::AddI { register_x } => write!(f, "ADD I, V{:X}", register_x),
+            Instruction::GetDelayTimer { register_x } => write!(f, "LD V{:X}, DT", register_x),
+            Instruction::SetDelayTimer { register_x } => write!(f, "LD DT, V{:X}", register_x),
+            Instruction::SetSoundTimer { register_x } => write!(f, "LD ST, V{:X}", register_x),
+            Instruction::StoreBcd { register_x } => write!(f, "LD B, V{:X}", register_x),
+            Instruction::StoreRegisters { register_x } => write!(f, "LD [I], V{:X}", register_x),
+            Instruction::LoadRegisters { register_x } => write!(f, "LD V{:X}, [I]", register_x),
+            Instruction::SkipIfKeyPressed { keycode } => write!(f, "SKP V{:X}", keycode),
+            Instruction::SkipIfKeyNotPressed { keycode } => write!(f, "SKNP V{:X}", keycode),
+        }
+    }
+}
+
+
+fn x(opcode: u16) -> u8 {
+    get_nibble(opcode, 2)
+}
+
+
+fn y(opcode: u16) -> u8 {
+    get_nibble(opcode, 3)
+}
+
+
+fn n(opcode: u16) -> u8 {
+    get_nibble(opcode, 4)
+}
+
+
+fn nn(opcode: u16) -> u8 {
+    (opcode & 0x00FF) as u8
+}
+
+
+fn nnn(opcode: u16) -> u16 {
+    opcode & 0x0FFF
+}
+
+
+/**
+One opcode of the instruction set: the opcodes with `opcode & mask == bits` decode with
+`decode`. The fixed bits of the patterns don't overlap, so at most one matches.
+*/
+struct Pattern {
+    mask: u16,
+    bits: u16,
+    decode: fn(u16) -> Instruction,
+}
+
+
+const PATTERNS: [Pattern; 31] = [
+    Pattern { mask: 0xFFFF, bits: 0x00E0, decode: |_| Instruction::ClearScreen },
+    Pattern { mask: 0xFFFF, bits: 0x00EE, decode: |_| Instruction::ReturnSubroutine },
+    Pattern { mask: 0xF000, bits: 0x1000, decode: |op| Instruction::Jump { addr: nnn(op) } },
+    Pattern { mask: 0xF000, bits: 0x2000, decode: |op| Instruction::CallSubroutine { addr: nnn(op) } },
+    Pattern { mask: 0xF000, bits: 0x3000, decode: |op| Instruction::SkipIfEqual { register_x: x(op).into(), value: nn(op) } },
+    Pattern { mask: 0xF000, bits: 0x4000, decode: |op| Instruction::SkipIfNotEqual { register_x: x(op).into(), value: nn(op) } },
+    Pattern { mask: 0xF00F, bits: 0x5000, decode: |op| Instruction::SkipIfRegistersEqual { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF000, bits: 0x6000, decode: |op| Instruction::Set { register: x(op).into(), value: nn(op) } },
+    Pattern { mask: 0xF000, bits: 0x7000, decode: |op| Instruction::Add { register: x(op).into(), value: nn(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8000, decode: |op| Instruction::SetVX { register_x: x(op), register_y: y(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8001, decode: |op| Instruction::BinaryOrVX { register_x: x(op), register_y: y(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8002, decode: |op| Instruction::BinaryAndVX { register_x: x(op), register_y: y(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8003, decode: |op| Instruction::BinaryXorVX { register_x: x(op), register_y: y(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8004, decode: |op| Instruction::AddVX { register_x: x(op), register_y: y(op) } },
+    Pattern { mask: 0xF00F, bits: 0x8005, decode: |op| Instruction::SubstractVXVY { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF00F, bits: 0x8006, decode: |op| Instruction::ShiftRight { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF00F, bits: 0x8007, decode: |op| Instruction::SubstractVYVX { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF00F, bits: 0x800E, decode: |op| Instruction::ShiftLeft { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF00F, bits: 0x9000, decode: |op| Instruction::SkipIfRegistersNotEqual { register_x: x(op).into(), register_y: y(op).into() } },
+    Pattern { mask: 0xF000, bits: 0xA000, decode: |op| Instruction::SetI { value: nnn(op) } },
+    Pattern { mask: 0xF000, bits: 0xB000, decode: |op| Instruction::JumpWithOffset { addr: nnn(op) } },
+    Pattern { mask: 0xF000, bits: 0xD000, decode: |op| Instruction::DisplayDraw { register_x: x(op), register_y: y(op), n: n(op) } },
+    Pattern { mask: 0xF0FF, bits: 0xE09E, decode: |op| Instruction::SkipIfKeyPressed { keycode: x(op) } },
+    Pattern { mask: 0xF0FF, bits: 0xE0A1, decode: |op| Instruction::SkipIfKeyNotPressed { keycode: x(op) } },
+    Pattern { mask: 0xF0FF, bits: 0xF007, decode: |op| Instruction::GetDelayTimer { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF015, decode: |op| Instruction::SetDelayTimer { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF018, decode: |op| Instruction::SetSoundTimer { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF01E, decode: |op| Instruction::AddI { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF033, decode: |op| Instruction::StoreBcd { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF055, decode: |op| Instruction::StoreRegisters { register_x: x(op).into() } },
+    Pattern { mask: 0xF0FF, bits: 0xF065, decode: |op| Instruction::LoadRegisters { register_x: x(op).into() } },
+];
+
+
+impl Instruction {
+    /**
+    Decodes an opcode. Opcodes that aren't implemented, including the `0NNN` machine code
+    calls, decode as `Nop` so any memory can be run or disassembled.
+    */
+    pub fn decode(opcode: u16) -> Instruction {
+        PATTERNS.iter()
+            .find(|pattern| opcode & pattern.mask == pattern.bits)
+            .map_or(Instruction::Nop, |pattern| (pattern.decode)(opcode))
+    }
+
+
+    /**
+    How many bytes the instruction stores to memory, from `I` on.
+    */
+    pub fn bytes_written(&self) -> usize {
+        match *self {
+            Instruction::StoreRegisters { register_x } => register_x + 1,
+            Instruction::StoreBcd { .. } => 3,
+            _ => 0,
+        }
+    }
+
+
+    /**
+    The inverse of `decode`.
+
+    # Returns
+    Returns the opcode of the instruction, or `None` for `Nop` and `FillScreen`, which
+    don't have one.
+    */
+    pub fn encode(&self) -> Option<u16> {
+        let xy = |bits: u16, x: usize, y: usize| bits | (x as u16) << 8 | (y as u16) << 4;
+        let opcode = match *self {
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::ReturnSubroutine => 0x00EE,
+            Instruction::Jump { addr } => 0x1000 | addr,
+            Instruction::CallSubroutine { addr } => 0x2000 | addr,
+            Instruction::SkipIfEqual { register_x, value } => 0x3000 | (register_x as u16) << 8 | value as u16,
+            Instruction::SkipIfNotEqual { register_x, value } => 0x4000 | (register_x as u16) << 8 | value as u16,
+            Instruction::SkipIfRegistersEqual { register_x, register_y } => xy(0x5000, register_x, register_y),
+            Instruction::Set { register, value } => 0x6000 | (register as u16) << 8 | value as u16,
+            Instruction::Add { register, value } => 0x7000 | (register as u16) << 8 | value as u16,
+            Instruction::SetVX { register_x, register_y } => xy(0x8000, register_x.into(), register_y.into()),
+            Instruction::BinaryOrVX { register_x, register_y } => xy(0x8001, register_x.into(), register_y.into()),
+            Instruction::BinaryAndVX { register_x, register_y } => xy(0x8002, register_x.into(), register_y.into()),
+            Instruction::BinaryXorVX { register_x, register_y } => xy(0x8003, register_x.into(), register_y.into()),
+            Instruction::AddVX { register_x, register_y } => xy(0x8004, register_x.into(), register_y.into()),
+            Instruction::SubstractVXVY { register_x, register_y } => xy(0x8005, register_x, register_y),
+            Instruction::ShiftRight { register_x, register_y } => xy(0x8006, register_x, register_y),
+            Instruction::SubstractVYVX { register_x, register_y } => xy(0x8007, register_x, register_y),
+            Instruction::ShiftLeft { register_x, register_y } => xy(0x800E, register_x, register_y),
+            Instruction::SkipIfRegistersNotEqual { register_x, register_y } => xy(0x9000, register_x, register_y),
+            Instruction::SetI { value } => 0xA000 | value,
+            Instruction::JumpWithOffset { addr } => 0xB000 | addr,
+            Instruction::DisplayDraw { register_x, register_y, n } => xy(0xD000, register_x.into(), register_y.into()) | n as u16,
+            Instruction::SkipIfKeyPressed { keycode } => xy(0xE09E, keycode.into(), 0),
+            Instruction::SkipIfKeyNotPressed { keycode } => xy(0xE0A1, keycode.into(), 0),
+            Instruction::GetDelayTimer { register_x } => xy(0xF007, register_x, 0),
+            Instruction::SetDelayTimer { register_x } => xy(0xF015, register_x, 0),
+            Instruction::SetSoundTimer { register_x } => xy(0xF018, register_x, 0),
+            Instruction::AddI { register_x } => xy(0xF01E, register_x, 0),
+            Instruction::StoreBcd { register_x } => xy(0xF033, register_x, 0),
+            Instruction::StoreRegisters { register_x } => xy(0xF055, register_x, 0),
+            Instruction::LoadRegisters { register_x } => xy(0xF065, register_x, 0),
+            Instruction::Nop | Instruction::FillScreen => return None,
+        };
+        Some(opcode)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    /**
+    Checks the fields of `instruction` against the nibbles of `opcode` it was decoded from.
+    */
+    fn check_fields(opcode: u16, instruction: Instruction) {
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let fields = match instruction {
+            Instruction::Jump { addr }
+            | Instruction::CallSubroutine { addr }
+            | Instruction::JumpWithOffset { addr }
+            | Instruction::SetI { value: addr } => addr == nnn,
+            Instruction::SkipIfEqual { register_x, value }
+            | Instruction::SkipIfNotEqual { register_x, value }
+            | Instruction::Set { register: register_x, value }
+            | Instruction::Add { register: register_x, value } => (register_x, value) == (x, nn),
+            Instruction::SkipIfRegistersEqual { register_x, register_y }
+            | Instruction::SkipIfRegistersNotEqual { register_x, register_y }
+            | Instruction::SubstractVXVY { register_x, register_y }
+            | Instruction::SubstractVYVX { register_x, register_y }
+            | Instruction::ShiftLeft { register_x, register_y }
+            | Instruction::ShiftRight { register_x, register_y } => (register_x, register_y) == (x, y),
+            Instruction::SetVX { register_x, register_y }
+            | Instruction::BinaryOrVX { register_x, register_y }
+            | Instruction::BinaryAndVX { register_x, register_y }
+            | Instruction::BinaryXorVX { register_x, register_y }
+            | Instruction::AddVX { register_x, register_y } => (register_x as usize, register_y as usize) == (x, y),
+            Instruction::DisplayDraw { register_x, register_y, n: rows } => (register_x as usize, register_y as usize, rows) == (x, y, n),
+            Instruction::SkipIfKeyPressed { keycode } | Instruction::SkipIfKeyNotPressed { keycode } => keycode as usize == x,
+            Instruction::AddI { register_x }
+            | Instruction::GetDelayTimer { register_x }
+            | Instruction::SetDelayTimer { register_x }
+            | Instruction::SetSoundTimer { register_x }
+            | Instruction::StoreBcd { register_x }
+            | Instruction::StoreRegisters { register_x }
+            | Instruction::LoadRegisters { register_x } => register_x == x,
+            Instruction::ClearScreen | Instruction::ReturnSubroutine | Instruction::Nop => true,
+            Instruction::FillScreen => false,
+        };
+        assert!(fields, "{:04X} decoded with the wrong fields: {:?}", opcode, instruction);
+    }
+
+    #[test]
+    fn test_all_opcodes() {
+        let mut variants = HashSet::new();
+        for opcode in 0..=u16::MAX {
+            let matching = PATTERNS.iter().filter(|pattern| opcode & pattern.mask == pattern.bits).count();
+            assert!(matching <= 1, "{:04X} matches {} patterns", opcode, matching);
+
+            let instruction = Instruction::decode(opcode);
+            variants.insert(discriminant(&instruction));
+            check_fields(opcode, instruction);
+            if matching == 0 {
+                assert_eq!(instruction, Instruction::Nop, "{:04X}", opcode);
+            } else {
+                assert_eq!(instruction.encode(), Some(opcode), "{:04X} decoded as {:?}", opcode, instruction);
+            }
+        }
+        // Every variant but `FillScreen` comes out of some opcode
+        assert_eq!(variants.len(), PATTERNS.len() + 1);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(0x3A42), Instruction::SkipIfEqual { register_x: 0xA, value: 0x42 });
+        assert_eq!(Instruction::decode(0x4B17), Instruction::SkipIfNotEqual { register_x: 0xB, value: 0x17 });
+        assert_eq!(Instruction::decode(0x5CD0), Instruction::SkipIfRegistersEqual { register_x: 0xC, register_y: 0xD });
+        assert_eq!(Instruction::decode(0x9120), Instruction::SkipIfRegistersNotEqual { register_x: 0x1, register_y: 0x2 });
+        assert_eq!(Instruction::decode(0x8450), Instruction::SetVX { register_x: 0x4, register_y: 0x5 });
+        assert_eq!(Instruction::decode(0x0123), Instruction::Nop);
+        assert_eq!(Instruction::decode(0xE19F), Instruction::Nop);
+        assert_eq!(Instruction::Nop.encode(), None);
+        assert_eq!(Instruction::FillScreen.encode(), None);
+    }
+}
@@ -1,6 +1,11 @@
 pub mod chip8;
 pub mod instruction;
+pub mod decode_cache;
+pub mod recompiler;
 pub mod errors;
 pub mod stack;
 pub mod low_level_operations;
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod quirks;
+pub mod state;
+pub mod trace;
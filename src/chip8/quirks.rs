@@ -0,0 +1,116 @@
+// quirks.rs
+
+use serde::{Deserialize, Serialize};
+
+
+/**
+Behaviours that differ between CHIP-8 implementations. Field names match the quirk
+names of the community chip-8-database, so platforms can be deserialized directly.
+
+The default is the modern CHIP-8 behaviour, with every quirk off.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+    /// `FX55`/`FX65` increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    /// `FX55`/`FX65` leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    /// `BNNN` behaves as `BXNN`: jumps to XNN + VX instead of NNN + V0
+    pub jump: bool,
+    /// `DXYN` waits for the next frame before continuing
+    pub vblank: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0
+    pub logic: bool,
+}
+
+
+/**
+Partial `Quirks`, e.g. a ROM that needs one quirk changed from its platform's defaults.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct QuirkOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_increment_by_x: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_leave_i_unchanged: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vblank: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    /**
+    Sets a quirk by its chip-8-database name, e.g. `memoryIncrementByX`.
+    */
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "memoryIncrementByX" => &mut self.memory_increment_by_x,
+            "memoryLeaveIUnchanged" => &mut self.memory_leave_i_unchanged,
+            "wrap" => &mut self.wrap,
+            "jump" => &mut self.jump,
+            "vblank" => &mut self.vblank,
+            "logic" => &mut self.logic,
+            _ => return Err(format!("Unknown quirk: {}", name)),
+        };
+        *quirk = Some(value);
+        Ok(())
+    }
+
+
+    /**
+    `self` with the quirks set in `other` replaced.
+    */
+    pub fn merge(self, other: &QuirkOverrides) -> Self {
+        QuirkOverrides {
+            shift: other.shift.or(self.shift),
+            memory_increment_by_x: other.memory_increment_by_x.or(self.memory_increment_by_x),
+            memory_leave_i_unchanged: other.memory_leave_i_unchanged.or(self.memory_leave_i_unchanged),
+            wrap: other.wrap.or(self.wrap),
+            jump: other.jump.or(self.jump),
+            vblank: other.vblank.or(self.vblank),
+            logic: other.logic.or(self.logic),
+        }
+    }
+}
+
+impl From<Quirks> for QuirkOverrides {
+    fn from(quirks: Quirks) -> Self {
+        QuirkOverrides {
+            shift: Some(quirks.shift),
+            memory_increment_by_x: Some(quirks.memory_increment_by_x),
+            memory_leave_i_unchanged: Some(quirks.memory_leave_i_unchanged),
+            wrap: Some(quirks.wrap),
+            jump: Some(quirks.jump),
+            vblank: Some(quirks.vblank),
+            logic: Some(quirks.logic),
+        }
+    }
+}
+
+impl Quirks {
+    pub fn with_overrides(self, overrides: &QuirkOverrides) -> Self {
+        Quirks {
+            shift: overrides.shift.unwrap_or(self.shift),
+            memory_increment_by_x: overrides.memory_increment_by_x.unwrap_or(self.memory_increment_by_x),
+            memory_leave_i_unchanged: overrides.memory_leave_i_unchanged.unwrap_or(self.memory_leave_i_unchanged),
+            wrap: overrides.wrap.unwrap_or(self.wrap),
+            jump: overrides.jump.unwrap_or(self.jump),
+            vblank: overrides.vblank.unwrap_or(self.vblank),
+            logic: overrides.logic.unwrap_or(self.logic),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// recompiler.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::MEMORY_SIZE;
+use crate::chip8::instruction::Instruction;
+
+use std::sync::Arc;
+
+
+/// Longest block compiled, so a long straight run doesn't have to be compiled at once
+const MAX_BLOCK_LENGTH: usize = 64;
+
+
+/**
+An instruction compiled by `Chip8::compile`: a closure with its operands bound, returning
+the fault that stops the machine if it can't run.
+*/
+pub type Handler = Box<dyn Fn(&mut Chip8) -> Result<(), String> + Send + Sync>;
+
+
+/**
+A basic block: the instructions from `start` up to and including the first one that can
+change the control flow or write to memory, compiled once into threaded code, one handler
+per instruction, that runs without fetching, decoding or dispatching on the instruction.
+*/
+pub struct Block {
+    pub start: u16,
+    /// Address just past the last instruction
+    pub end: u16,
+    /// The instructions as decoded, for inspecting the block
+    pub instructions: Vec<Instruction>,
+    /// The handlers run in turn, one per instruction
+    pub code: Vec<Handler>,
+}
+
+impl Block {
+    fn contains(&self, addr: usize) -> bool {
+        (self.start as usize..self.end as usize).contains(&addr)
+    }
+}
+
+
+/**
+Whether a block has to end after `instruction`: jumps, calls, returns and skips pick
+the next address at run time, and memory writes may rewrite the instructions that follow.
+*/
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(instruction,
+        Instruction::Jump { .. }
+        | Instruction::JumpWithOffset { .. }
+        | Instruction::CallSubroutine { .. }
+        | Instruction::ReturnSubroutine
+        | Instruction::SkipIfEqual { .. }
+        | Instruction::SkipIfNotEqual { .. }
+        | Instruction::SkipIfRegistersEqual { .. }
+        | Instruction::SkipIfRegistersNotEqual { .. }
+        | Instruction::SkipIfKeyPressed { .. }
+        | Instruction::SkipIfKeyNotPressed { .. }
+        | Instruction::StoreBcd { .. }
+        | Instruction::StoreRegisters { .. }
+    )
+}
+
+
+/**
+Compiles and keeps the basic blocks run so far, by start address, for `Chip8::run_block`.
+Writes to memory must `invalidate` the bytes written so self-modifying code recompiles.
+*/
+pub struct Recompiler {
+    /// By start address
+    blocks: Vec<Option<Arc<Block>>>,
+    /// How many blocks cover each byte of memory, to ignore writes to data quickly
+    covered: Vec<u16>,
+}
+
+impl Recompiler {
+    pub fn new() -> Self {
+        Recompiler { blocks: vec![None; MEMORY_SIZE], covered: vec![0; MEMORY_SIZE] }
+    }
+
+
+    /**
+    The block starting at `pc`, compiled from `memory` if it isn't already. It's empty,
+    and not kept, if no whole instruction starts at `pc` before the end of memory.
+    */
+    pub fn block(&mut self, pc: u16, memory: &[u8; MEMORY_SIZE]) -> Arc<Block> {
+        if let Some(Some(block)) = self.blocks.get(pc as usize) {
+            return Arc::clone(block);
+        }
+
+        let mut instructions = Vec::new();
+        let mut addr = pc as usize;
+        while addr + 1 < MEMORY_SIZE && instructions.len() < MAX_BLOCK_LENGTH {
+            let instruction = Instruction::decode(u16::from_be_bytes([memory[addr], memory[addr + 1]]));
+            instructions.push(instruction);
+            addr += 2;
+            if ends_block(&instruction) {
+                break;
+            }
+        }
+
+        let code = instructions.iter().map(|&instruction| Chip8::compile(instruction)).collect();
+        let block = Arc::new(Block { start: pc, end: addr as u16, instructions, code });
+        if block.code.is_empty() {
+            return block;
+        }
+        for count in &mut self.covered[pc as usize..addr] {
+            *count += 1;
+        }
+        self.blocks[pc as usize] = Some(Arc::clone(&block));
+        block
+    }
+
+
+    /**
+    Drops the blocks containing the byte at `addr`.
+    */
+    pub fn invalidate(&mut self, addr: usize) {
+        if self.covered[addr] == 0 {
+            return;
+        }
+        // Only blocks starting at most a block length before can reach it
+        let first = addr.saturating_sub(2 * MAX_BLOCK_LENGTH);
+        for entry in &mut self.blocks[first..=addr] {
+            let Some(block) = entry.take_if(|block| block.contains(addr)) else {
+                continue;
+            };
+            for count in &mut self.covered[block.start as usize..block.end as usize] {
+                *count -= 1;
+            }
+        }
+    }
+
+
+    pub fn clear(&mut self) {
+        self.blocks.fill(None);
+        self.covered.fill(0);
+    }
+}
+
+impl Default for Recompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::constants::{ROM_OFFSET, WIDTH, HEIGHT};
+    use crate::frontend::runner::Runner;
+
+    /**
+    Counts VA up with a subroutine that draws, until the subroutine rewrites the `ADD VA, VC`
+    in the loop into `SUB VA, VC` with FX55, after the block holding it has been compiled.
+    Ends in a `JP 216` loop with the BCD of VA loaded into V0-V2.
+    */
+    const SELF_MODIFYING: [(u16, u16); 22] = [
+        (0x200, 0x00E0), (0x202, 0x6A00), (0x204, 0x6C01), (0x206, 0xA300), (0x208, 0xD3A4),
+        (0x20A, 0x2230), (0x20C, 0x8AC4), (0x20E, 0x3A10), (0x210, 0x1206), (0x212, 0xFA33),
+        (0x214, 0xF265), (0x216, 0x1216),
+        (0x230, 0x7304), (0x232, 0x4314), (0x234, 0x223A), (0x236, 0x00EE),
+        (0x23A, 0x608A), (0x23C, 0x61C5), (0x23E, 0xA20C), (0x240, 0xF155), (0x242, 0x00EE),
+        (0x300, 0xF0F0),
+    ];
+
+    fn load() -> Chip8 {
+        let mut rom = vec![0; 0x102];
+        for (addr, opcode) in SELF_MODIFYING {
+            let offset = (addr - ROM_OFFSET) as usize;
+            rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8
+    }
+
+    type State = (u16, u16, [u8; 16], Vec<u16>, [u8; MEMORY_SIZE], [[bool; WIDTH]; HEIGHT], u8, u8);
+
+    fn state(chip8: &Chip8) -> State {
+        (chip8.pc(), chip8.index(), *chip8.v(), chip8.stack().to_vec(), *chip8.memory(),
+         chip8.pixel_array, chip8.delay_timer, chip8.sound_timer)
+    }
+
+    #[test]
+    fn test_block() {
+        let chip8 = load();
+        let mut recompiler = Recompiler::new();
+        let block = recompiler.block(0x206, chip8.memory());
+        assert_eq!((block.start, block.end), (0x206, 0x20C));
+        assert_eq!(block.instructions.last(), Some(&Instruction::CallSubroutine { addr: 0x230 }));
+        assert_eq!(block.code.len(), 3);
+
+        recompiler.block(0x20C, chip8.memory());
+        recompiler.invalidate(0x300);
+        let starts = |recompiler: &Recompiler| -> Vec<u16> { recompiler.blocks.iter().flatten().map(|block| block.start).collect() };
+        assert_eq!(starts(&recompiler), [0x206, 0x20C]);
+        recompiler.invalidate(0x20D);
+        assert_eq!(starts(&recompiler), [0x206]);
+        assert_eq!(recompiler.covered[0x20C], 0);
+        assert_eq!(recompiler.covered[0x20A], 1);
+    }
+
+    #[test]
+    fn test_lock_step_with_cycle() {
+        let mut interpreted = load();
+        let mut recompiled = load();
+        recompiled.recompiler = Some(Recompiler::new());
+
+        let mut instructions = 0;
+        while recompiled.pc() != 0x216 {
+            // Vary how much of each block may run, as the end of a frame would
+            let executed = recompiled.run_block(instructions % 7 + 1);
+            for _ in 0..executed {
+                interpreted.cycle();
+            }
+            instructions += executed;
+            assert_eq!(state(&recompiled), state(&interpreted), "after {} instructions", instructions);
+            assert!(instructions < 100_000, "never reached the end");
+        }
+        assert_eq!(&recompiled.memory()[0x20C..0x20E], &[0x8A, 0xC5]);
+        assert_eq!(recompiled.v()[0xA], 0x10);
+    }
+
+    #[test]
+    fn test_runner_lock_step() {
+        let mut recompiled = load();
+        recompiled.recompiler = Some(Recompiler::new());
+        let mut runners = [Runner::new(load()), Runner::new(recompiled)];
+        for runner in &mut runners {
+            runner.cycles_per_frame = 5;
+            runner.chip8.quirks.vblank = true;
+        }
+
+        for _ in 0..1000 {
+            for runner in &mut runners {
+                runner.step_frame();
+            }
+            let [interpreted, recompiled] = &runners;
+            assert_eq!(recompiled.instructions, interpreted.instructions);
+            assert_eq!(state(&recompiled.chip8), state(&interpreted.chip8), "in frame {}", interpreted.frames);
+        }
+        assert_eq!(runners[1].chip8.pc(), 0x216);
+    }
+
+    /**
+    Jumps to `target` in the last two bytes of memory, which hold `LD V0, 0x05`, and runs
+    until the program counter falls off the end and stops the machine.
+
+    # Returns
+    Returns the stopped machine and how many instructions each step ran.
+    */
+    fn run_off_the_end(target: u16, run: fn(&mut Chip8) -> u32) -> (Chip8, Vec<u32>) {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&(0x1000 | target).to_be_bytes()).unwrap();
+        chip8.write_memory(0xFFE, 0x60);
+        chip8.write_memory(0xFFF, 0x05);
+        let mut executed = Vec::new();
+        while chip8.fault().is_none() {
+            assert!(executed.len() < 3, "ran past 0x{:03X} without a fault", target);
+            executed.push(run(&mut chip8));
+        }
+        (chip8, executed)
+    }
+
+    #[test]
+    fn test_end_of_memory() {
+        let cycle = |chip8: &mut Chip8| {
+            chip8.cycle();
+            u32::from(chip8.fault().is_none())
+        };
+        for target in [0xFFE, 0xFFF] {
+            let (interpreted, cycles) = run_off_the_end(target, cycle);
+            let (recompiled, blocks) = run_off_the_end(target, |chip8| {
+                chip8.recompiler.get_or_insert_with(Recompiler::new);
+                chip8.run_block(10)
+            });
+            assert_eq!(blocks, cycles, "at 0x{:03X}", target);
+            assert_eq!(state(&recompiled), state(&interpreted), "at 0x{:03X}", target);
+            assert_eq!(recompiled.fault(), interpreted.fault(), "at 0x{:03X}", target);
+        }
+
+        let (mut chip8, cycles) = run_off_the_end(0xFFE, cycle);
+        assert_eq!((chip8.v()[0], cycles), (5, vec![1, 1, 0]), "the last whole instruction ran");
+        assert_eq!((chip8.pc(), chip8.fault()), (0x1000, Some("no instruction at 0x1000, the end of memory")));
+        chip8.cycle();
+        assert_eq!(chip8.pc(), 0x1000, "nothing runs once stopped");
+        chip8.reset();
+        assert_eq!((chip8.pc(), chip8.fault()), (0x200, None));
+    }
+}
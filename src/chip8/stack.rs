@@ -23,7 +23,21 @@ impl<T> Stack<T> {
     }
 
 
-    pub fn len(&self) -> usize {
-        self.stack.len()
+    pub fn clear(&mut self) {
+        self.stack.clear();
     }
-}
\ No newline at end of file
+
+
+    /**
+    The values on the stack, bottom first.
+    */
+    pub fn as_slice(&self) -> &[T] {
+        &self.stack
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
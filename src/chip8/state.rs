@@ -0,0 +1,122 @@
+// state.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::{WIDTH, HEIGHT, MEMORY_SIZE};
+
+use serde::{Deserialize, Serialize};
+
+
+/**
+Everything a running ROM can see of the machine, to save and later restore with
+`Chip8::save_state` and `Chip8::load_state`. The ROM, quirks and keypad aren't part of
+it: a state is restored into a machine running the same ROM.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveState {
+    pub pc: u16,
+    pub index: u16,
+    pub v: [u8; 16],
+    /// Return addresses, outermost first
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: Vec<u8>,
+    /// One hex bitmask per row, see `screen_rows`
+    pub screen: Vec<String>,
+}
+
+impl SaveState {
+    /**
+    Checks the parts serde can't: sizes, the stack depth and that the pc can run.
+    */
+    pub fn validate(&self) -> Result<(), String> {
+        if !Chip8::is_valid_pc(self.pc) {
+            return Err(format!("State pc {:03X} is outside memory", self.pc));
+        }
+        if self.memory.len() != MEMORY_SIZE {
+            return Err(format!("State memory is {} bytes instead of {}", self.memory.len(), MEMORY_SIZE));
+        }
+        if self.stack.len() > 16 {
+            return Err(format!("State stack is {} deep, at most 16 fit", self.stack.len()));
+        }
+        parse_screen_rows(&self.screen).map(|_| ())
+    }
+}
+
+
+/**
+The screen as one 16 digit hex number per row, the leftmost pixel being the highest bit,
+e.g. `8000000000000001` for a row with only its two ends lit.
+*/
+pub fn screen_rows(pixel_array: &[[bool; WIDTH]; HEIGHT]) -> Vec<String> {
+    pixel_array.iter().map(|row| {
+        let bits = row.iter().fold(0u64, |bits, &pixel| (bits << 1) | pixel as u64);
+        format!("{:016x}", bits)
+    }).collect()
+}
+
+
+pub fn parse_screen_rows(rows: &[String]) -> Result<[[bool; WIDTH]; HEIGHT], String> {
+    if rows.len() != HEIGHT {
+        return Err(format!("Screen has {} rows instead of {}", rows.len(), HEIGHT));
+    }
+    let mut pixel_array = [[false; WIDTH]; HEIGHT];
+    for (row, hex) in pixel_array.iter_mut().zip(rows) {
+        let bits = u64::from_str_radix(hex, 16).map_err(|_| format!("Bad screen row {:?}", hex))?;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = bits >> (WIDTH - 1 - x) & 1 == 1;
+        }
+    }
+    Ok(pixel_array)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+
+    #[test]
+    fn test_screen_rows() {
+        let mut pixel_array = [[false; WIDTH]; HEIGHT];
+        pixel_array[1][0] = true;
+        pixel_array[1][WIDTH - 1] = true;
+        let rows = screen_rows(&pixel_array);
+        assert_eq!(rows[0], "0000000000000000");
+        assert_eq!(rows[1], "8000000000000001");
+        assert_eq!(parse_screen_rows(&rows), Ok(pixel_array));
+        assert!(parse_screen_rows(&rows[1..]).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        // LD V0, 3; CALL 206; JP 204; ADD V0, 1; LD [I], V0; RET
+        let rom = [0x60, 0x03, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0xF0, 0x55, 0x00, 0xEE];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.set_index(0x300);
+        chip8.pixel_array[5][7] = true;
+        for _ in 0..3 {
+            chip8.cycle();
+        }
+        let state = chip8.save_state();
+        assert_eq!(state.stack, [0x204]);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let mut restored = Chip8::new();
+        restored.load_rom_bytes(&rom).unwrap();
+        restored.load_state(&serde_json::from_str(&json).unwrap()).unwrap();
+        for _ in 0..3 {
+            chip8.cycle();
+            restored.cycle();
+        }
+        assert_eq!(restored.save_state(), chip8.save_state());
+        assert_eq!(restored.memory()[0x300], 4);
+        assert!(restored.pixel_array[5][7]);
+
+        let mut short = state.clone();
+        short.memory.pop();
+        assert!(restored.load_state(&short).is_err());
+        assert!(restored.load_state(&SaveState { pc: 0xFFF, ..state }).is_err());
+    }
+}
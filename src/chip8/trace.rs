@@ -0,0 +1,202 @@
+// trace.rs
+
+use crate::chip8::instruction::Instruction;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+
+/// Columns the mnemonic is padded to in traces, enough for the longest, `DRW VX, VY, N`
+const MNEMONIC_WIDTH: usize = 16;
+
+
+/**
+Which executed instructions get logged. An empty filter logs everything.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Inclusive range of instruction addresses
+    pub addresses: Option<(u16, u16)>,
+    /// Opcode classes by their first nibble, e.g. `classes[0xD]` for DXYN
+    pub classes: Option<[bool; 16]>,
+}
+
+impl TraceFilter {
+    /**
+    Parses an inclusive address range written as `START-END` in hex, e.g. `200-2FF`.
+    */
+    pub fn parse_range(value: &str) -> Result<(u16, u16), String> {
+        let invalid = || format!("Invalid address range: {} (expected START-END in hex, e.g. 200-2FF)", value);
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let parse = |address: &str| u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| invalid());
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            return Err(invalid());
+        }
+        Ok((start, end))
+    }
+
+
+    /**
+    Parses a comma-separated list of opcode classes, each written as its first hex digit
+    optionally followed by `xxx` or `NNN`-style letters, e.g. `8,D,Fxxx`.
+    */
+    pub fn parse_classes(value: &str) -> Result<[bool; 16], String> {
+        let mut classes = [false; 16];
+        for class in value.split(',') {
+            let nibble = class.chars().next()
+                .and_then(|digit| digit.to_digit(16))
+                .filter(|_| class.chars().skip(1).all(|c| c.is_ascii_alphabetic()))
+                .ok_or(format!("Invalid opcode class: {} (expected a hex digit, e.g. D or Dxxx)", class))?;
+            classes[nibble as usize] = true;
+        }
+        Ok(classes)
+    }
+
+
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        self.addresses.is_none_or(|(start, end)| (start..=end).contains(&pc))
+            && self.classes.is_none_or(|classes| classes[(opcode >> 12) as usize])
+    }
+}
+
+
+/**
+Machine state the trace compares before and after an instruction.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub index: u16,
+}
+
+
+/**
+One executed instruction.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub before: Registers,
+    pub after: Registers,
+}
+
+impl Step {
+    /**
+    One line per instruction, without timing or anything else that would differ between
+    runs, so two traces can be compared with `diff`:
+
+    ```text
+    0200 6A05 LD VA, 0x05      VA=05
+    0202 A2F0 LD I, 0x2F0      I=02F0
+    0204 D015 DRW V0, V1, 5    VF=00
+    0206 1206 JP 0x206
+    ```
+
+    * the address of the instruction, 4 hex digits
+    * its opcode, 4 hex digits
+    * its mnemonic as the disassembler writes it, padded to `MNEMONIC_WIDTH` columns
+    * the registers and I it changed, as `VX=VV` and `I=IIII` in register order, if any
+
+    Only the address and opcode identify the instruction; the mnemonic is for readers, so
+    traces from other emulators can be compared on the first two columns alone.
+    */
+    pub fn format(&self) -> String {
+        let mut line = format!("{:04X} {:04X} {:<width$}", self.pc, self.opcode, self.instruction.to_string(), width = MNEMONIC_WIDTH);
+        for (register, (before, after)) in self.before.v.iter().zip(self.after.v).enumerate() {
+            if *before != after {
+                line.push_str(&format!(" V{:X}={:02X}", register, after));
+            }
+        }
+        if self.before.index != self.after.index {
+            line.push_str(&format!(" I={:04X}", self.after.index));
+        }
+        line.truncate(line.trim_end().len());
+        line
+    }
+}
+
+
+/**
+Writes a line per executed instruction that passes the filter. Off unless the `Chip8`
+has one, so it costs nothing in normal runs.
+*/
+pub struct Tracer {
+    pub filter: TraceFilter,
+    out: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write + Send>, filter: TraceFilter) -> Self {
+        Tracer { filter, out: BufWriter::new(out) }
+    }
+
+
+    /**
+    Traces to the file at `path`, or to stdout if it is `-`.
+    */
+    pub fn create(path: &str, filter: TraceFilter) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            "-" => Box::new(io::stdout()),
+            _ => Box::new(File::create(path)?),
+        };
+        Ok(Tracer::new(out, filter))
+    }
+
+
+    pub fn record(&mut self, step: &Step) -> io::Result<()> {
+        if self.filter.matches(step.pc, step.opcode) {
+            writeln!(self.out, "{}", step.format())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = TraceFilter {
+            addresses: Some(TraceFilter::parse_range("200-2FF").unwrap()),
+            classes: Some(TraceFilter::parse_classes("8,Dxxx").unwrap()),
+        };
+        assert!(filter.matches(0x200, 0xD015));
+        assert!(filter.matches(0x2FF, 0x8014));
+        assert!(!filter.matches(0x300, 0xD015));
+        assert!(!filter.matches(0x200, 0x6005));
+        assert!(TraceFilter::default().matches(0x300, 0x6005));
+
+        assert!(TraceFilter::parse_range("2FF-200").is_err());
+        assert!(TraceFilter::parse_range("200").is_err());
+        assert!(TraceFilter::parse_classes("G").is_err());
+        assert!(TraceFilter::parse_classes("8,").is_err());
+    }
+
+    #[test]
+    fn test_format_lists_changes() {
+        let before = Registers { v: [0; 16], index: 0 };
+        let mut after = before;
+        after.v[0xA] = 5;
+        after.v[0xF] = 1;
+
+        let step = Step { pc: 0x200, opcode: 0x6A05, instruction: Instruction::Set { register: 10, value: 5 }, before, after };
+        assert_eq!(step.format(), "0200 6A05 LD VA, 0x05      VA=05 VF=01");
+
+        let step = Step { pc: 0x202, opcode: 0xA2F0, instruction: Instruction::SetI { value: 0x2F0 }, before, after: Registers { index: 0x2F0, ..before } };
+        assert_eq!(step.format(), "0202 A2F0 LD I, 0x2F0      I=02F0");
+
+        let step = Step { pc: 0x204, opcode: 0x1204, instruction: Instruction::Jump { addr: 0x204 }, before, after: before };
+        assert_eq!(step.format(), "0204 1204 JP 0x204", "no trailing spaces without changes");
+    }
+}
@@ -0,0 +1,353 @@
+// cli.rs
+
+use octorust::chip8::trace::TraceFilter;
+use octorust::config::{Layer, PaletteSetting};
+use octorust::rl::task::{ActionSet, Done, Reward, Task};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontendKind {
+    Sdl,
+    Winit,
+    Terminal,
+    Headless,
+}
+
+impl FrontendKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sdl" => Some(FrontendKind::Sdl),
+            "winit" => Some(FrontendKind::Winit),
+            "terminal" => Some(FrontendKind::Terminal),
+            "headless" => Some(FrontendKind::Headless),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub frontend: FrontendKind,
+    pub frames: Option<u64>,
+    pub rom_db_path: Option<String>,
+    pub config_path: Option<String>,
+    pub print_config: bool,
+    /// File to write the instruction trace to, `-` for stdout
+    pub trace_path: Option<String>,
+    pub trace_filter: TraceFilter,
+    /// Where to write the profiler report, `-` for stdout
+    pub profile_path: Option<String>,
+    /// Where to write the profile as folded stacks for flamegraph tools
+    pub profile_folded_path: Option<String>,
+    /// Where to write the coverage as an annotated hex dump
+    pub coverage_path: Option<String>,
+    /// Where to write the coverage as a JSON code/data map
+    pub coverage_map_path: Option<String>,
+    /// Where to write the static control-flow graph as Graphviz DOT, instead of playing
+    pub cfg_path: Option<String>,
+    /// Port to serve the JSON-RPC control API on
+    pub server_port: Option<u16>,
+    /// Port to wait on for a GDB remote debugger before running
+    pub gdb_port: Option<u16>,
+    /// Rhai script to hook into the run
+    pub script_path: Option<String>,
+    /// Reference trace to compare the run against instead of playing
+    pub diff_path: Option<String>,
+    /// Run this many instructions as fast as possible with each execution backend (the
+    /// interpreter, the decode cache and the recompiler) and print the speeds
+    pub benchmark: Option<u64>,
+    /// Run recompiled basic blocks instead of interpreting one instruction at a time
+    pub recompiler: bool,
+    /// Play this many episodes with random actions and print their rewards
+    pub rl_episodes: Option<usize>,
+    /// Actions, rewards and end of the episodes for `--rl`
+    pub task: Task,
+    /// How many environments `--rl` plays at once
+    pub rl_envs: usize,
+    /// Threads to spread them over, all cores if unset
+    pub rl_threads: Option<usize>,
+    pub rl_seed: u64,
+    /// Reload the ROM when it or its Octo source changes on disk
+    pub watch: bool,
+    /// Settings given on the command line, the topmost config layer
+    pub overrides: Layer,
+}
+
+
+pub const USAGE: &str = "Usage: octorust [OPTIONS] <ROM>
+
+Options:
+    --frontend <sdl|winit|terminal|headless>
+                                        Backend used to display the game (default: sdl)
+    --frames <N>                        Stop after N frames
+    --platform <ID>                     Run as this platform (e.g. originalChip8, superchip),
+                                        overriding the ROM database
+    --speed <N>                         Instructions per frame (default: from the platform)
+    --quirk <NAME=on|off>               Override one quirk, e.g. shift=on; repeatable
+    --palette <NAME|#RGB,#RGB,...>      Colour palette, a built-in name or 2-4 hex colours
+    --filter <none|fade[:N]|blend[:N]>  Anti-flicker filter: fade pixels out over N frames
+                                        or show pixels lit in any of the last N frames
+    --effects <EFFECT,...|none>         Post-processing chain, applied in order: grid,
+                                        scanlines, bloom, rounded, curvature (default: grid)
+    --scale <N>                         Initial window scale (default: 10)
+    --volume <0-100>                    Buzzer volume (default: 15)
+    --mute                              Same as --volume 0
+    --rom-db <PATH>                     Extra ROM entries in chip-8-database programs.json
+                                        format (default: ~/.config/octorust/roms.json)
+    --config <PATH>                     Config file (default: ~/.config/octorust/config.toml)
+    --print-config                      Print the effective settings for the ROM and exit
+    --trace <PATH|->                    Log every executed instruction to a file or stdout
+    --trace-range <START-END>           Only trace instructions in this hex address range
+    --trace-ops <CLASS,...>             Only trace these opcode classes, e.g. 8,D,Fxxx
+    --profile <PATH|->                  Write the hottest addresses, instructions and
+                                        subroutines to a file or stdout on exit
+    --profile-folded <PATH>             Write the profile as folded stacks for flamegraphs
+    --coverage <PATH|->                 Write a hex dump of the ROM marking code, sprite
+                                        and data bytes on exit
+    --coverage-map <PATH|->             Write the same as a JSON code/data map
+    --cfg <PATH|->                      Write the ROM's static control-flow graph as a
+                                        Graphviz DOT file, print a summary and exit
+    --server <PORT>                     Serve a JSON-RPC control API over WebSocket (or one
+                                        message per line over TCP) on localhost:PORT
+    --gdb <PORT>                        Wait for GDB to connect on localhost:PORT (target
+                                        remote :PORT) and let it control the run
+    --script <PATH>                     Run a Rhai script with hooks on PC, memory writes,
+                                        frames and keys, e.g. for cheats or timers
+    --diff <PATH>                       Compare the run against another emulator's trace
+                                        and stop at the first difference
+    --recompiler                        Run recompiled basic blocks instead of interpreting,
+                                        for long headless runs
+    --benchmark <N>                     Run N instructions as fast as possible with each
+                                        execution backend and print the speeds
+    --rl <EPISODES>                     Play EPISODES episodes with random actions as a
+                                        reinforcement learning environment and print
+                                        their rewards; --frames caps each episode
+    --rl-actions <KEYS,...>             Actions to pick from, hex keys with + joining
+                                        keys held together, e.g. 4,6,4+5 (default: each key)
+    --rl-reward <SPEC>                  Reward changes of a RAM value: byte:ADDR, word:ADDR
+                                        or bcd:ADDR:DIGITS, optionally *WEIGHT; repeatable
+    --rl-done <ADDR=VALUE>              End the episode when the byte at ADDR holds VALUE;
+                                        repeatable. Halting in a jump to itself always does
+    --rl-frame-skip <N>                 Frames each action is held for (default: 4)
+    --rl-envs <N>                       Environments played at once (default: 8)
+    --rl-threads <N>                    Threads to run them on (default: all cores)
+    --rl-seed <N>                       Seed for the random actions (default: 0)
+    --watch                             Reload the ROM when it or the .8o source next to
+                                        it changes
+    -h, --help                          Print this help";
+
+
+fn number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a value", option))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+
+/**
+Parses command line arguments, without the program name.
+
+# Returns
+Returns the parsed `Options`, or an error message to be printed together with `USAGE`.
+*/
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut frontend = FrontendKind::Sdl;
+    let mut frames = None;
+    let mut rom_db_path = None;
+    let mut config_path = None;
+    let mut print_config = false;
+    let mut trace_path = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut cfg_path = None;
+    let mut server_port = None;
+    let mut gdb_port = None;
+    let mut script_path = None;
+    let mut diff_path = None;
+    let mut benchmark = None;
+    let mut recompiler = false;
+    let mut rl_episodes = None;
+    let mut task = Task::new();
+    let mut rl_envs = 8;
+    let mut rl_threads = None;
+    let mut rl_seed = 0;
+    let mut watch = false;
+    let mut profile_path = None;
+    let mut profile_folded_path = None;
+    let mut coverage_path = None;
+    let mut coverage_map_path = None;
+    let mut overrides = Layer::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(String::new()),
+            "--frontend" => {
+                let name = args.next().ok_or("--frontend needs a value")?;
+                frontend = FrontendKind::from_name(&name)
+                    .ok_or(format!("Unknown frontend: {}", name))?;
+            },
+            "--frames" => frames = Some(number(&arg, args.next())?),
+            "--platform" => overrides.platform = Some(args.next().ok_or("--platform needs a value")?),
+            "--speed" => overrides.speed = Some(number(&arg, args.next())?),
+            "--quirk" => {
+                let value = args.next().ok_or("--quirk needs a value")?;
+                let (name, state) = value.split_once('=').ok_or(format!("Invalid quirk: {} (expected NAME=on|off)", value))?;
+                let state = match state {
+                    "on" | "true" | "1" => true,
+                    "off" | "false" | "0" => false,
+                    _ => return Err(format!("Invalid quirk: {} (expected NAME=on|off)", value)),
+                };
+                overrides.quirks.set(name, state)?;
+            },
+            "--palette" => overrides.palette = Some(PaletteSetting::Name(args.next().ok_or("--palette needs a value")?)),
+            "--filter" => overrides.filter = Some(args.next().ok_or("--filter needs a value")?),
+            "--effects" => {
+                let value = args.next().ok_or("--effects needs a value")?;
+                overrides.effects = Some(value.split(',').map(String::from).collect());
+            },
+            "--scale" => overrides.scale = Some(number(&arg, args.next())?),
+            "--volume" => overrides.audio.volume = Some(number(&arg, args.next())?),
+            "--mute" => overrides.audio.volume = Some(0),
+            "--rom-db" => rom_db_path = Some(args.next().ok_or("--rom-db needs a value")?),
+            "--config" => config_path = Some(args.next().ok_or("--config needs a value")?),
+            "--print-config" => print_config = true,
+            "--trace" => trace_path = Some(args.next().ok_or("--trace needs a value")?),
+            "--profile" => profile_path = Some(args.next().ok_or("--profile needs a value")?),
+            "--profile-folded" => profile_folded_path = Some(args.next().ok_or("--profile-folded needs a value")?),
+            "--coverage" => coverage_path = Some(args.next().ok_or("--coverage needs a value")?),
+            "--coverage-map" => coverage_map_path = Some(args.next().ok_or("--coverage-map needs a value")?),
+            "--cfg" => cfg_path = Some(args.next().ok_or("--cfg needs a value")?),
+            "--server" => server_port = Some(number(&arg, args.next())?),
+            "--gdb" => gdb_port = Some(number(&arg, args.next())?),
+            "--script" => script_path = Some(args.next().ok_or("--script needs a value")?),
+            "--diff" => diff_path = Some(args.next().ok_or("--diff needs a value")?),
+            "--benchmark" => benchmark = Some(number(&arg, args.next())?),
+            "--recompiler" => recompiler = true,
+            "--rl" => rl_episodes = Some(number(&arg, args.next())?),
+            "--rl-actions" => task.actions = ActionSet::parse(&args.next().ok_or("--rl-actions needs a value")?)?,
+            "--rl-reward" => task.rewards.push(Reward::parse(&args.next().ok_or("--rl-reward needs a value")?)?),
+            "--rl-done" => task.done.push(Done::parse(&args.next().ok_or("--rl-done needs a value")?)?),
+            "--rl-frame-skip" => task.frame_skip = number(&arg, args.next())?,
+            "--rl-envs" => rl_envs = number(&arg, args.next())?,
+            "--rl-threads" => rl_threads = Some(number(&arg, args.next())?),
+            "--rl-seed" => rl_seed = number(&arg, args.next())?,
+            "--watch" => watch = true,
+            "--trace-range" => {
+                let value = args.next().ok_or("--trace-range needs a value")?;
+                trace_filter.addresses = Some(TraceFilter::parse_range(&value)?);
+            },
+            "--trace-ops" => {
+                let value = args.next().ok_or("--trace-ops needs a value")?;
+                trace_filter.classes = Some(TraceFilter::parse_classes(&value)?);
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    Ok(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        frontend,
+        frames,
+        rom_db_path,
+        config_path,
+        print_config,
+        trace_path,
+        trace_filter,
+        profile_path,
+        profile_folded_path,
+        coverage_path,
+        coverage_map_path,
+        cfg_path,
+        server_port,
+        gdb_port,
+        script_path,
+        diff_path,
+        benchmark,
+        recompiler,
+        rl_episodes,
+        task,
+        rl_envs,
+        rl_threads,
+        rl_seed,
+        watch,
+        overrides,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = parse(args("--frontend headless --frames 10 ibm.ch8")).unwrap();
+        assert_eq!(options.rom_path, "ibm.ch8");
+        assert_eq!(options.frontend, FrontendKind::Headless);
+        assert_eq!(options.frames, Some(10));
+        assert!(!options.watch);
+        assert!(parse(args("--watch ibm.ch8")).unwrap().watch);
+        assert_eq!(parse(args("--benchmark 1000 ibm.ch8")).unwrap().benchmark, Some(1000));
+        assert!(parse(args("--recompiler ibm.ch8")).unwrap().recompiler);
+        assert_eq!(parse(args("--cfg rom.dot ibm.ch8")).unwrap().cfg_path.as_deref(), Some("rom.dot"));
+        assert_eq!(parse(args("--gdb 1234 ibm.ch8")).unwrap().gdb_port, Some(1234));
+        assert_eq!(parse(args("--server 8765 ibm.ch8")).unwrap().server_port, Some(8765));
+        assert!(parse(args("--gdb 70000 ibm.ch8")).is_err());
+        assert_eq!(parse(args("--script timer.rhai ibm.ch8")).unwrap().script_path.as_deref(), Some("timer.rhai"));
+        assert!(parse(args("ibm.ch8 --script")).is_err());
+
+        assert_eq!(parse(args("ibm.ch8")).unwrap().frontend, FrontendKind::Sdl);
+        assert!(parse(args("--frontend vga ibm.ch8")).is_err());
+        assert!(parse(args("--frames")).is_err());
+        assert!(parse(args("")).is_err());
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        let options = parse(args("--speed 20 --quirk shift=on --quirk vblank=off --mute --effects grid,bloom ibm.ch8")).unwrap();
+        assert_eq!(options.overrides.speed, Some(20));
+        assert_eq!(options.overrides.quirks.shift, Some(true));
+        assert_eq!(options.overrides.quirks.vblank, Some(false));
+        assert_eq!(options.overrides.audio.volume, Some(0));
+        assert_eq!(options.overrides.effects, Some(vec!["grid".to_string(), "bloom".to_string()]));
+        assert!(!options.print_config);
+
+        assert!(parse(args("--quirk shift ibm.ch8")).is_err());
+        assert!(parse(args("--quirk shfit=on ibm.ch8")).is_err());
+        assert!(parse(args("--speed fast ibm.ch8")).is_err());
+        assert!(parse(args("--print-config ibm.ch8")).unwrap().print_config);
+    }
+
+    #[test]
+    fn test_parse_rl() {
+        let options = parse(args("--rl 10 --rl-actions 4,6 --rl-reward bcd:2F0:3 --rl-reward byte:2F4*-5 \
+                                  --rl-done 2F4=0 --rl-frame-skip 2 --rl-envs 4 ibm.ch8")).unwrap();
+        assert_eq!(options.rl_episodes, Some(10));
+        assert_eq!(options.task.actions.len(), 3);
+        assert_eq!(options.task.rewards.len(), 2);
+        assert_eq!(options.task.done, [Done { addr: 0x2F4, value: 0 }]);
+        assert_eq!((options.task.frame_skip, options.rl_envs, options.rl_threads), (2, 4, None));
+
+        assert!(parse(args("--rl-reward score ibm.ch8")).is_err());
+        assert!(parse(args("--rl-actions 4,X ibm.ch8")).is_err());
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let options = parse(args("--trace - --trace-range 200-2ff --trace-ops d ibm.ch8")).unwrap();
+        assert_eq!(options.trace_path.as_deref(), Some("-"));
+        assert_eq!(options.trace_filter.addresses, Some((0x200, 0x2FF)));
+        assert!(options.trace_filter.matches(0x200, 0xD015));
+        assert!(!options.trace_filter.matches(0x200, 0x6005));
+
+        assert!(parse(args("ibm.ch8")).unwrap().trace_path.is_none());
+        assert!(parse(args("--trace-range 2ff ibm.ch8")).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+// config.rs
+
+use crate::chip8::constants::{CYCLES_PER_FRAME, SCALE_FACTOR};
+use crate::chip8::quirks::{Quirks, QuirkOverrides};
+use crate::display::effects::Effect;
+use crate::display::palette::Palette;
+use crate::display::persistence::FilterMode;
+use crate::frontend::Audio;
+use crate::frontend::keymap::Keymap;
+use crate::rom_db::RomDatabase;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+/**
+A palette in the config file: a built-in name or a list of 2 to 4 hex colours.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteSetting {
+    Name(String),
+    Colors(Vec<String>),
+}
+
+impl PaletteSetting {
+    pub fn to_palette(&self) -> Result<Palette, String> {
+        match self {
+            PaletteSetting::Name(name) => Palette::parse(name),
+            PaletteSetting::Colors(colors) => Palette::from_hex(colors),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSetting {
+    /// 0 to 100, 0 mutes the buzzer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u8>,
+    /// Buzzer pitch in Hz
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<u32>,
+}
+
+
+/**
+One layer of settings, where `None` means "not set here". Layers are stacked from least
+to most specific: built-in defaults, ROM database, user config file, the ROM's section in
+that file, command line flags.
+*/
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layer {
+    /// chip-8-database platform id, which picks the default quirks and speed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Instructions per frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+    /// Initial window scale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<PaletteSetting>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effects: Option<Vec<String>>,
+    pub quirks: QuirkOverrides,
+    pub keymap: Keymap,
+    pub audio: AudioSetting,
+}
+
+impl Layer {
+    /**
+    `self` with everything set in `other` replaced.
+    */
+    pub fn merge(self, other: &Layer) -> Self {
+        Layer {
+            platform: other.platform.clone().or(self.platform),
+            speed: other.speed.or(self.speed),
+            scale: other.scale.or(self.scale),
+            palette: other.palette.clone().or(self.palette),
+            filter: other.filter.clone().or(self.filter),
+            effects: other.effects.clone().or(self.effects),
+            quirks: self.quirks.merge(&other.quirks),
+            keymap: self.keymap.merge(&other.keymap),
+            audio: AudioSetting {
+                volume: other.audio.volume.or(self.audio.volume),
+                frequency: other.audio.frequency.or(self.audio.frequency),
+            },
+        }
+    }
+}
+
+
+/**
+The user config file: top-level settings plus per-ROM sections keyed by SHA-1 or file
+name, e.g. `[roms."invaders.ch8"]`.
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub defaults: Layer,
+    pub roms: HashMap<String, Layer>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table: toml::Table = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let roms = match table.remove("roms") {
+            Some(roms) => roms.try_into().map_err(|e: toml::de::Error| format!("[roms]: {}", e))?,
+            None => HashMap::new(),
+        };
+        let defaults = table.try_into().map_err(|e: toml::de::Error| e.to_string())?;
+
+        Ok(Config { defaults, roms })
+    }
+
+
+    /**
+    Loads `path` if given, otherwise the user config file if there is one.
+
+    # Returns
+    Returns the default config when no path was given and the user has no config file.
+    */
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+
+    /**
+    Settings for a ROM: the top-level ones, then its section by file name, then its
+    section by SHA-1.
+    */
+    pub fn for_rom(&self, rom_path: &str, sha1: &str) -> Layer {
+        let by_name = Path::new(rom_path).file_name()
+            .and_then(|name| self.roms.get(name.to_string_lossy().as_ref()));
+        let by_hash = self.roms.get(sha1)
+            .or_else(|| self.roms.get(&sha1.to_uppercase()));
+
+        [by_name, by_hash].into_iter().flatten()
+            .fold(self.defaults.clone(), |layer, section| layer.merge(section))
+    }
+}
+
+
+/**
+The effective settings a ROM runs with, after all layers are resolved.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// `None` when the ROM is unknown and no platform was asked for
+    pub platform: Option<String>,
+    pub speed: u32,
+    pub scale: u32,
+    pub quirks: Quirks,
+    pub palette: Palette,
+    pub filter: FilterMode,
+    pub effects: Vec<Effect>,
+    pub keymap: Keymap,
+    pub audio: Audio,
+}
+
+impl Settings {
+    /**
+    Resolves `layer` (config file and command line, already merged) on top of what the
+    ROM database knows about the ROM with hash `sha1` and the built-in defaults.
+    */
+    pub fn resolve(layer: &Layer, rom_db: &RomDatabase, sha1: &str) -> Result<Self, String> {
+        let detected = rom_db.lookup(sha1, None);
+
+        let platform_id = layer.platform.clone()
+            .or_else(|| detected.as_ref().map(|rom| rom.platform.id.clone()));
+        let platform = match &platform_id {
+            Some(id) => Some(rom_db.platform(id).ok_or(format!(
+                "Unknown platform: {} (available: {})", id, rom_db.platform_ids().join(", ")))?),
+            None => None,
+        };
+
+        // The ROM's own quirks and speed for the chosen platform, else the platform's
+        let rom = platform.and_then(|platform| rom_db.lookup(sha1, Some(&platform.id)));
+        let quirks = match (&rom, platform) {
+            (Some(rom), _) => rom.quirks,
+            (None, Some(platform)) => platform.quirks,
+            (None, None) => Quirks::default(),
+        };
+        let database_speed = match (&rom, platform) {
+            (Some(rom), _) => Some(rom.tickrate),
+            (None, Some(platform)) => Some(platform.default_tickrate),
+            (None, None) => None,
+        };
+
+        let database_palette = detected.as_ref()
+            .and_then(|rom| rom.entry.colors.as_ref())
+            .filter(|colors| colors.pixels.len() >= 2)
+            .map(|colors| PaletteSetting::Colors(colors.pixels[..colors.pixels.len().min(4)].to_vec()));
+        let palette = match layer.palette.as_ref().or(database_palette.as_ref()) {
+            Some(setting) => setting.to_palette()?,
+            None => Palette::default(),
+        };
+
+        let filter = match &layer.filter {
+            Some(value) => FilterMode::parse(value)?,
+            None => FilterMode::None,
+        };
+
+        let effects = match &layer.effects {
+            Some(names) => Effect::parse_chain(&names.join(","))?,
+            None => vec![Effect::Grid],
+        };
+
+        let database_keymap = detected.as_ref()
+            .map(|rom| Keymap::from_names(&rom.entry.keys))
+            .unwrap_or_default();
+
+        let speed = layer.speed.or(database_speed).unwrap_or(CYCLES_PER_FRAME);
+        let scale = layer.scale.unwrap_or(SCALE_FACTOR as u32);
+        if speed == 0 {
+            return Err("speed must be at least 1".to_string());
+        }
+        if scale == 0 {
+            return Err("scale must be at least 1".to_string());
+        }
+
+        Ok(Settings {
+            platform: platform.map(|platform| platform.id.clone()),
+            speed,
+            scale,
+            quirks: quirks.with_overrides(&layer.quirks),
+            palette,
+            filter,
+            effects,
+            keymap: database_keymap.merge(&layer.keymap),
+            audio: Audio {
+                volume: layer.audio.volume.unwrap_or(Audio::default().volume).min(100),
+                frequency: layer.audio.frequency.unwrap_or(Audio::default().frequency),
+            },
+        })
+    }
+
+
+    /**
+    The effective settings as a config file, e.g. to attach to a bug report. Pasting it
+    into a ROM's section reproduces the same setup.
+    */
+    pub fn to_toml(&self) -> String {
+        let palette = match Palette::built_in(&self.palette.name) {
+            Some(built_in) if built_in == self.palette => PaletteSetting::Name(self.palette.name.clone()),
+            _ => PaletteSetting::Colors(self.palette.colors.iter().map(|color| format!("#{:06X}", color)).collect()),
+        };
+
+        let layer = Layer {
+            platform: self.platform.clone(),
+            speed: Some(self.speed),
+            scale: Some(self.scale),
+            palette: Some(palette),
+            filter: Some(self.filter.to_string()),
+            effects: Some(self.effects.iter().map(|effect| effect.name().to_string()).collect()),
+            quirks: self.quirks.into(),
+            keymap: self.keymap,
+            audio: AudioSetting {
+                volume: Some(self.audio.volume),
+                frequency: Some(self.audio.frequency),
+            },
+        };
+        toml::to_string(&layer).expect("Settings are always serializable")
+    }
+}
+
+
+/**
+`$XDG_CONFIG_HOME/octorust/config.toml`, falling back to `~/.config` or `%APPDATA%`.
+*/
+pub fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(dir.join("octorust").join("config.toml"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("palette = \"amber\"").unwrap();
+        assert_eq!(config.defaults.palette.unwrap().to_palette().unwrap().name, "amber");
+
+        let config = Config::parse("palette = [\"#000000\", \"#FFFFFF\"]\n[quirks]\nshift = true").unwrap();
+        assert_eq!(config.defaults.palette.unwrap().to_palette().unwrap().colors[1], 0xFF_FFFF);
+        assert_eq!(config.defaults.quirks.shift, Some(true));
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("colour = \"amber\"").is_err());
+        assert!(Config::parse("[roms.\"a.ch8\"]\ncolour = \"amber\"").is_err());
+        assert!(Config::parse("[quirks]\nshfit = true").is_err());
+    }
+
+    #[test]
+    fn test_for_rom() {
+        let config = Config::parse(&format!(r#"
+            palette = "amber"
+            filter = "fade"
+            speed = 20
+
+            [roms."invaders.ch8"]
+            filter = "blend:3"
+            speed = 30
+
+            [roms."{}"]
+            speed = 40
+        "#, SHA1)).unwrap();
+
+        let rom = config.for_rom("/roms/invaders.ch8", "ffff");
+        assert_eq!(rom.filter.as_deref(), Some("blend:3"));
+        assert_eq!(rom.palette, Some(PaletteSetting::Name("amber".to_string())));
+        assert_eq!(rom.speed, Some(30));
+
+        // The hash section is more specific than the file name one
+        let rom = config.for_rom("/roms/invaders.ch8", SHA1);
+        assert_eq!(rom.filter.as_deref(), Some("blend:3"));
+        assert_eq!(rom.speed, Some(40));
+
+        assert_eq!(config.for_rom("pong.ch8", "ffff").filter.as_deref(), Some("fade"));
+    }
+
+    #[test]
+    fn test_resolve_layers() {
+        let mut rom_db = RomDatabase::embedded();
+        rom_db.add_programs(&format!(r##"[{{ "title": "Test", "roms": {{ "{}": {{
+            "platforms": ["superchip"],
+            "colors": {{ "pixels": ["#000000", "#FFFFFF"] }},
+            "keys": {{ "up": 5, "a": 6 }}
+        }} }} }}]"##, SHA1)).unwrap();
+
+        // Defaults only
+        let settings = Settings::resolve(&Layer::default(), &rom_db, "ffff").unwrap();
+        assert_eq!(settings.platform, None);
+        assert_eq!(settings.speed, CYCLES_PER_FRAME);
+        assert_eq!(settings.quirks, Quirks::default());
+        assert_eq!(settings.palette, Palette::default());
+
+        // ROM database
+        let settings = Settings::resolve(&Layer::default(), &rom_db, SHA1).unwrap();
+        assert_eq!(settings.platform.as_deref(), Some("superchip"));
+        assert_eq!(settings.speed, 30);
+        assert!(settings.quirks.shift);
+        assert_eq!(settings.palette.colors[1], 0xFF_FFFF);
+        assert_eq!(settings.keymap.up, Some(5));
+
+        // User settings over the database
+        let mut layer = Layer {
+            platform: Some("originalChip8".to_string()),
+            palette: Some(PaletteSetting::Name("lcd".to_string())),
+            keymap: Keymap { up: Some(2), ..Keymap::default() },
+            ..Layer::default()
+        };
+        layer.quirks.set("vblank", false).unwrap();
+        let settings = Settings::resolve(&layer, &rom_db, SHA1).unwrap();
+        assert_eq!(settings.speed, 15);
+        assert!(!settings.quirks.shift);
+        assert!(settings.quirks.logic);
+        assert!(!settings.quirks.vblank);
+        assert_eq!(settings.palette.name, "lcd");
+        assert_eq!(settings.keymap.up, Some(2));
+        assert_eq!(settings.keymap.a, Some(6));
+
+        let layer = Layer { platform: Some("vic20".to_string()), ..Layer::default() };
+        assert!(Settings::resolve(&layer, &rom_db, SHA1).is_err());
+    }
+
+    #[test]
+    fn test_to_toml_round_trips() {
+        let rom_db = RomDatabase::embedded();
+        let layer = Layer {
+            platform: Some("chip48".to_string()),
+            palette: Some(PaletteSetting::Colors(vec!["#123456".to_string(), "#ABCDEF".to_string()])),
+            filter: Some("fade:3".to_string()),
+            ..Layer::default()
+        };
+        let settings = Settings::resolve(&layer, &rom_db, "ffff").unwrap();
+
+        let printed = Config::parse(&settings.to_toml()).unwrap();
+        assert_eq!(Settings::resolve(&printed.defaults, &rom_db, "ffff").unwrap(), settings);
+    }
+}
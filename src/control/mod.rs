@@ -0,0 +1,3 @@
+pub mod base64;
+pub mod server;
+pub mod websocket;
@@ -0,0 +1,690 @@
+// server.rs
+
+use crate::chip8::chip8::{Chip8, sha1_hex};
+use crate::chip8::constants::{WIDTH, HEIGHT, MEMORY_SIZE};
+use crate::chip8::state::{SaveState, screen_rows};
+use crate::control::base64;
+use crate::control::websocket::{self, CONTINUATION, TEXT, BINARY, CLOSE, PING, PONG, MAX_PAYLOAD};
+use crate::control::websocket::{NORMAL_CLOSURE, PROTOCOL_ERROR, MESSAGE_TOO_BIG};
+use crate::frontend::runner::Runner;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+
+/// How long to wait for a client's next request after answering one, so scripts sending
+/// requests one after another aren't held to one per frame
+const FOLLOW_UP_POLL: Duration = Duration::from_millis(1);
+
+/// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const FAILED: i64 = -32000;
+
+
+#[derive(Debug, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+
+/**
+How a client speaks, told by its first bytes: an HTTP upgrade to WebSocket, or JSON-RPC
+messages one per line straight over TCP, for clients without a WebSocket library.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Unknown,
+    WebSocket,
+    Lines,
+}
+
+
+struct Client {
+    stream: TcpStream,
+    protocol: Protocol,
+    /// Bytes received that don't make a whole message yet
+    buffer: Vec<u8>,
+    /// Payload of a fragmented WebSocket message so far
+    fragments: Vec<u8>,
+    closed: bool,
+}
+
+impl Client {
+    /**
+    Reads what the client sent, waiting up to `timeout` for something if given.
+
+    # Returns
+    Returns the complete messages received.
+    */
+    fn receive(&mut self, timeout: Option<Duration>) -> io::Result<Vec<String>> {
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.set_nonblocking(timeout.is_none())?;
+        let mut chunk = [0; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => self.closed = true,
+            Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+            Err(e) => return Err(e),
+        }
+        self.stream.set_nonblocking(false)?;
+
+        if self.protocol == Protocol::Unknown {
+            self.detect()?;
+        }
+        match self.protocol {
+            Protocol::Unknown => Ok(Vec::new()),
+            Protocol::WebSocket => self.websocket_messages(),
+            Protocol::Lines => Ok(self.lines()),
+        }
+    }
+
+
+    fn detect(&mut self) -> io::Result<()> {
+        if self.buffer.iter().find(|byte| !byte.is_ascii_whitespace()).is_some_and(|&byte| byte == b'{' || byte == b'[') {
+            self.protocol = Protocol::Lines;
+            return Ok(());
+        }
+        let Some(end) = self.buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return Ok(());
+        };
+        let request: Vec<u8> = self.buffer.drain(..end + 4).collect();
+        match websocket::handshake_response(&String::from_utf8_lossy(&request)) {
+            Ok(response) => {
+                self.stream.write_all(response.as_bytes())?;
+                self.protocol = Protocol::WebSocket;
+            },
+            Err(e) => {
+                write!(self.stream, "HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", e.len(), e)?;
+                self.closed = true;
+            },
+        }
+        Ok(())
+    }
+
+
+    fn websocket_messages(&mut self) -> io::Result<Vec<String>> {
+        let mut messages = Vec::new();
+        loop {
+            let (frame, used) = match websocket::parse_frame(&self.buffer) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break,
+                Err(_) => {
+                    self.close(PROTOCOL_ERROR)?;
+                    break;
+                },
+            };
+            self.buffer.drain(..used);
+            match frame.opcode {
+                TEXT | BINARY | CONTINUATION => {
+                    if self.fragments.len() + frame.payload.len() > MAX_PAYLOAD {
+                        self.close(MESSAGE_TOO_BIG)?;
+                        break;
+                    }
+                    self.fragments.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        messages.push(String::from_utf8_lossy(&self.fragments).into_owned());
+                        self.fragments.clear();
+                    }
+                },
+                PING => self.stream.write_all(&websocket::encode_frame(PONG, &frame.payload))?,
+                CLOSE => {
+                    self.close(NORMAL_CLOSURE)?;
+                    break;
+                },
+                _ => {},
+            }
+        }
+        Ok(messages)
+    }
+
+
+    fn lines(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() {
+                messages.push(line.trim().to_string());
+            }
+        }
+        messages
+    }
+
+
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        match self.protocol {
+            Protocol::WebSocket => self.stream.write_all(&websocket::encode_frame(TEXT, message.as_bytes())),
+            _ => writeln!(self.stream, "{}", message),
+        }
+    }
+
+
+    /**
+    Closes the WebSocket connection with a close frame holding `status`.
+    */
+    fn close(&mut self, status: u16) -> io::Result<()> {
+        self.closed = true;
+        self.stream.write_all(&websocket::encode_frame(CLOSE, &status.to_be_bytes()))
+    }
+}
+
+
+/**
+A local JSON-RPC 2.0 server for driving the emulator from scripts, over WebSocket or over
+plain TCP with one message per line. Requests are answered between frames, and `step` and
+`run_frames` go through the `Runner`, so the timers and the profiler keep counting. See
+`call` for the methods.
+*/
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    /// Stops the ROM between requests, set by the `pause` method
+    pub paused: bool,
+}
+
+impl ControlServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer { listener, clients: Vec::new(), paused: false })
+    }
+
+
+    pub fn port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+
+    /**
+    Accepts new clients and answers their requests. While paused, waits up to a frame for
+    them rather than letting the run loop spin.
+    */
+    pub fn poll(&mut self, runner: &mut Runner) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nodelay(true).is_ok() {
+                self.clients.push(Client { stream, protocol: Protocol::Unknown, buffer: Vec::new(), fragments: Vec::new(), closed: false });
+            }
+        }
+
+        let wait = (runner.frame_duration / self.clients.len().max(1) as u32).max(FOLLOW_UP_POLL);
+        if self.paused && self.clients.is_empty() {
+            std::thread::sleep(wait);
+            return;
+        }
+        let deadline = Instant::now() + runner.frame_duration;
+        let mut timeout = self.paused.then_some(wait);
+        loop {
+            let mut answered = false;
+            for client in &mut self.clients {
+                let served = client.receive(timeout).and_then(|messages| {
+                    for message in &messages {
+                        if let Some(reply) = handle(message, runner, &mut self.paused) {
+                            client.send(&reply)?;
+                        }
+                    }
+                    Ok(!messages.is_empty())
+                });
+                match served {
+                    Ok(served) => answered |= served,
+                    Err(_) => client.closed = true,
+                }
+            }
+            self.clients.retain(|client| !client.closed);
+            if !answered || Instant::now() >= deadline {
+                break;
+            }
+            timeout = Some(FOLLOW_UP_POLL);
+        }
+    }
+}
+
+
+/**
+Answers a JSON-RPC message, a single request or a batch.
+
+# Returns
+Returns the reply, `None` if there was nothing to answer, i.e. only notifications.
+*/
+fn handle(message: &str, runner: &mut Runner, paused: &mut bool) -> Option<String> {
+    let reply = match serde_json::from_str::<Value>(message) {
+        Err(e) => Some(error_reply(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        Ok(Value::Array(batch)) if !batch.is_empty() => {
+            let replies: Vec<Value> = batch.into_iter().filter_map(|request| handle_request(request, runner, paused)).collect();
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        },
+        Ok(request) => handle_request(request, runner, paused),
+    };
+    reply.map(|reply| reply.to_string())
+}
+
+
+fn handle_request(request: Value, runner: &mut Runner, paused: &mut bool) -> Option<Value> {
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Some(error_reply(request.get("id").cloned().unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Missing method")));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let result = call(method, params, runner, paused);
+    // Requests without an id are notifications, which get no reply
+    let id = request.get("id")?.clone();
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_reply(id, error),
+    })
+}
+
+
+fn error_reply(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } })
+}
+
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    // Methods without required params can be called without any
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+
+#[derive(Deserialize)]
+struct LoadRom {
+    path: Option<String>,
+    /// The ROM itself, in base64
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Step {
+    #[serde(default = "one")]
+    cycles: u32,
+}
+
+#[derive(Deserialize)]
+struct RunFrames {
+    #[serde(default = "one")]
+    frames: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct Key {
+    key: usize,
+}
+
+#[derive(Deserialize)]
+struct ReadMemory {
+    address: usize,
+    length: usize,
+}
+
+#[derive(Deserialize)]
+struct WriteMemory {
+    address: usize,
+    data: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct SetRegisters {
+    v: Option<[u8; 16]>,
+    i: Option<u16>,
+    pc: Option<u16>,
+    delay_timer: Option<u8>,
+    sound_timer: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct Framebuffer {
+    #[serde(default)]
+    format: FramebufferFormat,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum FramebufferFormat {
+    /// The pixels packed 8 to a byte, row by row, leftmost pixel in the highest bit
+    #[default]
+    Base64,
+    /// One hex number per row, see `screen_rows`
+    Bitmask,
+}
+
+#[derive(Deserialize)]
+struct LoadState {
+    state: SaveState,
+}
+
+
+/**
+Runs a method on the emulator:
+
+- `info`: frames and instructions run, whether paused, `pc`, speed and ROM SHA-1
+- `load_rom` `{path}` or `{data}` (base64): loads a ROM keeping the quirks and speed
+- `reset`, `pause`, `resume`
+- `step` `{cycles}`: runs instructions, returning the registers
+- `run_frames` `{frames}`: runs whole frames
+
+  Both fail once the ROM stops the machine, e.g. returning with an empty stack, until
+  it's reset or another ROM or state is loaded. See `Chip8::fault`.
+- `press_key`, `release_key` `{key}`: a keypad key, 0 to 15
+- `read_memory` `{address, length}` and `write_memory` `{address, data}`, bytes as arrays
+- `get_registers` and `set_registers`: `v` (all 16), `i`, `pc`, `stack` (read only) and
+  the timers
+- `get_framebuffer` `{format}`: `base64` or `bitmask`
+- `save_state` and `load_state` `{state}`
+*/
+fn call(method: &str, params: Value, runner: &mut Runner, paused: &mut bool) -> RpcResult {
+    let failed = |e: String| RpcError::new(FAILED, e);
+    match method {
+        "info" => Ok(json!({
+            "frames": runner.frames,
+            "instructions": runner.instructions,
+            "paused": *paused,
+            "pc": runner.chip8.pc(),
+            "speed": runner.cycles_per_frame,
+            "sha1": sha1_hex(runner.chip8.rom()),
+        })),
+        "load_rom" => {
+            let LoadRom { path, data } = self::params(params)?;
+            let rom = match (path, data) {
+                (Some(path), None) => std::fs::read(&path).map_err(|e| failed(format!("Failed reading {}: {}", path, e)))?,
+                (None, Some(data)) => base64::decode(&data).map_err(|e| RpcError::new(INVALID_PARAMS, e))?,
+                _ => return Err(RpcError::new(INVALID_PARAMS, "Expected either path or data")),
+            };
+            let mut chip8 = Chip8::new();
+            chip8.quirks = runner.chip8.quirks;
+            let sha1 = chip8.load_rom_bytes(&rom).map_err(|e| failed(e.to_string()))?;
+            runner.replace(chip8);
+            Ok(json!({ "sha1": sha1 }))
+        },
+        "reset" => {
+            runner.reset();
+            Ok(Value::Null)
+        },
+        "pause" | "resume" => {
+            *paused = method == "pause";
+            Ok(Value::Null)
+        },
+        "step" => {
+            let Step { cycles } = self::params(params)?;
+            for _ in 0..cycles {
+                runner.step();
+                check_fault(&runner.chip8)?;
+            }
+            Ok(registers(&runner.chip8))
+        },
+        "run_frames" => {
+            let RunFrames { frames } = self::params(params)?;
+            for _ in 0..frames {
+                runner.step_frame();
+                check_fault(&runner.chip8)?;
+            }
+            Ok(json!({ "frames": runner.frames }))
+        },
+        "press_key" | "release_key" => {
+            let Key { key } = self::params(params)?;
+            let pressed = runner.chip8.keypad.get_mut(key).ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("No key {}", key)))?;
+            *pressed = method == "press_key";
+            Ok(Value::Null)
+        },
+        "read_memory" => {
+            let ReadMemory { address, length } = self::params(params)?;
+            let bytes = runner.chip8.memory().get(address..address.saturating_add(length))
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Range outside memory"))?;
+            Ok(json!(bytes))
+        },
+        "write_memory" => {
+            let WriteMemory { address, data } = self::params(params)?;
+            if address.checked_add(data.len()).is_none_or(|end| end > MEMORY_SIZE) {
+                return Err(RpcError::new(INVALID_PARAMS, "Range outside memory"));
+            }
+            for (offset, byte) in data.into_iter().enumerate() {
+                runner.chip8.write_memory(address + offset, byte);
+            }
+            Ok(Value::Null)
+        },
+        "get_registers" => Ok(registers(&runner.chip8)),
+        "set_registers" => {
+            let SetRegisters { v, i, pc, delay_timer, sound_timer } = self::params(params)?;
+            if pc.is_some_and(|pc| !Chip8::is_valid_pc(pc)) {
+                return Err(RpcError::new(INVALID_PARAMS, "The pc must leave room for an instruction in memory"));
+            }
+            let chip8 = &mut runner.chip8;
+            for (register, value) in v.iter().flatten().enumerate() {
+                chip8.set_v(register, *value);
+            }
+            if let Some(index) = i {
+                chip8.set_index(index);
+            }
+            if let Some(pc) = pc {
+                chip8.set_pc(pc);
+            }
+            chip8.delay_timer = delay_timer.unwrap_or(chip8.delay_timer);
+            chip8.sound_timer = sound_timer.unwrap_or(chip8.sound_timer);
+            Ok(registers(chip8))
+        },
+        "get_framebuffer" => {
+            let Framebuffer { format } = self::params(params)?;
+            let pixels = &runner.chip8.pixel_array;
+            Ok(match format {
+                FramebufferFormat::Base64 => {
+                    let packed: Vec<u8> = pixels.as_flattened().chunks(8)
+                        .map(|byte| byte.iter().fold(0, |bits, &pixel| (bits << 1) | pixel as u8))
+                        .collect();
+                    json!({ "width": WIDTH, "height": HEIGHT, "data": base64::encode(&packed) })
+                },
+                FramebufferFormat::Bitmask => json!({ "width": WIDTH, "height": HEIGHT, "rows": screen_rows(pixels) }),
+            })
+        },
+        "save_state" => Ok(json!(runner.chip8.save_state())),
+        "load_state" => {
+            let LoadState { state } = self::params(params)?;
+            runner.chip8.load_state(&state).map_err(failed)?;
+            Ok(Value::Null)
+        },
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("No method {}", method))),
+    }
+}
+
+
+fn check_fault(chip8: &Chip8) -> Result<(), RpcError> {
+    match chip8.fault() {
+        Some(fault) => Err(RpcError::new(FAILED, format!("Stopped at 0x{:03X}: {}", chip8.pc(), fault))),
+        None => Ok(()),
+    }
+}
+
+
+fn registers(chip8: &Chip8) -> Value {
+    json!({
+        "v": chip8.v(),
+        "i": chip8.index(),
+        "pc": chip8.pc(),
+        "stack": chip8.stack(),
+        "delay_timer": chip8.delay_timer,
+        "sound_timer": chip8.sound_timer,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// LD V0, 5; ADD V0, 1; JP 202
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    fn runner() -> Runner {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        Runner::new(chip8)
+    }
+
+    fn request(runner: &mut Runner, method: &str, params: Value) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let reply: Value = serde_json::from_str(&handle(&message, runner, &mut false).unwrap()).unwrap();
+        assert_eq!(reply["id"], 1);
+        reply
+    }
+
+    #[test]
+    fn test_methods() {
+        let mut runner = runner();
+        assert_eq!(request(&mut runner, "step", json!({ "cycles": 2 }))["result"]["v"][0], 6);
+        request(&mut runner, "write_memory", json!({ "address": 0x203, "data": [2] }));
+        request(&mut runner, "step", json!({ "cycles": 2 }));
+        assert_eq!(request(&mut runner, "get_registers", Value::Null)["result"]["v"][0], 8);
+        assert_eq!(request(&mut runner, "read_memory", json!({ "address": 0x200, "length": 4 }))["result"], json!([0x60, 5, 0x70, 2]));
+
+        let state = request(&mut runner, "save_state", Value::Null)["result"].clone();
+        request(&mut runner, "set_registers", json!({ "v": vec![0; 16], "i": 0x300 }));
+        runner.chip8.pixel_array[0][..9].fill(true);
+        let framebuffer = request(&mut runner, "get_framebuffer", Value::Null)["result"].clone();
+        assert_eq!(framebuffer["data"].as_str().unwrap()[..4], *"/4AA");
+        let bitmask = request(&mut runner, "get_framebuffer", json!({ "format": "bitmask" }))["result"].clone();
+        assert_eq!(bitmask["rows"][0], "ff80000000000000");
+        request(&mut runner, "load_state", json!({ "state": state }));
+        assert_eq!(runner.chip8.v()[0], 8);
+        assert!(!runner.chip8.pixel_array[0][0]);
+
+        request(&mut runner, "press_key", json!({ "key": 0xF }));
+        assert!(runner.chip8.keypad[0xF]);
+        assert_eq!(request(&mut runner, "run_frames", json!({ "frames": 2 }))["result"]["frames"], 2);
+        request(&mut runner, "load_rom", json!({ "data": base64::encode(&[0x6A, 0x42]) }));
+        request(&mut runner, "step", Value::Null);
+        assert_eq!(runner.chip8.v()[0xA], 0x42);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut runner = runner();
+        assert_eq!(request(&mut runner, "fly", Value::Null)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(request(&mut runner, "press_key", json!({ "key": 16 }))["error"]["code"], INVALID_PARAMS);
+        assert_eq!(request(&mut runner, "read_memory", json!({ "address": 4000, "length": 100 }))["error"]["code"], INVALID_PARAMS);
+        assert_eq!(request(&mut runner, "load_state", json!({ "state": { "pc": 1 } }))["error"]["code"], INVALID_PARAMS);
+        assert_eq!(request(&mut runner, "write_memory", json!({ "address": usize::MAX, "data": [1] }))["error"]["code"], INVALID_PARAMS);
+        for pc in [0, 0xFFF, 0xFFFF] {
+            assert_eq!(request(&mut runner, "set_registers", json!({ "i": 0x300, "pc": pc }))["error"]["code"], INVALID_PARAMS);
+        }
+        assert_eq!(runner.chip8.index(), 0, "nothing was set");
+
+        // RET with an empty stack stops the machine instead of the server
+        request(&mut runner, "load_rom", json!({ "data": base64::encode(&[0x00, 0xEE]) }));
+        let error = request(&mut runner, "step", Value::Null)["error"].clone();
+        assert_eq!(error["code"], FAILED);
+        assert_eq!(error["message"], "Stopped at 0x200: return with an empty stack");
+        assert_eq!(request(&mut runner, "run_frames", Value::Null)["error"]["code"], FAILED);
+        assert_eq!(request(&mut runner, "get_registers", Value::Null)["result"]["pc"], 0x200);
+        request(&mut runner, "reset", Value::Null);
+        assert!(runner.chip8.fault().is_none());
+
+        let reply = handle("{", &mut runner, &mut false).unwrap();
+        assert!(reply.contains(&PARSE_ERROR.to_string()));
+        // Notifications get no reply, a batch gets one per request
+        assert_eq!(handle(r#"{"jsonrpc":"2.0","method":"reset"}"#, &mut runner, &mut false), None);
+        let batch = handle(r#"[{"jsonrpc":"2.0","id":1,"method":"info"},{"jsonrpc":"2.0","method":"pause"}]"#, &mut runner, &mut false).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&batch).unwrap().as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fragments_are_limited() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut client = Client { stream, protocol: Protocol::WebSocket, buffer: Vec::new(), fragments: Vec::new(), closed: false };
+        let fragment = |opcode| {
+            let mut frame = websocket::encode_frame(opcode, &vec![b' '; MAX_PAYLOAD / 2 + 1]);
+            frame[0] &= 0x7F;
+            frame
+        };
+
+        client.buffer = fragment(TEXT);
+        assert!(client.websocket_messages().unwrap().is_empty());
+        assert!(!client.closed);
+        client.buffer = fragment(CONTINUATION);
+        assert!(client.websocket_messages().unwrap().is_empty());
+        assert!(client.closed, "the message got too long");
+        let mut close = [0; 4];
+        peer.read_exact(&mut close).unwrap();
+        assert_eq!(close, [0x80 | CLOSE, 2, 0x03, 0xF1]);
+    }
+
+    /// A client on another thread doing the WebSocket handshake and sending masked frames
+    /// while the ROM runs, then a request over a second, plain connection
+    #[test]
+    fn test_session() {
+        let mut runner = runner();
+        let mut server = ControlServer::bind(0).unwrap();
+        let port = server.port().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            socket.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                               Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+            let mut received = Vec::new();
+            let mut exchange = |socket: &mut TcpStream, text: &str| -> Value {
+                let mut frame = vec![0x81, 0x80 | text.len() as u8, 1, 2, 3, 4];
+                frame.extend(text.bytes().enumerate().map(|(i, byte)| byte ^ (i as u8 % 4 + 1)));
+                socket.write_all(&frame).unwrap();
+                loop {
+                    if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                        assert!(received.starts_with(b"HTTP/1.1 101"));
+                        received.drain(..end + 4);
+                    }
+                    if let Ok(Some((frame, used))) = websocket::parse_frame(&received) {
+                        received.drain(..used);
+                        return serde_json::from_slice(&frame.payload).unwrap();
+                    }
+                    let mut chunk = [0; 1024];
+                    let read = socket.read(&mut chunk).unwrap();
+                    assert!(read > 0, "server closed the connection");
+                    received.extend_from_slice(&chunk[..read]);
+                }
+            };
+
+            let reply = exchange(&mut socket, r#"{"jsonrpc":"2.0","id":"a","method":"pause"}"#);
+            assert_eq!(reply, json!({ "jsonrpc": "2.0", "id": "a", "result": null }));
+            let reply = exchange(&mut socket, r#"{"jsonrpc":"2.0","id":2,"method":"info"}"#);
+            let frames = reply["result"]["frames"].clone();
+            let reply = exchange(&mut socket, r#"{"jsonrpc":"2.0","id":3,"method":"info"}"#);
+            assert_eq!(reply["result"]["frames"], frames, "ran while paused");
+            assert_eq!(reply["result"]["paused"], true);
+
+            let mut plain = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            plain.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":4,\"method\":\"set_registers\",\"params\":{\"pc\":768}}\n").unwrap();
+            let mut reply = String::new();
+            io::BufRead::read_line(&mut io::BufReader::new(plain), &mut reply).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&reply).unwrap()["result"]["pc"], 0x300);
+        });
+
+        // What `Runner::run` does each frame, for at most ten seconds
+        let start = Instant::now();
+        while !client.is_finished() && start.elapsed() < Duration::from_secs(10) {
+            server.poll(&mut runner);
+            if !server.paused {
+                runner.step_frame();
+            }
+        }
+        // Disconnects the client if it's still waiting
+        drop(server);
+        client.join().unwrap();
+        assert_eq!(runner.chip8.pc(), 0x300);
+    }
+}
@@ -0,0 +1,148 @@
+// websocket.rs
+
+use crate::control::base64;
+
+use sha1::{Digest, Sha1};
+
+
+/// Appended to the client's key before hashing it into the accept key (RFC 6455)
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const CONTINUATION: u8 = 0x0;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xA;
+
+/// Status codes sent in close frames
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Largest message accepted, far more than any request needs
+pub const MAX_PAYLOAD: usize = 1 << 20;
+
+
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+
+/**
+The server's reply to a WebSocket upgrade request, given the request's header up to the
+blank line.
+*/
+pub fn handshake_response(request: &str) -> Result<String, String> {
+    let key = request.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim())
+        .ok_or("Not a WebSocket upgrade request")?;
+    Ok(format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\r\n", accept_key(key)))
+}
+
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+    base64::encode(&hasher.finalize())
+}
+
+
+/**
+Parses the frame at the start of `buffer`, unmasking its payload.
+
+# Returns
+Returns the frame and how many bytes it took, or `None` if it isn't complete yet.
+*/
+pub fn parse_frame(buffer: &[u8]) -> Result<Option<(Frame, usize)>, String> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let masked = buffer[1] & 0x80 != 0;
+    let (length, mut offset) = match buffer[1] & 0x7F {
+        126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() >= 10 => (u64::from_be_bytes(buffer[2..10].try_into().unwrap()), 10),
+        126 | 127 => return Ok(None),
+        length => (length as u64, 2),
+    };
+    if length > MAX_PAYLOAD as u64 {
+        return Err(format!("Message of {} bytes is too long", length));
+    }
+    let mask = if masked { 4 } else { 0 };
+    if buffer.len() < offset + mask + length as usize {
+        return Ok(None);
+    }
+
+    let key = &buffer[offset..offset + mask];
+    offset += mask;
+    let mut payload = buffer[offset..offset + length as usize].to_vec();
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= key[i % 4];
+        }
+    }
+    let frame = Frame { fin: buffer[0] & 0x80 != 0, opcode: buffer[0] & 0x0F, payload };
+    Ok(Some((frame, offset + length as usize)))
+}
+
+
+/**
+A single unmasked frame, as servers send them.
+*/
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake() {
+        // The example from RFC 6455
+        let request = "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\n\
+                       Connection: Upgrade\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let response = handshake_response(request).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(handshake_response("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        // A masked "Hello" from the RFC, sent in two pieces
+        let masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert!(parse_frame(&masked[..6]).unwrap().is_none());
+        let (frame, used) = parse_frame(&masked).unwrap().unwrap();
+        assert_eq!((frame.fin, frame.opcode, frame.payload.as_slice(), used), (true, TEXT, &b"Hello"[..], 11));
+
+        for length in [0, 125, 126, 70_000] {
+            let payload = vec![b'x'; length];
+            let encoded = encode_frame(BINARY, &payload);
+            let (frame, used) = parse_frame(&encoded).unwrap().unwrap();
+            assert_eq!((frame.payload.len(), used), (length, encoded.len()));
+        }
+        assert!(parse_frame(&[0x81, 0x7F, 0, 0, 0, 0, 0x10, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// benchmark.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::frontend::runner::Runner;
+
+use std::time::{Duration, Instant};
+
+
+/**
+How long an uncapped run took.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Instructions per second
+    pub fn ips(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+
+/**
+Runs `chip8` as fast as possible for whole frames of `cycles_per_frame` instructions, so
+the timers tick as they would while playing, until `instructions` have run.
+*/
+pub fn measure(chip8: Chip8, cycles_per_frame: u32, instructions: u64) -> Measurement {
+    let mut runner = Runner::new(chip8);
+    runner.cycles_per_frame = cycles_per_frame;
+    let start = Instant::now();
+    while runner.instructions < instructions {
+        runner.step_frame();
+    }
+    Measurement { instructions: runner.instructions, elapsed: start.elapsed() }
+}
+
+
+/**
+One line per named measurement, with how much faster than the first one it was.
+*/
+pub fn report(measurements: &[(&str, Measurement)]) -> String {
+    let Some((_, baseline)) = measurements.first() else {
+        return String::new();
+    };
+    measurements.iter()
+        .map(|(name, measurement)| format!("{:<14}{:>12.0} IPS {:>10.2?} {:>6.2}x\n",
+            name, measurement.ips(), measurement.elapsed, measurement.ips() / baseline.ips()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let mut chip8 = Chip8::new();
+        // 7001 1200: count in V0 forever
+        chip8.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let measurement = measure(chip8, 10, 1000);
+        assert_eq!(measurement.instructions, 1000);
+        assert!(measurement.ips() > 0.0);
+
+        let fast = Measurement { instructions: 1000, elapsed: Duration::from_millis(1) };
+        let slow = Measurement { instructions: 1000, elapsed: Duration::from_millis(4) };
+        assert_eq!(report(&[("Slow", slow), ("Fast", fast)]), "Slow                250000 IPS     4.00ms   1.00x\n\
+                                                                 Fast               1000000 IPS     1.00ms   4.00x\n");
+    }
+}
@@ -0,0 +1,344 @@
+// control_flow.rs
+
+use crate::chip8::constants::ROM_OFFSET;
+use crate::chip8::instruction::Instruction;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+
+/**
+How control gets from the end of a block to the start of another.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Runs on into the next instruction, including a skip that isn't taken
+    Next,
+    Jump,
+    Call,
+    /// From a call to the instruction after it, where the subroutine returns to
+    Return,
+    /// Over the next instruction when a skip is taken
+    Skip,
+}
+
+impl Edge {
+    fn dot_attributes(&self) -> &'static str {
+        match self {
+            Edge::Next => "",
+            Edge::Jump => " [label=\"jump\"]",
+            Edge::Call => " [label=\"call\" style=dashed]",
+            Edge::Return => " [label=\"return\" style=dotted]",
+            Edge::Skip => " [label=\"skip\"]",
+        }
+    }
+}
+
+
+/**
+Instructions run one after the other, entered only at the first one.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    /// Address just past the last instruction
+    pub end: u16,
+    /// Address, opcode and instruction
+    pub instructions: Vec<(u16, u16, Instruction)>,
+    pub successors: Vec<(u16, Edge)>,
+    /// Ends in `BNNN`, whose target is only known at run time
+    pub indirect: bool,
+}
+
+
+/**
+The blocks reachable from a subroutine's entry without following calls.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    /// Start addresses, in order
+    pub blocks: Vec<u16>,
+}
+
+impl Subroutine {
+    pub fn name(&self) -> String {
+        if self.entry == ROM_OFFSET { "main".to_string() } else { format!("sub_{:04X}", self.entry) }
+    }
+}
+
+
+/**
+Where each instruction can go next, and whether a block has to end after it.
+*/
+fn successors(pc: u16, instruction: Instruction) -> (Vec<(u16, Edge)>, bool) {
+    match instruction {
+        Instruction::Jump { addr } => (vec![(addr, Edge::Jump)], true),
+        Instruction::CallSubroutine { addr } => (vec![(addr, Edge::Call), (pc + 2, Edge::Return)], true),
+        Instruction::ReturnSubroutine | Instruction::JumpWithOffset { .. } => (Vec::new(), true),
+        Instruction::SkipIfEqual { .. }
+        | Instruction::SkipIfNotEqual { .. }
+        | Instruction::SkipIfRegistersEqual { .. }
+        | Instruction::SkipIfRegistersNotEqual { .. }
+        | Instruction::SkipIfKeyPressed { .. }
+        | Instruction::SkipIfKeyNotPressed { .. } => (vec![(pc + 2, Edge::Next), (pc + 4, Edge::Skip)], true),
+        _ => (vec![(pc + 2, Edge::Next)], false),
+    }
+}
+
+
+/**
+The static control-flow graph of a ROM, followed from `ROM_OFFSET` without running it, for
+getting to know an unfamiliar ROM. Only the targets written in the instructions are
+known: `BNNN` jumps are flagged as indirect and not followed, and code the ROM writes
+itself isn't seen.
+*/
+pub struct ControlFlow {
+    pub blocks: BTreeMap<u16, Block>,
+    /// `main` first, then by entry address
+    pub subroutines: Vec<Subroutine>,
+    /// Addresses control reaches outside the ROM, e.g. by running off its end
+    pub outside: BTreeSet<u16>,
+    rom: Vec<u8>,
+    /// Start addresses of the instructions reached
+    reached: BTreeSet<u16>,
+}
+
+impl ControlFlow {
+    pub fn analyse(rom: &[u8]) -> Self {
+        let rom_end = ROM_OFFSET as usize + rom.len();
+        let opcode = |pc: u16| -> Option<u16> {
+            let offset = (pc as usize).checked_sub(ROM_OFFSET as usize)?;
+            (pc as usize + 1 < rom_end).then(|| u16::from_be_bytes([rom[offset], rom[offset + 1]]))
+        };
+
+        // Every instruction reachable, and the ones blocks have to start at
+        let mut reached = BTreeSet::new();
+        let mut leaders = BTreeSet::from([ROM_OFFSET]);
+        let mut outside = BTreeSet::new();
+        let mut pending = VecDeque::from([ROM_OFFSET]);
+        while let Some(pc) = pending.pop_front() {
+            if reached.contains(&pc) {
+                continue;
+            }
+            let Some(opcode) = opcode(pc) else {
+                outside.insert(pc);
+                continue;
+            };
+            reached.insert(pc);
+            let (successors, ends_block) = successors(pc, Instruction::decode(opcode));
+            for (target, _) in successors {
+                if ends_block {
+                    leaders.insert(target);
+                }
+                pending.push_back(target);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|leader| reached.contains(leader)) {
+            let mut block = Block { start, end: start, instructions: Vec::new(), successors: Vec::new(), indirect: false };
+            let mut pc = start;
+            loop {
+                let opcode = opcode(pc).expect("reached instructions are in the ROM");
+                let instruction = Instruction::decode(opcode);
+                block.instructions.push((pc, opcode, instruction));
+                let (successors, ends_block) = successors(pc, instruction);
+                pc += 2;
+                if ends_block || leaders.contains(&pc) || !reached.contains(&pc) {
+                    block.indirect = matches!(instruction, Instruction::JumpWithOffset { .. });
+                    block.successors = successors;
+                    break;
+                }
+            }
+            block.end = pc;
+            blocks.insert(start, block);
+        }
+
+        let mut entries: Vec<u16> = blocks.values()
+            .flat_map(|block| &block.successors)
+            .filter(|(target, edge)| *edge == Edge::Call && blocks.contains_key(target))
+            .map(|(target, _)| *target)
+            .filter(|&target| target != ROM_OFFSET)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        entries.insert(0, ROM_OFFSET);
+        let subroutines = entries.into_iter()
+            .filter(|entry| blocks.contains_key(entry))
+            .map(|entry| Subroutine { entry, blocks: Self::body(&blocks, entry) })
+            .collect();
+
+        ControlFlow { blocks, subroutines, outside, rom: rom.to_vec(), reached }
+    }
+
+
+    /**
+    The blocks reachable from `entry` without following calls.
+    */
+    fn body(blocks: &BTreeMap<u16, Block>, entry: u16) -> Vec<u16> {
+        let mut body = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            let Some(block) = blocks.get(&start) else {
+                continue;
+            };
+            if body.insert(start) {
+                pending.extend(block.successors.iter().filter(|(_, edge)| *edge != Edge::Call).map(|(target, _)| *target));
+            }
+        }
+        body.into_iter().collect()
+    }
+
+
+    /**
+    Runs of ROM bytes no reachable instruction covers, as inclusive address ranges. These
+    are usually sprites and other data, or code only reached through `BNNN`.
+    */
+    pub fn unreachable(&self) -> Vec<(u16, u16)> {
+        let mut code = vec![false; self.rom.len()];
+        for &pc in &self.reached {
+            let offset = (pc - ROM_OFFSET) as usize;
+            code[offset..offset + 2].fill(true);
+        }
+
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for (offset, _) in code.iter().enumerate().filter(|(_, &is_code)| !is_code) {
+            let addr = ROM_OFFSET + offset as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == addr => *end = addr,
+                _ => ranges.push((addr, addr)),
+            }
+        }
+        ranges
+    }
+
+
+    /**
+    Counts of what was found, the indirect jumps and the unreachable bytes.
+    */
+    pub fn summary(&self) -> String {
+        let unreachable = self.unreachable();
+        let unreachable_bytes: usize = unreachable.iter().map(|(start, end)| (end - start + 1) as usize).sum();
+        let indirect: Vec<String> = self.blocks.values()
+            .filter(|block| block.indirect)
+            .map(|block| format!("0x{:03X}", block.end - 2))
+            .collect();
+
+        let mut summary = String::new();
+        let _ = writeln!(summary, "{} blocks in {} subroutines: {}", self.blocks.len(), self.subroutines.len(),
+            self.subroutines.iter().map(Subroutine::name).collect::<Vec<_>>().join(", "));
+        if !indirect.is_empty() {
+            let _ = writeln!(summary, "Indirect jumps (BNNN), not followed: {}", indirect.join(", "));
+        }
+        if !self.outside.is_empty() {
+            let outside: Vec<String> = self.outside.iter().map(|addr| format!("0x{:03X}", addr)).collect();
+            let _ = writeln!(summary, "Reaches outside the ROM: {}", outside.join(", "));
+        }
+        let _ = writeln!(summary, "Unreachable: {} of {} bytes", unreachable_bytes, self.rom.len());
+        for (start, end) in unreachable {
+            let _ = writeln!(summary, "  0x{:03X}-0x{:03X} {:>5} bytes", start, end, end - start + 1);
+        }
+        summary
+    }
+
+
+    /**
+    The graph in Graphviz DOT format, with a cluster per subroutine. Blocks shared by
+    several subroutines are drawn in the first one.
+    */
+    pub fn dot(&self) -> String {
+        let mut drawn = BTreeSet::new();
+        let mut dot = String::from("digraph rom {\n    node [shape=box fontname=\"monospace\"];\n");
+        for subroutine in &self.subroutines {
+            let _ = writeln!(dot, "    subgraph cluster_{:04X} {{\n        label=\"{}\";", subroutine.entry, subroutine.name());
+            for start in subroutine.blocks.iter().filter(|&&start| drawn.insert(start)) {
+                let block = &self.blocks[start];
+                let label: String = block.instructions.iter()
+                    .map(|(pc, opcode, instruction)| format!("{:04X}  {:04X}  {}\\l", pc, opcode, instruction))
+                    .collect();
+                let color = if block.indirect { " color=red" } else { "" };
+                let _ = writeln!(dot, "        b{:04X} [label=\"{}\"{}];", start, label, color);
+            }
+            dot.push_str("    }\n");
+        }
+        for addr in &self.outside {
+            let _ = writeln!(dot, "    b{:04X} [label=\"{:04X} outside the ROM\" style=dashed];", addr, addr);
+        }
+        for block in self.blocks.values() {
+            for (target, edge) in &block.successors {
+                let _ = writeln!(dot, "    b{:04X} -> b{:04X}{};", block.start, target, edge.dot_attributes());
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls a subroutine in a loop, which skips a draw and ends in BNNN, then sprite data
+    const ROM: [u8; 22] = [
+        0x22, 0x08, // 200 CALL 208
+        0x7A, 0x01, // 202 ADD VA, 0x01
+        0x12, 0x00, // 204 JP 200
+        0x00, 0x00, // 206 data
+        0x3A, 0x05, // 208 SE VA, 0x05
+        0xD0, 0x15, // 20A DRW V0, V1, 5
+        0x00, 0xEE, // 20C RET
+        0xB2, 0x00, // 20E JP V0, 0x200 (unreached)
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x00, // 210 sprite
+    ];
+
+    #[test]
+    fn test_analyse() {
+        let flow = ControlFlow::analyse(&ROM);
+        let starts: Vec<_> = flow.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x208, 0x20A, 0x20C]);
+        assert_eq!(flow.blocks[&0x200].successors, [(0x208, Edge::Call), (0x202, Edge::Return)]);
+        assert_eq!(flow.blocks[&0x202].end, 0x206);
+        assert_eq!(flow.blocks[&0x202].successors, [(0x200, Edge::Jump)]);
+        assert_eq!(flow.blocks[&0x208].successors, [(0x20A, Edge::Next), (0x20C, Edge::Skip)]);
+        assert_eq!(flow.blocks[&0x20A].successors, [(0x20C, Edge::Next)]);
+
+        assert_eq!(flow.subroutines, [
+            Subroutine { entry: 0x200, blocks: vec![0x200, 0x202] },
+            Subroutine { entry: 0x208, blocks: vec![0x208, 0x20A, 0x20C] },
+        ]);
+        assert_eq!(flow.unreachable(), [(0x206, 0x207), (0x20E, 0x215)]);
+        assert!(flow.outside.is_empty());
+    }
+
+    #[test]
+    fn test_indirect_and_outside() {
+        // 200 SE V0, 0  202 JP V0, 0x300  204 ADD V0, 1, running off the end
+        let flow = ControlFlow::analyse(&[0x30, 0x00, 0xB3, 0x00, 0x70, 0x01]);
+        assert!(flow.blocks[&0x202].indirect);
+        assert!(flow.blocks[&0x202].successors.is_empty());
+        assert_eq!(flow.outside, BTreeSet::from([0x206]));
+        assert_eq!(flow.summary(), "3 blocks in 1 subroutines: main\n\
+                                    Indirect jumps (BNNN), not followed: 0x202\n\
+                                    Reaches outside the ROM: 0x206\n\
+                                    Unreachable: 0 of 6 bytes\n");
+    }
+
+    #[test]
+    fn test_summary_and_dot() {
+        let flow = ControlFlow::analyse(&ROM);
+        assert_eq!(flow.summary(), "5 blocks in 2 subroutines: main, sub_0208\n\
+                                    Unreachable: 10 of 22 bytes\n\
+                                    \x20 0x206-0x207     2 bytes\n\
+                                    \x20 0x20E-0x215     8 bytes\n");
+
+        let dot = flow.dot();
+        assert!(dot.starts_with("digraph rom {\n"));
+        assert!(dot.contains("    subgraph cluster_0208 {\n        label=\"sub_0208\";\n"));
+        assert!(dot.contains("        b0202 [label=\"0202  7A01  ADD VA, 0x01\\l0204  1200  JP 0x200\\l\"];\n"));
+        assert!(dot.contains("    b0200 -> b0208 [label=\"call\" style=dashed];\n"));
+        assert!(dot.contains("    b0208 -> b020A;\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
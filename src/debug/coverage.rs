@@ -0,0 +1,214 @@
+// coverage.rs
+
+use crate::chip8::constants::{MEMORY_SIZE, ROM_OFFSET};
+use crate::chip8::instruction::Instruction;
+
+use serde::Serialize;
+
+use std::fmt::Write;
+
+
+/// Fetched as part of an opcode
+pub const EXECUTED: u8 = 1 << 0;
+/// Drawn by `DXYN`
+pub const SPRITE: u8 = 1 << 1;
+/// Loaded into registers by `FX65`
+pub const READ: u8 = 1 << 2;
+/// Stored by `FX55` or `FX33`
+pub const WRITTEN: u8 = 1 << 3;
+
+const KINDS: [(u8, &str); 4] = [
+    (EXECUTED, "code"),
+    (SPRITE, "sprite"),
+    (READ, "read"),
+    (WRITTEN, "written"),
+];
+
+
+/**
+A run of bytes with the same coverage in `Coverage::map`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Region {
+    pub start: u16,
+    /// Inclusive
+    pub end: u16,
+    /// `code`, `sprite`, `read` and/or `written`; empty if the bytes were never touched
+    pub kinds: Vec<&'static str>,
+}
+
+
+#[derive(Serialize)]
+struct CoverageMap {
+    regions: Vec<Region>,
+}
+
+
+/**
+How every byte of memory was used while running, to tell code from data when reverse
+engineering a ROM. A byte can be used in several ways, e.g. self-modifying code is both
+executed and written.
+*/
+pub struct Coverage {
+    pub flags: [u8; MEMORY_SIZE],
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { flags: [0; MEMORY_SIZE] }
+    }
+
+
+    /**
+    Records the accesses `instruction` at `pc` is about to make, given the index register
+    before it runs.
+    */
+    pub fn record(&mut self, pc: u16, instruction: Instruction, index: u16) {
+        self.mark(pc, 2, EXECUTED);
+        match instruction {
+            Instruction::DisplayDraw { n, .. } => self.mark(index, n as usize, SPRITE),
+            Instruction::StoreRegisters { .. } | Instruction::StoreBcd { .. } => self.mark(index, instruction.bytes_written(), WRITTEN),
+            Instruction::LoadRegisters { register_x } => self.mark(index, register_x + 1, READ),
+            _ => {},
+        }
+    }
+
+
+    fn mark(&mut self, start: u16, len: usize, flag: u8) {
+        for i in 0..len {
+            self.flags[(start as usize + i) % MEMORY_SIZE] |= flag;
+        }
+    }
+
+
+    /**
+    The ROM area worth showing: from `ROM_OFFSET` to the last byte that is touched or
+    non-zero in `memory`, in whole lines of 16 bytes.
+    */
+    fn range(&self, memory: &[u8]) -> std::ops::Range<usize> {
+        let start = ROM_OFFSET as usize;
+        let end = (start..MEMORY_SIZE).rev()
+            .find(|&addr| self.flags[addr] != 0 || memory.get(addr).is_some_and(|&byte| byte != 0))
+            .map_or(start, |last| (last / 16 + 1) * 16);
+        start..end
+    }
+
+
+    /**
+    A hex dump of the ROM area with a letter per byte showing how it was used:
+
+    ```text
+    0200  22 08 22 08 12 04 00 00 7A 01 22 0E 00 EE F0 90  CCCCCC..CCCCCCSS
+    ```
+
+    `C` code, `S` sprite, `R` read by `FX65`, `W` written by `FX55`/`FX33`, `*` more than
+    one of those and `.` untouched.
+    */
+    pub fn hex_dump(&self, memory: &[u8]) -> String {
+        let mut out = String::new();
+        for line in self.range(memory).step_by(16) {
+            let _ = write!(out, "{:04X} ", line);
+            for addr in line..line + 16 {
+                let _ = write!(out, " {:02X}", memory.get(addr).copied().unwrap_or(0));
+            }
+            out.push_str("  ");
+            out.extend(self.flags[line..line + 16].iter().map(|&flags| legend(flags)));
+            out.push('\n');
+        }
+        out
+    }
+
+
+    /**
+    The ROM area as runs of bytes with the same usage, as JSON for other tools:
+
+    ```json
+    {"regions":[{"start":512,"end":517,"kinds":["code"]},{"start":518,"end":519,"kinds":[]}]}
+    ```
+    */
+    pub fn map(&self, memory: &[u8]) -> String {
+        let mut regions: Vec<Region> = Vec::new();
+        for addr in self.range(memory) {
+            let flags = self.flags[addr];
+            match regions.last_mut() {
+                Some(region) if self.flags[region.start as usize] == flags => region.end = addr as u16,
+                _ => regions.push(Region {
+                    start: addr as u16,
+                    end: addr as u16,
+                    kinds: KINDS.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| *name).collect(),
+                }),
+            }
+        }
+        serde_json::to_string(&CoverageMap { regions }).expect("Regions are always serializable")
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+fn legend(flags: u8) -> char {
+    match flags {
+        0 => '.',
+        EXECUTED => 'C',
+        SPRITE => 'S',
+        READ => 'R',
+        WRITTEN => 'W',
+        _ => '*',
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+    use crate::frontend::runner::Runner;
+
+    // A20A D012 F233 F265 1208, then a 2 byte sprite at 020A
+    const ROM: [u8; 12] = [0xA2, 0x0A, 0xD0, 0x12, 0xF2, 0x33, 0xF2, 0x65, 0x12, 0x08, 0xF0, 0x90];
+
+    fn cover(steps: usize) -> (Coverage, Chip8) {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        let mut runner = Runner::new(chip8);
+        runner.coverage = Some(Coverage::new());
+        for _ in 0..steps {
+            runner.step();
+        }
+        (runner.coverage.take().unwrap(), runner.chip8)
+    }
+
+    #[test]
+    fn test_record() {
+        let (coverage, _) = cover(6);
+        assert_eq!(coverage.flags[0x200], EXECUTED);
+        assert_eq!(coverage.flags[0x209], EXECUTED);
+        // The sprite is also overwritten by FX33 and read back by FX65
+        assert_eq!(coverage.flags[0x20A], SPRITE | WRITTEN | READ);
+        assert_eq!(coverage.flags[0x20C], WRITTEN | READ);
+        assert_eq!(coverage.flags[0x20D], 0);
+        assert_eq!(coverage.flags[0x1FF], 0);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let (coverage, chip8) = cover(6);
+        assert_eq!(coverage.hex_dump(chip8.memory()),
+            "0200  A2 0A D0 12 F2 33 F2 65 12 08 00 00 00 00 00 00  CCCCCCCCCC***...\n");
+    }
+
+    #[test]
+    fn test_map() {
+        let (coverage, chip8) = cover(6);
+        assert_eq!(coverage.map(chip8.memory()), concat!(
+            r#"{"regions":[{"start":512,"end":521,"kinds":["code"]},"#,
+            r#"{"start":522,"end":523,"kinds":["sprite","read","written"]},"#,
+            r#"{"start":524,"end":524,"kinds":["read","written"]},"#,
+            r#"{"start":525,"end":527,"kinds":[]}]}"#,
+        ));
+    }
+}
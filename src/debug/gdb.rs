@@ -0,0 +1,557 @@
+// gdb.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::MEMORY_SIZE;
+use crate::frontend::runner::Runner;
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+
+/// How long to wait for the debugger each frame while the ROM is stopped
+const HALTED_POLL: Duration = Duration::from_millis(16);
+
+/// Stop signals: a breakpoint or single step, the debugger interrupting a continue, and
+/// an instruction that stopped the machine with a `Chip8::fault`
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+const SIGSEGV: u8 = 11;
+
+/// Registers in `g` packet order with their size in bytes. 16-bit ones are big-endian,
+/// like CHIP-8 memory, and `sp` is the depth of the call stack.
+const REGISTERS: [(&str, usize); 21] = [
+    ("v0", 1), ("v1", 1), ("v2", 1), ("v3", 1), ("v4", 1), ("v5", 1), ("v6", 1), ("v7", 1),
+    ("v8", 1), ("v9", 1), ("va", 1), ("vb", 1), ("vc", 1), ("vd", 1), ("ve", 1), ("vf", 1),
+    ("i", 2), ("pc", 2), ("sp", 1), ("dt", 1), ("st", 1),
+];
+
+
+fn stop_signal(chip8: &Chip8) -> u8 {
+    if chip8.fault().is_some() { SIGSEGV } else { SIGTRAP }
+}
+
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+
+fn number(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+
+/**
+The target description GDB asks for, naming the registers since it knows no CHIP-8.
+*/
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+                                <target version=\"1.0\">\n<feature name=\"org.octorust.chip8\">\n");
+    for (name, size) in REGISTERS {
+        let kind = if name == "pc" { " type=\"code_ptr\"" } else { "" };
+        let _ = writeln!(xml, "<reg name=\"{}\" bitsize=\"{}\"{}/>", name, size * 8, kind);
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+
+/**
+The registers in `g` packet order, see `REGISTERS`.
+*/
+fn registers(chip8: &Chip8) -> Vec<u8> {
+    let mut bytes = chip8.v().to_vec();
+    bytes.extend(chip8.index().to_be_bytes());
+    bytes.extend(chip8.pc().to_be_bytes());
+    bytes.extend([chip8.stack().len() as u8, chip8.delay_timer, chip8.sound_timer]);
+    bytes
+}
+
+
+/**
+Sets register `number` from its big-endian bytes. The stack depth can't be changed.
+
+# Returns
+Returns whether the register exists and the value fits it, for the pc see `Chip8::is_valid_pc`.
+*/
+fn set_register(chip8: &mut Chip8, number: usize, value: &[u8]) -> bool {
+    match (number, value) {
+        (0..=15, &[byte]) => chip8.set_v(number, byte),
+        (16, &[high, low]) => chip8.set_index(u16::from_be_bytes([high, low])),
+        (17, &[high, low]) => {
+            let pc = u16::from_be_bytes([high, low]);
+            if !Chip8::is_valid_pc(pc) {
+                return false;
+            }
+            chip8.set_pc(pc);
+        },
+        (18, &[depth]) => return depth as usize == chip8.stack().len(),
+        (19, &[byte]) => chip8.delay_timer = byte,
+        (20, &[byte]) => chip8.sound_timer = byte,
+        _ => return false,
+    }
+    true
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Incoming {
+    Packet(String),
+    /// Ctrl-C, sent outside of packets
+    Interrupt,
+}
+
+
+/**
+The packet layer of the protocol: `$data#checksum` framing and `+`/`-` acknowledgements.
+*/
+struct Connection {
+    stream: TcpStream,
+    /// Bytes received that don't make a whole packet yet
+    buffer: Vec<u8>,
+    /// Cleared by `QStartNoAckMode`
+    acks: bool,
+}
+
+impl Connection {
+    /**
+    Reads what the debugger sent, waiting up to `HALTED_POLL` for something if `wait`.
+    */
+    fn receive(&mut self, wait: bool) -> io::Result<Vec<Incoming>> {
+        self.stream.set_read_timeout(Some(HALTED_POLL))?;
+        self.stream.set_nonblocking(!wait)?;
+        let read = self.read();
+        self.stream.set_nonblocking(false)?;
+        read?;
+        // Wait for the rest of a packet that arrived split
+        while self.partial_packet() && self.read()? {}
+
+        let mut incoming = Vec::new();
+        while let Some(&first) = self.buffer.first() {
+            if first == 0x03 {
+                self.buffer.remove(0);
+                incoming.push(Incoming::Interrupt);
+                continue;
+            }
+            if first != b'$' {
+                // Acknowledgements of our packets and anything else between packets
+                self.buffer.remove(0);
+                continue;
+            }
+            let Some(hash) = self.buffer.iter().position(|&byte| byte == b'#') else {
+                break;
+            };
+            if self.buffer.len() < hash + 3 {
+                break;
+            }
+            let packet: Vec<u8> = self.buffer.drain(..hash + 3).collect();
+            let data = &packet[1..hash];
+            let sent = std::str::from_utf8(&packet[hash + 1..]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let valid = sent == Some(checksum(data));
+            if self.acks {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                incoming.push(Incoming::Packet(String::from_utf8_lossy(data).into_owned()));
+            }
+        }
+        Ok(incoming)
+    }
+
+
+    /**
+    Appends what can be read without waiting longer than the stream allows.
+
+    # Returns
+    Returns whether anything was read.
+    */
+    fn read(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "debugger disconnected")),
+            Ok(read) => {
+                self.buffer.extend_from_slice(&chunk[..read]);
+                Ok(true)
+            },
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+
+    /**
+    Whether the buffer ends in a packet missing its end or checksum.
+    */
+    fn partial_packet(&self) -> bool {
+        self.buffer.iter().rposition(|&byte| byte == b'$').is_some_and(|start| {
+            self.buffer[start..].iter().position(|&byte| byte == b'#').is_none_or(|hash| start + hash + 3 > self.buffer.len())
+        })
+    }
+
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+}
+
+
+/**
+What the debugger wants after a frame.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Debugger {
+    Attached,
+    /// It detached or the connection dropped: the ROM runs on without it
+    Detached,
+    /// It asked to kill the program
+    Killed,
+}
+
+
+/**
+A GDB remote serial protocol server, to debug ROMs from GDB or any front-end speaking the
+protocol. It drives the `Runner`, so it works the same in a window and headless. Supports
+reading and writing registers and memory, breakpoints, single-step, continue and
+interrupting with Ctrl-C.
+*/
+pub struct GdbStub {
+    connection: Connection,
+    breakpoints: BTreeSet<u16>,
+    /// Continuing, rather than stopped waiting for the debugger
+    running: bool,
+    /// Just continued, so a breakpoint at `pc` must not stop it straight away
+    resuming: bool,
+}
+
+impl GdbStub {
+    /**
+    Waits for a debugger to connect to `listener`. The ROM starts stopped.
+    */
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            connection: Connection { stream, buffer: Vec::new(), acks: true },
+            breakpoints: BTreeSet::new(),
+            running: false,
+            resuming: false,
+        })
+    }
+
+
+    /**
+    Serves the debugger for a frame: answers its packets and, while it lets the ROM run,
+    runs until the end of the frame or a breakpoint. While stopped, waits a frame's time
+    for the debugger instead.
+    */
+    pub fn frame(&mut self, runner: &mut Runner) -> Debugger {
+        self.serve(runner).unwrap_or(Debugger::Detached)
+    }
+
+
+    fn serve(&mut self, runner: &mut Runner) -> io::Result<Debugger> {
+        for incoming in self.connection.receive(!self.running)? {
+            match incoming {
+                Incoming::Interrupt if self.running => self.stop(SIGINT)?,
+                Incoming::Interrupt => {},
+                Incoming::Packet(packet) => match self.handle(&packet, runner)? {
+                    Debugger::Attached => {},
+                    other => return Ok(other),
+                },
+            }
+        }
+
+        while self.running {
+            if self.breakpoints.contains(&runner.chip8.pc()) && !self.resuming {
+                self.stop(SIGTRAP)?;
+                break;
+            }
+            self.resuming = false;
+            let frame_ended = runner.step();
+            if runner.chip8.fault().is_some() {
+                self.stop(SIGSEGV)?;
+                break;
+            }
+            if frame_ended {
+                break;
+            }
+        }
+        Ok(Debugger::Attached)
+    }
+
+
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.running = false;
+        self.connection.send(&format!("S{:02x}", signal))
+    }
+
+
+    /**
+    Answers a packet. Unsupported packets get the empty reply the protocol asks for.
+    */
+    fn handle(&mut self, packet: &str, runner: &mut Runner) -> io::Result<Debugger> {
+        let chip8 = &mut runner.chip8;
+        let error = || "E01".to_string();
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match command {
+            "?" => format!("S{:02x}", stop_signal(chip8)),
+            "g" => to_hex(&registers(chip8)),
+            "G" => {
+                let bytes = from_hex(arguments).unwrap_or_default();
+                let mut offset = 0;
+                let written = bytes.len() == registers(chip8).len()
+                    && REGISTERS.iter().enumerate().all(|(number, (_, size))| {
+                        offset += size;
+                        set_register(chip8, number, &bytes[offset - size..offset])
+                    });
+                if written { "OK".to_string() } else { error() }
+            },
+            "p" => {
+                let bytes = registers(chip8);
+                number(arguments).filter(|&number| number < REGISTERS.len()).map_or_else(error, |number| {
+                    let offset: usize = REGISTERS[..number].iter().map(|(_, size)| size).sum();
+                    to_hex(&bytes[offset..offset + REGISTERS[number].1])
+                })
+            },
+            "P" => {
+                let written = arguments.split_once('=')
+                    .and_then(|(number_hex, value)| Some((number(number_hex)?, from_hex(value)?)))
+                    .is_some_and(|(number, value)| set_register(chip8, number, &value));
+                if written { "OK".to_string() } else { error() }
+            },
+            "m" => {
+                let range = arguments.split_once(',')
+                    .and_then(|(addr, length)| Some((number(addr)?, number(length)?)))
+                    .filter(|&(addr, _)| addr < MEMORY_SIZE);
+                range.map_or_else(error, |(addr, length)| to_hex(&chip8.memory()[addr..addr.saturating_add(length).min(MEMORY_SIZE)]))
+            },
+            "M" => {
+                let write = arguments.split_once(':')
+                    .and_then(|(range, data)| Some((range.split_once(',')?, from_hex(data)?)))
+                    .and_then(|((addr, length), data)| Some((number(addr)?, number(length)?, data)))
+                    .filter(|(addr, length, data)| addr.checked_add(*length).is_some_and(|end| end <= MEMORY_SIZE) && *length == data.len());
+                match write {
+                    Some((addr, _, data)) => {
+                        for (i, byte) in data.into_iter().enumerate() {
+                            chip8.write_memory(addr + i, byte);
+                        }
+                        "OK".to_string()
+                    },
+                    None => error(),
+                }
+            },
+            "s" | "c" => {
+                if !arguments.is_empty() {
+                    let addr = number(arguments).and_then(|addr| u16::try_from(addr).ok()).filter(|&addr| Chip8::is_valid_pc(addr));
+                    let Some(addr) = addr else {
+                        self.connection.send(&error())?;
+                        return Ok(Debugger::Attached);
+                    };
+                    chip8.set_pc(addr);
+                }
+                if command == "s" {
+                    runner.step();
+                    self.stop(stop_signal(&runner.chip8))?;
+                } else {
+                    self.running = true;
+                    self.resuming = true;
+                }
+                return Ok(Debugger::Attached);
+            },
+            // Software and hardware breakpoints are the same here
+            "Z" | "z" if arguments.starts_with(['0', '1']) => {
+                let addr = arguments.split(',').nth(1).and_then(number).filter(|&addr| addr < MEMORY_SIZE);
+                match addr {
+                    Some(addr) if command == "Z" => { self.breakpoints.insert(addr as u16); "OK".to_string() },
+                    Some(addr) => { self.breakpoints.remove(&(addr as u16)); "OK".to_string() },
+                    None => error(),
+                }
+            },
+            "D" => {
+                self.connection.send("OK")?;
+                return Ok(Debugger::Detached);
+            },
+            "k" => return Ok(Debugger::Killed),
+            "H" => "OK".to_string(),
+            _ if packet.starts_with("qSupported") => "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string(),
+            _ if packet == "QStartNoAckMode" => {
+                self.connection.send("OK")?;
+                self.connection.acks = false;
+                return Ok(Debugger::Attached);
+            },
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let xml = target_xml();
+                let range = packet.rsplit(':').next().and_then(|range| range.split_once(','))
+                    .and_then(|(offset, length)| Some((number(offset)?, number(length)?)));
+                range.map_or_else(error, |(offset, length)| {
+                    let end = offset.saturating_add(length);
+                    let chunk = xml.get(offset.min(xml.len())..end.min(xml.len())).unwrap_or("");
+                    let more = end < xml.len();
+                    format!("{}{}", if more { "m" } else { "l" }, chunk)
+                })
+            },
+            _ if packet == "qAttached" => "1".to_string(),
+            _ if packet == "qC" => "QC1".to_string(),
+            _ if packet == "qfThreadInfo" => "m1".to_string(),
+            _ if packet == "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        };
+        self.connection.send(&reply)?;
+        Ok(Debugger::Attached)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The debugger's side, speaking the protocol by hand
+    struct Client {
+        stream: TcpStream,
+        /// Acknowledges the last reply with the next packet, so the stub reads both at once
+        ack: &'static str,
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) {
+            let packet = format!("{}${}#{:02x}", self.ack, data, checksum(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            self.ack = "";
+        }
+
+        fn reply(&mut self) -> String {
+            let mut received = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' if received.is_empty() => {},
+                    b'#' => break,
+                    b'$' => received.clear(),
+                    other => received.push(other),
+                }
+            }
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            assert_eq!(std::str::from_utf8(&sum).unwrap(), format!("{:02x}", checksum(&received)));
+            self.ack = "+";
+            String::from_utf8(received).unwrap()
+        }
+    }
+
+    fn connect() -> (TcpListener, Client, GdbStub) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let stub = GdbStub::accept(&listener).unwrap();
+        (listener, Client { stream, ack: "" }, stub)
+    }
+
+    #[test]
+    fn test_session() {
+        // 6A05, then 7A01 1202 forever
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x6A, 0x05, 0x7A, 0x01, 0x12, 0x02]).unwrap();
+        let mut runner = Runner::new(chip8);
+        let (_listener, mut client, mut stub) = connect();
+        let mut exchange = |client: &mut Client, packet: &str| {
+            client.send(packet);
+            assert_eq!(stub.frame(&mut runner), Debugger::Attached);
+            client.reply()
+        };
+
+        assert!(exchange(&mut client, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert_eq!(exchange(&mut client, "?"), "S05");
+        assert_eq!(exchange(&mut client, "g"), format!("{}0000020000{}", "00".repeat(16), "0000"));
+        assert!(exchange(&mut client, "qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+        assert!(exchange(&mut client, "qXfer:features:read:target.xml:0,10").starts_with("m<?xml"));
+
+        assert_eq!(exchange(&mut client, "s"), "S05");
+        assert_eq!(exchange(&mut client, "p11"), "0202");
+        assert_eq!(exchange(&mut client, "pa"), "05");
+
+        assert_eq!(exchange(&mut client, "Z0,204,2"), "OK");
+        assert_eq!(exchange(&mut client, "c"), "S05");
+        assert_eq!(exchange(&mut client, "c"), "S05");
+        assert_eq!((exchange(&mut client, "p11"), exchange(&mut client, "pa")), ("0204".to_string(), "07".to_string()));
+        assert_eq!(exchange(&mut client, "z0,204,2"), "OK");
+
+        assert_eq!(exchange(&mut client, "M300,2:abcd"), "OK");
+        assert_eq!(exchange(&mut client, "m2ff,4"), "00abcd00");
+        assert_eq!(exchange(&mut client, "m1000,1"), "E01");
+        assert_eq!(exchange(&mut client, "Mfff,2:abcd"), "E01", "doesn't wrap around to 0x000");
+        assert_eq!(exchange(&mut client, "Mfff,ffffffffffffffff:ab"), "E01");
+        assert_eq!(exchange(&mut client, "m0,1"), "00");
+        assert_eq!(exchange(&mut client, "P10=0300"), "OK");
+        assert_eq!(exchange(&mut client, "p10"), "0300");
+        assert_eq!(exchange(&mut client, "P12=05"), "E01");
+        let dump = exchange(&mut client, "m2ff,ffffffffffffffff");
+        assert_eq!((&dump[..8], dump.len()), ("00abcd00", 2 * (MEMORY_SIZE - 0x2FF)), "read up to the end of memory");
+        for packet in ["P11=0fff", "P11=ffff", "P11=0000", "cffff", "cfff", "s10000", "sx"] {
+            assert_eq!(exchange(&mut client, packet), "E01", "{}", packet);
+        }
+        assert_eq!(exchange(&mut client, "p11"), "0204", "the pc is kept");
+        assert_eq!(exchange(&mut client, "vMustReplyEmpty"), "");
+
+        // Rewriting the ADD, which has already run, changes what the next one does
+        assert_eq!(exchange(&mut client, "M202,2:7a10"), "OK");
+        assert_eq!(exchange(&mut client, "s"), "S05");
+        assert_eq!(exchange(&mut client, "s"), "S05");
+        assert_eq!(exchange(&mut client, "pa"), "17");
+
+        // Running until interrupted
+        client.send("c");
+        assert_eq!(stub.frame(&mut runner), Debugger::Attached);
+        assert!(runner.frames > 0);
+        client.stream.write_all(&[0x03]).unwrap();
+        stub.frame(&mut runner);
+        assert_eq!(client.reply(), "S02");
+
+        // Returning with an empty stack stops the machine, which the debugger sees as a fault
+        for (packet, reply) in [("M206,2:00ee", "OK"), ("P11=0206", "OK"), ("c", "S0b"), ("?", "S0b"), ("p11", "0206")] {
+            client.send(packet);
+            assert_eq!(stub.frame(&mut runner), Debugger::Attached);
+            assert_eq!(client.reply(), reply, "{}", packet);
+        }
+
+        client.send("D");
+        assert_eq!(stub.frame(&mut runner), Debugger::Detached);
+        assert_eq!(client.reply(), "OK");
+    }
+
+    #[test]
+    fn test_kill_and_no_acks() {
+        let mut runner = Runner::new(Chip8::new());
+        let (_listener, mut client, mut stub) = connect();
+
+        client.send("QStartNoAckMode");
+        stub.frame(&mut runner);
+        assert_eq!(client.reply(), "OK");
+        client.send("?");
+        stub.frame(&mut runner);
+        let mut reply = [0; 1];
+        client.stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$");
+
+        client.send("k");
+        assert_eq!(stub.frame(&mut runner), Debugger::Killed);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod benchmark;
+pub mod control_flow;
+pub mod coverage;
+pub mod gdb;
+pub mod profiler;
+pub mod reference;
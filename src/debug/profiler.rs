@@ -0,0 +1,205 @@
+// profiler.rs
+
+use crate::chip8::instruction::Instruction;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem::{discriminant, Discriminant};
+
+
+/// Rows shown per table in `Profiler::report`
+pub const REPORT_ROWS: usize = 20;
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub calls: u64,
+    /// Instructions run inside it, including in the subroutines it called. The `2NNN`
+    /// that calls it is counted in the caller, the `00EE` that returns in it.
+    pub inclusive_cycles: u64,
+}
+
+
+/**
+Counts where a program spends its instructions: per address, per instruction kind and
+per subroutine. Subroutines are followed through the stack: a `2NNN` that grows it enters
+the subroutine at the new PC, a `00EE` that shrinks it goes back to the caller.
+*/
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pub total: u64,
+    pub addresses: HashMap<u16, u64>,
+    /// Keyed by variant, with the last instruction of that kind to name it
+    instructions: HashMap<Discriminant<Instruction>, (Instruction, u64)>,
+    pub subroutines: HashMap<u16, Subroutine>,
+    /// Entry addresses of the subroutines being run, outermost first
+    path: Vec<u16>,
+    /// Instructions run in each call path, excluding callees
+    folded: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+
+    /**
+    Records that `instruction` at `pc` ran, leaving the PC at `next_pc` and `stack_depth`
+    return addresses on the stack.
+    */
+    pub fn record(&mut self, pc: u16, instruction: Instruction, next_pc: u16, stack_depth: usize) {
+        self.total += 1;
+        *self.addresses.entry(pc).or_default() += 1;
+        self.instructions.entry(discriminant(&instruction)).or_insert((instruction, 0)).1 += 1;
+
+        match self.folded.get_mut(self.path.as_slice()) {
+            Some(count) => *count += 1,
+            None => { self.folded.insert(self.path.clone(), 1); },
+        }
+        // A recursive subroutine is only counted once per instruction
+        for (depth, &entry) in self.path.iter().enumerate() {
+            if !self.path[..depth].contains(&entry) {
+                self.subroutines.entry(entry).or_default().inclusive_cycles += 1;
+            }
+        }
+
+        if stack_depth > self.path.len() {
+            self.path.push(next_pc);
+            self.subroutines.entry(next_pc).or_default().calls += 1;
+        }
+        self.path.truncate(stack_depth);
+    }
+
+
+    /**
+    Executions per instruction variant, most frequent first.
+    */
+    pub fn instruction_counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<_> = self.instructions.values()
+            .map(|(instruction, count)| (variant_name(instruction), *count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+
+    /**
+    A plain text report of the hottest addresses, instructions and subroutines. `memory`
+    is used to show the opcode at each address.
+    */
+    pub fn report(&self, memory: &[u8]) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        let mut out = format!("Executed {} instructions\n", self.total);
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        out.push_str("\nHottest addresses\n       count       %  address  opcode\n");
+        for (&pc, &count) in addresses.iter().take(REPORT_ROWS) {
+            let opcode = match memory.get(pc as usize..pc as usize + 2) {
+                Some(bytes) => format!("{:02X}{:02X}", bytes[0], bytes[1]),
+                None => "----".to_string(),
+            };
+            let _ = writeln!(out, "{:>12}  {:>6.2}  {:04X}     {}", count, percent(count), pc, opcode);
+        }
+
+        out.push_str("\nInstructions\n       count       %  instruction\n");
+        for (name, count) in self.instruction_counts() {
+            let _ = writeln!(out, "{:>12}  {:>6.2}  {}", count, percent(count), name);
+        }
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive_cycles.cmp(&a.1.inclusive_cycles).then_with(|| a.0.cmp(b.0)));
+        out.push_str("\nSubroutines\n   inclusive       %       calls  address\n");
+        for (&entry, subroutine) in subroutines.iter().take(REPORT_ROWS) {
+            let _ = writeln!(out, "{:>12}  {:>6.2}  {:>10}  {:04X}",
+                subroutine.inclusive_cycles, percent(subroutine.inclusive_cycles), subroutine.calls, entry);
+        }
+        out
+    }
+
+
+    /**
+    Call paths in the folded stack format read by flamegraph tools (e.g. `inferno` or
+    `flamegraph.pl`), one `main;sub_0234;sub_0300 COUNT` line per path, sorted.
+    */
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<_> = self.folded.iter()
+            .map(|(path, count)| {
+                let frames: Vec<_> = std::iter::once("main".to_string())
+                    .chain(path.iter().map(|entry| format!("sub_{:04X}", entry)))
+                    .collect();
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+
+/**
+`SetI` for `SetI { value: 752 }`.
+*/
+fn variant_name(instruction: &Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    debug.split([' ', '{']).next().unwrap_or_default().to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+    use crate::frontend::runner::Runner;
+
+    // 0200: call 0208 twice, then loop forever
+    // 0208: add 1 to VA, call 020E, return
+    // 020E: return
+    const ROM: [u8; 18] = [
+        0x22, 0x08, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00,
+        0x7A, 0x01, 0x22, 0x0E, 0x00, 0xEE,
+        0x00, 0xEE, 0x00, 0x00,
+    ];
+
+    fn profile(steps: usize) -> Profiler {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        let mut runner = Runner::new(chip8);
+        runner.profiler = Some(Profiler::new());
+        for _ in 0..steps {
+            runner.step();
+        }
+        runner.profiler.unwrap()
+    }
+
+    #[test]
+    fn test_counts() {
+        // 2 x (call, add, call, return, return) + 3 jumps
+        let profiler = profile(13);
+        assert_eq!(profiler.total, 13);
+        assert_eq!(profiler.addresses[&0x204], 3);
+        assert_eq!(profiler.addresses[&0x208], 2);
+
+        let counts = profiler.instruction_counts();
+        assert_eq!(counts[0], ("CallSubroutine".to_string(), 4));
+        assert!(counts.contains(&("ReturnSubroutine".to_string(), 4)));
+        assert!(counts.contains(&("Jump".to_string(), 3)));
+
+        assert_eq!(profiler.subroutines[&0x208], Subroutine { calls: 2, inclusive_cycles: 8 });
+        assert_eq!(profiler.subroutines[&0x20E], Subroutine { calls: 2, inclusive_cycles: 2 });
+    }
+
+    #[test]
+    fn test_folded() {
+        assert_eq!(profile(13).folded(), "main 5\nmain;sub_0208 6\nmain;sub_0208;sub_020E 2\n");
+    }
+
+    #[test]
+    fn test_report() {
+        let report = profile(13).report(&[0; 4096]);
+        assert!(report.starts_with("Executed 13 instructions\n"));
+        assert!(report.contains("           3   23.08  0204     0000\n"), "{}", report);
+        assert!(report.contains("           8   61.54           2  0208\n"), "{}", report);
+    }
+}
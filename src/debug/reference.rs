@@ -0,0 +1,335 @@
+// reference.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::frontend::runner::Runner;
+
+use std::fmt;
+
+
+/**
+The parts of the machine state a reference trace can pin down.
+
+A reference trace is a text file with one line per instruction, holding the machine state
+*before* that instruction runs. The first line is the state right after the ROM is loaded.
+Lines are whitespace-separated `KEY=VALUE` fields in hex, without `0x`:
+
+```text
+# Comments and blank lines are ignored
+PC=0200 I=0000 V=00000000000000000000000000000000 S=
+PC=0202 I=0000 V=00000000000000000000050000000000 S=
+PC=0204 I=02F0 V0=00 V1=00 VA=05 S=0206,0310 DT=00 ST=00
+```
+
+* `PC`, `I`: 16-bit program counter and index register
+* `V`: all 16 registers as 32 hex digits, V0 first, or `V0` to `VF` one by one
+* `S`: the stack's return addresses, outermost first, comma separated; empty if none
+* `DT`, `ST`: delay and sound timers
+* `OP`: the opcode at `PC`, for readers; it is not compared
+
+Fields that are missing from a line aren't compared, so traces from emulators that don't
+expose everything still work. Timers tick once per frame of `cycles_per_frame`
+instructions, the same as a normal run.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub pc: Option<u16>,
+    pub index: Option<u16>,
+    pub v: [Option<u8>; 16],
+    pub stack: Option<Vec<u16>>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+}
+
+impl State {
+    /**
+    The full current state of `chip8`.
+    */
+    pub fn of(chip8: &Chip8) -> Self {
+        State {
+            pc: Some(chip8.pc()),
+            index: Some(chip8.index()),
+            v: chip8.v().map(Some),
+            stack: Some(chip8.stack().to_vec()),
+            delay_timer: Some(chip8.delay_timer),
+            sound_timer: Some(chip8.sound_timer),
+        }
+    }
+
+
+    /**
+    Parses a line of a reference trace.
+
+    # Returns
+    Returns `None` for blank and comment lines.
+    */
+    pub fn parse(line: &str) -> Option<Result<Self, String>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        Some(line.split_whitespace().try_fold(State::default(), |mut state, field| {
+            let (key, value) = field.split_once('=').ok_or(format!("Expected KEY=VALUE, got {}", field))?;
+            let invalid = || format!("Invalid value for {}: {}", key, value);
+            let byte = |value: &str| u8::from_str_radix(value, 16).map_err(|_| invalid());
+            let word = |value: &str| u16::from_str_radix(value, 16).map_err(|_| invalid());
+
+            match key {
+                "PC" => state.pc = Some(word(value)?),
+                "I" => state.index = Some(word(value)?),
+                "DT" => state.delay_timer = Some(byte(value)?),
+                "ST" => state.sound_timer = Some(byte(value)?),
+                "OP" => { word(value)?; },
+                "S" if value.is_empty() => state.stack = Some(Vec::new()),
+                "S" => state.stack = Some(value.split(',').map(word).collect::<Result<_, _>>()?),
+                "V" => {
+                    if value.len() != 32 || !value.is_ascii() {
+                        return Err(invalid());
+                    }
+                    for (register, digits) in state.v.iter_mut().zip(value.as_bytes().chunks(2)) {
+                        *register = Some(byte(std::str::from_utf8(digits).unwrap())?);
+                    }
+                },
+                _ => {
+                    let register = key.strip_prefix('V')
+                        .filter(|digit| digit.len() == 1)
+                        .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                        .ok_or(format!("Unknown field: {}", key))?;
+                    state.v[register] = Some(byte(value)?);
+                },
+            }
+            Ok(state)
+        }))
+    }
+
+
+    /**
+    Whether `actual` agrees with every field set in `self`.
+    */
+    pub fn matches(&self, actual: &State) -> bool {
+        self.rows(actual).iter().all(|row| !row.differs)
+    }
+
+
+    /**
+    One row per field set in `self`, for comparing it with `actual`.
+    */
+    fn rows(&self, actual: &State) -> Vec<Row> {
+        fn row<T: PartialEq>(name: String, expected: &Option<T>, actual: &Option<T>, show: impl Fn(&T) -> String) -> Option<Row> {
+            expected.as_ref().map(|expected| Row {
+                name,
+                expected: show(expected),
+                actual: actual.as_ref().map_or("-".to_string(), &show),
+                differs: actual.as_ref() != Some(expected),
+            })
+        }
+        let word = |value: &u16| format!("{:04X}", value);
+        let byte = |value: &u8| format!("{:02X}", value);
+        let stack = |stack: &Vec<u16>| stack.iter().map(word).collect::<Vec<_>>().join(",");
+
+        let mut rows = vec![
+            row("PC".to_string(), &self.pc, &actual.pc, word),
+            row("I".to_string(), &self.index, &actual.index, word),
+        ];
+        for register in 0..16 {
+            rows.push(row(format!("V{:X}", register), &self.v[register], &actual.v[register], byte));
+        }
+        rows.push(row("S".to_string(), &self.stack, &actual.stack, stack));
+        rows.push(row("DT".to_string(), &self.delay_timer, &actual.delay_timer, byte));
+        rows.push(row("ST".to_string(), &self.sound_timer, &actual.sound_timer, byte));
+        rows.into_iter().flatten().collect()
+    }
+}
+
+impl fmt::Display for State {
+    /**
+    The state as a reference trace line.
+    */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(pc) = self.pc {
+            fields.push(format!("PC={:04X}", pc));
+        }
+        if let Some(index) = self.index {
+            fields.push(format!("I={:04X}", index));
+        }
+        if self.v.iter().all(Option::is_some) {
+            fields.push(format!("V={}", self.v.iter().flatten().map(|v| format!("{:02X}", v)).collect::<String>()));
+        } else {
+            for (register, value) in self.v.iter().enumerate() {
+                if let Some(value) = value {
+                    fields.push(format!("V{:X}={:02X}", register, value));
+                }
+            }
+        }
+        if let Some(stack) = &self.stack {
+            fields.push(format!("S={}", stack.iter().map(|addr| format!("{:04X}", addr)).collect::<Vec<_>>().join(",")));
+        }
+        if let Some(delay_timer) = self.delay_timer {
+            fields.push(format!("DT={:02X}", delay_timer));
+        }
+        if let Some(sound_timer) = self.sound_timer {
+            fields.push(format!("ST={:02X}", sound_timer));
+        }
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+
+struct Row {
+    name: String,
+    expected: String,
+    actual: String,
+    differs: bool,
+}
+
+
+/**
+Where the emulator first disagreed with the reference.
+*/
+#[derive(Debug)]
+pub struct Divergence {
+    /// 1-based line of the reference trace
+    pub line: usize,
+    /// Instructions run before the divergence
+    pub steps: u64,
+    /// Address and opcode of the last instruction run, which produced the wrong state
+    pub previous: Option<(u16, u16)>,
+    pub expected: State,
+    pub actual: State,
+}
+
+impl fmt::Display for Divergence {
+    /**
+    A side-by-side table of the expected and actual state, marking the fields that differ.
+    */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Diverged at reference line {} after {} instructions", self.line, self.steps)?;
+        match self.previous {
+            Some((pc, opcode)) => writeln!(f, ", last one {:04X} at {:04X}", opcode, pc)?,
+            None => writeln!(f, ", in the initial state")?,
+        }
+
+        let rows = self.expected.rows(&self.actual);
+        let width = rows.iter().map(|row| row.expected.len()).max().unwrap_or(0).max("expected".len());
+        writeln!(f, "     {:<width$}  actual", "expected", width = width)?;
+        for row in rows {
+            let marker = if row.differs { "  <--" } else { "" };
+            writeln!(f, "{:<4} {:<width$}  {}{}", row.name, row.expected, row.actual, marker, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+
+/**
+Parses a whole reference trace.
+
+# Returns
+Returns the states with their 1-based line numbers, or the first broken line.
+*/
+pub fn parse_reference(text: &str) -> Result<Vec<(usize, State)>, String> {
+    text.lines().enumerate()
+        .filter_map(|(number, line)| State::parse(line).map(|state| (number + 1, state)))
+        .map(|(number, state)| state.map(|state| (number, state)).map_err(|e| format!("line {}: {}", number, e)))
+        .collect()
+}
+
+
+/**
+Runs `runner` one instruction per reference state, comparing the state before each one.
+
+# Returns
+Returns the number of states that matched, or where the run first diverged.
+*/
+pub fn compare(runner: &mut Runner, reference: &[(usize, State)]) -> Result<usize, Box<Divergence>> {
+    let mut previous = None;
+
+    for (steps, (line, expected)) in reference.iter().enumerate() {
+        if steps > 0 {
+            let pc = runner.chip8.pc();
+            let opcode = Chip8::read_memory_address(*runner.chip8.memory(), pc);
+            runner.step();
+            previous = Some((pc, opcode));
+        }
+
+        let actual = State::of(&runner.chip8);
+        if !expected.matches(&actual) {
+            return Err(Box::new(Divergence {
+                line: *line, steps: steps as u64, previous, expected: expected.clone(), actual,
+            }));
+        }
+    }
+    Ok(reference.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner(rom: &[u8]) -> Runner {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(rom).unwrap();
+        Runner::new(chip8)
+    }
+
+    // 6A05 A2F0 2208 ... 2208: 7A01 00EE
+    const ROM: [u8; 12] = [0x6A, 0x05, 0xA2, 0xF0, 0x22, 0x08, 0x12, 0x06, 0x7A, 0x01, 0x00, 0xEE];
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(State::parse("  # comment"), None);
+        assert_eq!(State::parse(""), None);
+
+        let state = State::parse("PC=0204 OP=2208 I=02F0 VA=05 S=0206,0310 DT=3C").unwrap().unwrap();
+        assert_eq!(state.pc, Some(0x204));
+        assert_eq!(state.index, Some(0x2F0));
+        assert_eq!(state.v[0xA], Some(5));
+        assert_eq!(state.v[0], None);
+        assert_eq!(state.stack, Some(vec![0x206, 0x310]));
+        assert_eq!(state.delay_timer, Some(0x3C));
+        assert_eq!(state.sound_timer, None);
+
+        assert_eq!(State::parse("S=").unwrap().unwrap().stack, Some(vec![]));
+        assert!(State::parse("V=00").unwrap().is_err());
+        assert!(State::parse("VG=00").unwrap().is_err());
+        assert!(State::parse("PC").unwrap().is_err());
+
+        assert_eq!(parse_reference("PC=0200\n\nPC=0202 I=zz").unwrap_err(), "line 3: Invalid value for I: zz");
+
+        let state = State::of(&Chip8::new());
+        assert_eq!(State::parse(&state.to_string()).unwrap().unwrap(), state);
+    }
+
+    #[test]
+    fn test_compare_matching_trace() {
+        let reference = "
+            PC=0200 V=00000000000000000000000000000000 S=
+            PC=0202 VA=05
+            PC=0204 I=02F0
+            # Entering the subroutine
+            PC=0208 S=0206
+            PC=020A VA=06
+            PC=0206 S=
+        ";
+        assert_eq!(compare(&mut runner(&ROM), &parse_reference(reference).unwrap()).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_compare_stops_at_divergence() {
+        let reference = "
+            PC=0200
+            PC=0202 VA=05
+            PC=0204 I=0300 VA=05
+            PC=0208
+        ";
+        let divergence = compare(&mut runner(&ROM), &parse_reference(reference).unwrap()).unwrap_err();
+        assert_eq!(divergence.line, 4);
+        assert_eq!(divergence.steps, 2);
+        assert_eq!(divergence.previous, Some((0x202, 0xA2F0)));
+
+        let report = divergence.to_string();
+        assert!(report.contains("I    0300      02F0  <--"), "{}", report);
+        assert!(report.contains("VA   05        05\n"), "{}", report);
+    }
+}
@@ -0,0 +1,310 @@
+// effects.rs
+
+use crate::chip8::constants::{WIDTH, HEIGHT, SCALE_FACTOR, PADDING};
+use crate::display::framebuffer::{Viewport, BORDER_COLOR};
+use crate::display::palette::{Palette, Rgb, split_rgb};
+
+
+/**
+A CPU post-processing pass applied to the scaled image, after `framebuffer::blit`.
+Effects are applied in the order they are listed, so e.g. `grid,bloom` makes the gaps
+glow while `bloom,grid` keeps them sharp.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Thin gaps between pixels, like the original SDL renderer
+    Grid,
+    /// Darkens the lower part of every CHIP-8 row
+    Scanlines,
+    /// Lit pixels glow onto their neighbours
+    Bloom,
+    /// Pixels drawn as dots instead of squares
+    Rounded,
+    /// Barrel distortion, like a curved CRT tube
+    Curvature,
+}
+
+
+pub const ALL: [Effect; 5] = [
+    Effect::Grid,
+    Effect::Scanlines,
+    Effect::Bloom,
+    Effect::Rounded,
+    Effect::Curvature,
+];
+
+const SCANLINE_DIM: u32 = 55;
+const BLOOM_STRENGTH: u32 = 70;
+const CURVATURE: f32 = 0.06;
+
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Grid => "grid",
+            Effect::Scanlines => "scanlines",
+            Effect::Bloom => "bloom",
+            Effect::Rounded => "rounded",
+            Effect::Curvature => "curvature",
+        }
+    }
+
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL.iter().find(|effect| effect.name() == name).copied()
+    }
+
+
+    /**
+    Parses a comma-separated chain such as `"scanlines,bloom"`. `none` or an empty string
+    means no effects.
+    */
+    pub fn parse_chain(value: &str) -> Result<Vec<Self>, String> {
+        if value.is_empty() || value == "none" {
+            return Ok(Vec::new());
+        }
+        value.split(',')
+            .map(|name| Self::from_name(name.trim()).ok_or(format!(
+                "Unknown effect: {} (available: {})",
+                name, ALL.map(|effect| effect.name()).join(", "))))
+            .collect()
+    }
+
+
+    /**
+    Applies the effect in place on a `width * height` buffer drawn with `viewport`.
+    `scratch` is a reusable buffer of the same size for passes that need a copy.
+    */
+    pub fn apply(&self, buffer: &mut [u32], scratch: &mut Vec<u32>, width: usize, height: usize, viewport: &Viewport, palette: &Palette) {
+        match self {
+            Effect::Grid => grid(buffer, width, viewport, palette),
+            Effect::Scanlines => scanlines(buffer, width, viewport),
+            Effect::Bloom => bloom(buffer, scratch, width, height, viewport, palette),
+            Effect::Rounded => rounded(buffer, width, viewport, palette),
+            Effect::Curvature => curvature(buffer, scratch, width, height),
+        }
+    }
+}
+
+
+/**
+Calls `f` with the position inside its CHIP-8 pixel of every buffer pixel that belongs
+to the screen (not the letterbox).
+*/
+fn for_each_cell_pixel<F: FnMut(&mut u32, usize, usize)>(buffer: &mut [u32], width: usize, viewport: &Viewport, mut f: F) {
+    let screen_width = WIDTH * viewport.scale;
+    let screen_height = HEIGHT * viewport.scale;
+
+    for (y, line) in buffer.chunks_exact_mut(width).enumerate().skip(viewport.offset_y).take(screen_height) {
+        let cell_y = (y - viewport.offset_y) % viewport.scale;
+        let end = (viewport.offset_x + screen_width).min(width);
+        for (x, pixel) in line.iter_mut().enumerate().take(end).skip(viewport.offset_x) {
+            f(pixel, (x - viewport.offset_x) % viewport.scale, cell_y);
+        }
+    }
+}
+
+
+fn scale_color(color: Rgb, percent: u32) -> Rgb {
+    let (r, g, b) = split_rgb(color);
+    let scale = |channel: u8| (channel as u32 * percent / 100).min(255);
+    (scale(r) << 16) | (scale(g) << 8) | scale(b)
+}
+
+
+fn grid(buffer: &mut [u32], width: usize, viewport: &Viewport, palette: &Palette) {
+    // Same proportion as the SDL renderer's PADDING at the default scale
+    let thickness = (viewport.scale * PADDING as usize / SCALE_FACTOR as usize).max(1);
+    if viewport.scale <= thickness {
+        return;
+    }
+    let background = palette.background();
+    for_each_cell_pixel(buffer, width, viewport, |pixel, cell_x, cell_y| {
+        if cell_x < thickness || cell_y < thickness {
+            *pixel = background;
+        }
+    });
+}
+
+
+fn scanlines(buffer: &mut [u32], width: usize, viewport: &Viewport) {
+    if viewport.scale < 2 {
+        return;
+    }
+    let start = viewport.scale - (viewport.scale / 3).max(1);
+    for_each_cell_pixel(buffer, width, viewport, |pixel, _, cell_y| {
+        if cell_y >= start {
+            *pixel = scale_color(*pixel, SCANLINE_DIM);
+        }
+    });
+}
+
+
+fn rounded(buffer: &mut [u32], width: usize, viewport: &Viewport, palette: &Palette) {
+    if viewport.scale < 3 {
+        return;
+    }
+    let center = (viewport.scale as f32 - 1.0) / 2.0;
+    // Slightly larger than the inscribed circle so neighbouring dots touch
+    let radius = viewport.scale as f32 / 2.0 * 1.1;
+    let background = palette.background();
+    for_each_cell_pixel(buffer, width, viewport, |pixel, cell_x, cell_y| {
+        let dx = cell_x as f32 - center;
+        let dy = cell_y as f32 - center;
+        if dx * dx + dy * dy > radius * radius {
+            *pixel = background;
+        }
+    });
+}
+
+
+/**
+Adds a blurred copy of everything brighter than the background. The blur is two box
+passes (horizontal, then vertical) with running sums, so it's linear in the buffer size.
+*/
+fn bloom(buffer: &mut [u32], scratch: &mut Vec<u32>, width: usize, height: usize, viewport: &Viewport, palette: &Palette) {
+    let radius = (viewport.scale / 2).max(1);
+    let (bg_r, bg_g, bg_b) = split_rgb(palette.background());
+
+    // Glow source: per channel excess over the background
+    let mut channels: [Vec<u32>; 3] = Default::default();
+    for channel in channels.iter_mut() {
+        channel.resize(buffer.len(), 0);
+    }
+    for (i, &color) in buffer.iter().enumerate() {
+        let (r, g, b) = split_rgb(color);
+        channels[0][i] = r.saturating_sub(bg_r) as u32;
+        channels[1][i] = g.saturating_sub(bg_g) as u32;
+        channels[2][i] = b.saturating_sub(bg_b) as u32;
+    }
+
+    scratch.resize(buffer.len(), 0);
+    for channel in channels.iter_mut() {
+        box_blur(channel, scratch, width, height, radius, 1);
+        box_blur(channel, scratch, width, height, radius, width);
+    }
+
+    for (i, color) in buffer.iter_mut().enumerate() {
+        let (r, g, b) = split_rgb(*color);
+        let add = |base: u8, glow: u32| (base as u32 + glow * BLOOM_STRENGTH / 100).min(255);
+        *color = (add(r, channels[0][i]) << 16) | (add(g, channels[1][i]) << 8) | add(b, channels[2][i]);
+    }
+}
+
+
+/**
+One box blur pass along rows (`stride == 1`) or columns (`stride == width`).
+*/
+fn box_blur(values: &mut [u32], scratch: &mut [u32], width: usize, height: usize, radius: usize, stride: usize) {
+    let (lines, length, line_stride) = if stride == 1 { (height, width, width) } else { (width, height, 1) };
+    let window = (2 * radius + 1) as u32;
+
+    for line in 0..lines {
+        let base = line * line_stride;
+        let at = |i: usize| base + i * stride;
+
+        let mut sum: u32 = (0..=radius.min(length - 1)).map(|i| values[at(i)]).sum();
+        for i in 0..length {
+            scratch[at(i)] = sum / window;
+            if i + radius + 1 < length {
+                sum += values[at(i + radius + 1)];
+            }
+            if i >= radius {
+                sum -= values[at(i - radius)];
+            }
+        }
+    }
+    values.copy_from_slice(&scratch[..values.len()]);
+}
+
+
+fn curvature(buffer: &mut [u32], scratch: &mut Vec<u32>, width: usize, height: usize) {
+    scratch.clear();
+    scratch.extend_from_slice(buffer);
+
+    let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+    for (y, line) in buffer.chunks_exact_mut(width).enumerate() {
+        let ny = (y as f32 + 0.5) / half_height - 1.0;
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let nx = (x as f32 + 0.5) / half_width - 1.0;
+            let sx = nx * (1.0 + CURVATURE * ny * ny);
+            let sy = ny * (1.0 + CURVATURE * nx * nx);
+
+            *pixel = if sx.abs() > 1.0 || sy.abs() > 1.0 {
+                BORDER_COLOR
+            } else {
+                let source_x = (((sx + 1.0) * half_width) as usize).min(width - 1);
+                let source_y = (((sy + 1.0) * half_height) as usize).min(height - 1);
+                scratch[source_y * width + source_x]
+            };
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::framebuffer::blit;
+    use crate::display::persistence::Frame;
+
+    const BACKGROUND: u32 = 0x10_1010;
+    const FOREGROUND: u32 = 0xF0_F0F0;
+
+    fn render(effect: Effect, frame: &Frame) -> (Vec<u32>, usize, usize) {
+        let palette = Palette::parse("#101010,#F0F0F0").unwrap();
+        let (width, height) = (WIDTH * 10, HEIGHT * 10);
+        let mut buffer = vec![0; width * height];
+        blit(frame, &palette, &mut buffer, width, height);
+        effect.apply(&mut buffer, &mut Vec::new(), width, height, &Viewport::fit(width, height), &palette);
+        (buffer, width, height)
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        assert_eq!(Effect::parse_chain("scanlines,bloom"), Ok(vec![Effect::Scanlines, Effect::Bloom]));
+        assert_eq!(Effect::parse_chain("none"), Ok(Vec::new()));
+        assert!(Effect::parse_chain("scanlines,vhs").is_err());
+    }
+
+    #[test]
+    fn test_grid() {
+        let (buffer, width, _) = render(Effect::Grid, &[[255; WIDTH]; HEIGHT]);
+        assert_eq!(buffer[0], BACKGROUND);
+        assert_eq!(buffer[width + 1], FOREGROUND);
+        assert_eq!(buffer[width + 10], BACKGROUND);
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let (buffer, width, _) = render(Effect::Scanlines, &[[255; WIDTH]; HEIGHT]);
+        assert_eq!(buffer[0], FOREGROUND);
+        assert_eq!(buffer[9 * width], scale_color(FOREGROUND, SCANLINE_DIM));
+    }
+
+    #[test]
+    fn test_rounded() {
+        let (buffer, width, _) = render(Effect::Rounded, &[[255; WIDTH]; HEIGHT]);
+        assert_eq!(buffer[0], BACKGROUND);
+        assert_eq!(buffer[5 * width + 5], FOREGROUND);
+    }
+
+    #[test]
+    fn test_bloom_spreads_light() {
+        let mut frame = [[0; WIDTH]; HEIGHT];
+        frame[10][10] = 255;
+        let (buffer, width, _) = render(Effect::Bloom, &frame);
+
+        // Right next to the lit pixel gets brighter, far away stays background
+        assert!(buffer[105 * width + 99] > BACKGROUND);
+        assert_eq!(buffer[5 * width + 5], BACKGROUND);
+    }
+
+    #[test]
+    fn test_curvature_blacks_out_corners() {
+        let (buffer, width, height) = render(Effect::Curvature, &[[255; WIDTH]; HEIGHT]);
+        assert_eq!(buffer[0], BORDER_COLOR);
+        assert_eq!(buffer[width * height - 1], BORDER_COLOR);
+        assert_eq!(buffer[height / 2 * width + width / 2], FOREGROUND);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::palette::Palette;
+use crate::display::persistence::Frame;
+
+
+pub const BORDER_COLOR: u32 = 0x0000_0000;
+
+
+/**
+Where the CHIP-8 screen goes inside a window of arbitrary size: the largest integer
+scale that fits, centered, with the rest of the window left as letterbox borders.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub scale: usize,
+    pub offset_x: usize,
+    pub offset_y: usize,
+}
+
+impl Viewport {
+    pub fn fit(width: usize, height: usize) -> Self {
+        let scale = (width / WIDTH).min(height / HEIGHT).max(1);
+        Viewport {
+            scale,
+            offset_x: width.saturating_sub(WIDTH * scale) / 2,
+            offset_y: height.saturating_sub(HEIGHT * scale) / 2,
+        }
+    }
+}
+
+
+/**
+Draws `frame` into a `0x00RRGGBB` buffer of `width * height` pixels, scaled and
+letterboxed according to `Viewport::fit`.
+*/
+pub fn blit(frame: &Frame, palette: &Palette, buffer: &mut [u32], width: usize, height: usize) {
+    let viewport = Viewport::fit(width, height);
+    buffer.fill(BORDER_COLOR);
+
+    for (y, line) in buffer.chunks_exact_mut(width).enumerate().take(height) {
+        let Some(row) = y.checked_sub(viewport.offset_y).map(|j| j / viewport.scale) else {
+            continue;
+        };
+        if row >= HEIGHT {
+            break;
+        }
+
+        for (i, &brightness) in frame[row].iter().enumerate() {
+            let start = viewport.offset_x + i * viewport.scale;
+            let end = (start + viewport.scale).min(width);
+            if start >= end {
+                break;
+            }
+            line[start..end].fill(palette.shade(brightness));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewport_fit() {
+        assert_eq!(Viewport::fit(640, 320), Viewport { scale: 10, offset_x: 0, offset_y: 0 });
+        // Wide window: pillarbox
+        assert_eq!(Viewport::fit(1000, 320), Viewport { scale: 10, offset_x: 180, offset_y: 0 });
+        // Not a multiple: keep integer scale and letterbox
+        assert_eq!(Viewport::fit(200, 200), Viewport { scale: 3, offset_x: 4, offset_y: 52 });
+        // Too small: never scale below 1
+        assert_eq!(Viewport::fit(10, 10).scale, 1);
+    }
+
+    const ON_COLOR: u32 = 0xFF_FFFF;
+    const OFF_COLOR: u32 = 0x11_1111;
+
+    fn palette() -> Palette {
+        Palette::parse("#111111,#FFFFFF").unwrap()
+    }
+
+    #[test]
+    fn test_blit() {
+        let mut frame = [[0; WIDTH]; HEIGHT];
+        frame[0][0] = 255;
+        frame[HEIGHT - 1][WIDTH - 1] = 255;
+
+        let (width, height) = (WIDTH * 2 + 2, HEIGHT * 2);
+        let mut buffer = vec![0xFFFF_FFFF; width * height];
+        blit(&frame, &palette(), &mut buffer, width, height);
+
+        assert_eq!(buffer[0], BORDER_COLOR);
+        assert_eq!(buffer[1], ON_COLOR);
+        assert_eq!(buffer[2], ON_COLOR);
+        assert_eq!(buffer[3], OFF_COLOR);
+        assert_eq!(buffer[width + 2], ON_COLOR);
+        assert_eq!(buffer[width * height - 2], ON_COLOR);
+        assert_eq!(buffer[width * height - 1], BORDER_COLOR);
+    }
+
+    #[test]
+    fn test_blit_tiny_window() {
+        let frame = [[255; WIDTH]; HEIGHT];
+        let mut buffer = vec![0; 5 * 3];
+        blit(&frame, &palette(), &mut buffer, 5, 3);
+        assert!(buffer.iter().all(|&pixel| pixel == ON_COLOR));
+    }
+}
@@ -0,0 +1,237 @@
+// inspector.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::palette::Rgb;
+use crate::display::renderer::Renderer;
+use crate::display::text::{fill_rect, Text};
+
+
+const FONT_SIZE: f32 = 13.0;
+/// Characters per line, enough for a row of 8 bytes or a disassembled instruction
+pub const COLUMNS: usize = 30;
+const MARGIN: usize = 8;
+
+const BACKGROUND: Rgb = 0x14_1820;
+const TEXT: Rgb = 0xC8_D0E0;
+const LABEL: Rgb = 0x7F_8CA8;
+const HIGHLIGHT: Rgb = 0xFF_D75F;
+
+/// Bytes per row of the memory views
+const HEX_ROW: usize = 8;
+/// Instructions shown before PC in the disassembly; the rest of the panel is filled after it
+const DISASSEMBLY_BEFORE: usize = 4;
+const DISASSEMBLY_AFTER: usize = 24;
+
+
+/**
+A line of the panel and its colour.
+*/
+pub type Line = (String, Rgb);
+
+
+/**
+A debug side panel showing the machine state while the game runs: registers, timers,
+stack, memory around I and PC, and the disassembly around PC.
+*/
+pub struct Inspector {
+    pub visible: bool,
+    text: Text,
+    lines: Vec<Line>,
+    /// The game, rendered narrower to make room for the panel
+    game: Vec<u32>,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Inspector { visible: false, text: Text::new(FONT_SIZE), lines: Vec::new(), game: Vec::new() }
+    }
+
+
+    /**
+    Width of the panel in pixels.
+    */
+    pub fn width(&self) -> usize {
+        COLUMNS * self.text.advance + 2 * MARGIN
+    }
+
+
+    /**
+    Width the game is rendered at in a window `width` pixels wide.
+    */
+    pub fn game_width(&self, width: usize) -> usize {
+        if self.visible { width.saturating_sub(self.width()).max(1).min(width) } else { width }
+    }
+
+
+    /**
+    Takes a snapshot of `chip8` to show on the next `draw`.
+    */
+    pub fn update(&mut self, chip8: &Chip8) {
+        if self.visible {
+            self.lines = lines(chip8);
+        }
+    }
+
+
+    /**
+    Renders the next frame with `renderer`, leaving room for the panel on the right when
+    it is visible.
+    */
+    pub fn render(&mut self, renderer: &mut Renderer, pixel_array: &[[bool; WIDTH]; HEIGHT], buffer: &mut [u32], width: usize, height: usize) {
+        if !self.visible {
+            renderer.render(pixel_array, buffer, width, height);
+            return;
+        }
+
+        let game_width = self.game_width(width);
+        self.game.resize(game_width * height, 0);
+        renderer.render(pixel_array, &mut self.game, game_width, height);
+        for (row, game_row) in buffer.chunks_mut(width).zip(self.game.chunks(game_width)) {
+            row[..game_width].copy_from_slice(game_row);
+        }
+        self.draw(buffer, width, height, game_width);
+    }
+
+
+    /**
+    Draws the panel over the columns of `buffer` from `x` to the right edge.
+    */
+    pub fn draw(&mut self, buffer: &mut [u32], width: usize, height: usize, x: usize) {
+        fill_rect(buffer, width, height, x, 0, width.saturating_sub(x), height, BACKGROUND, 255);
+
+        let line_height = self.text.line_height;
+        for (i, (line, color)) in self.lines.iter().enumerate() {
+            let y = MARGIN + i * line_height;
+            if y >= height {
+                break;
+            }
+            self.text.draw(buffer, width, height, (x + MARGIN) as i32, y as i32, line, *color);
+        }
+    }
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/**
+The panel contents for the current state of `chip8`.
+*/
+pub fn lines(chip8: &Chip8) -> Vec<Line> {
+    let v = chip8.v();
+    let mut lines = vec![
+        (format!("PC {:04X}  I {:04X}  SP {:X}", chip8.pc(), chip8.index(), chip8.stack().len()), TEXT),
+    ];
+    for (row, values) in v.chunks(4).enumerate() {
+        let registers: Vec<String> = values.iter().enumerate()
+            .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+            .collect();
+        lines.push((registers.join("  "), TEXT));
+    }
+    lines.push((format!("DT {:02X}  ST {:02X}", chip8.delay_timer, chip8.sound_timer), TEXT));
+
+    lines.push(("Stack".to_string(), LABEL));
+    if chip8.stack().is_empty() {
+        lines.push(("-".to_string(), TEXT));
+    }
+    for addresses in chip8.stack().chunks(5) {
+        let addresses: Vec<String> = addresses.iter().map(|addr| format!("{:04X}", addr)).collect();
+        lines.push((addresses.join(" "), TEXT));
+    }
+
+    lines.push(("Memory at I".to_string(), LABEL));
+    lines.extend(hex_rows(chip8.memory(), chip8.index() as usize));
+    lines.push(("Memory at PC".to_string(), LABEL));
+    lines.extend(hex_rows(chip8.memory(), chip8.pc() as usize));
+
+    lines.push(("Disassembly".to_string(), LABEL));
+    lines.extend(disassembly(chip8));
+    lines
+}
+
+
+/**
+Three rows of memory with the one holding `addr` in the middle and highlighted.
+*/
+fn hex_rows(memory: &[u8], addr: usize) -> Vec<Line> {
+    let row_of_addr = addr / HEX_ROW * HEX_ROW;
+    let first = row_of_addr.saturating_sub(HEX_ROW);
+
+    (first..first + 3 * HEX_ROW).step_by(HEX_ROW)
+        .filter(|&start| start + HEX_ROW <= memory.len())
+        .map(|start| {
+            let bytes: Vec<String> = memory[start..start + HEX_ROW].iter().map(|byte| format!("{:02X}", byte)).collect();
+            let color = if start == row_of_addr { HIGHLIGHT } else { TEXT };
+            (format!("{:04X}  {}", start, bytes.join(" ")), color)
+        })
+        .collect()
+}
+
+
+fn disassembly(chip8: &Chip8) -> Vec<Line> {
+    let pc = chip8.pc() as usize;
+    let memory = chip8.memory();
+    let first = pc.saturating_sub(2 * DISASSEMBLY_BEFORE);
+
+    (first..pc + 2 * DISASSEMBLY_AFTER).step_by(2)
+        .filter(|&addr| addr + 1 < memory.len())
+        .map(|addr| {
+            let opcode = Chip8::read_memory_address(*memory, addr as u16);
+            let (marker, color) = if addr == pc { (">", HIGHLIGHT) } else { (" ", TEXT) };
+            (format!("{}{:04X}  {:04X}  {}", marker, addr, opcode, chip8.decode(opcode)), color)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::palette::Palette;
+    use crate::display::persistence::{FilterMode, Persistence};
+
+    #[test]
+    fn test_lines() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x6A, 0x05, 0xA2, 0xF0, 0x22, 0x08]).unwrap();
+        chip8.cycle();
+        chip8.cycle();
+
+        let lines = lines(&chip8);
+        let text: Vec<&str> = lines.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(text[0], "PC 0204  I 02F0  SP 0");
+        assert_eq!(text[3], "V8 00  V9 00  VA 05  VB 00");
+        assert!(text.contains(&"02F0  00 00 00 00 00 00 00 00"));
+        assert!(text.contains(&" 0202  A2F0  LD I, 0x2F0"));
+        assert!(lines.contains(&(">0204  2208  CALL 0x208".to_string(), HIGHLIGHT)));
+        assert!(lines.iter().all(|(line, _)| line.chars().count() <= COLUMNS));
+    }
+
+    #[test]
+    fn test_render_leaves_room_for_panel() {
+        let mut inspector = Inspector::new();
+        let mut renderer = Renderer::new(Palette::default(), Persistence::new(FilterMode::None), Vec::new());
+        let pixel_array = [[false; WIDTH]; HEIGHT];
+        let (width, height) = (640 + inspector.width(), 320);
+        let mut buffer = vec![0; width * height];
+
+        inspector.visible = true;
+        inspector.update(&Chip8::new());
+        inspector.render(&mut renderer, &pixel_array, &mut buffer, width, height);
+        assert_eq!(buffer[0], renderer.palette.background());
+        assert_eq!(buffer[width - 1], BACKGROUND);
+        assert_eq!(buffer[640 + 1], BACKGROUND);
+        assert_eq!(buffer[639], renderer.palette.background());
+    }
+
+    #[test]
+    fn test_hex_rows_at_memory_edges() {
+        let memory = [0; 4096];
+        assert_eq!(hex_rows(&memory, 0)[0].0, "0000  00 00 00 00 00 00 00 00");
+        assert_eq!(hex_rows(&memory, 0xFFF).len(), 2);
+    }
+}
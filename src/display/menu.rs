@@ -0,0 +1,634 @@
+// menu.rs
+
+use crate::cheat::cheats::{Cheat, Code, Target};
+use crate::cheat::search::Comparison;
+use crate::display::palette::Rgb;
+use crate::display::renderer::DisplayCommand;
+use crate::display::text::{fill_rect, Text};
+use crate::library;
+
+use std::path::{Path, PathBuf};
+
+
+const FONT_SIZE: f32 = 16.0;
+const PADDING: usize = 12;
+/// ROMs listed at once; the list scrolls to keep the selection visible
+const VISIBLE_ROMS: usize = 12;
+/// Longer file names are cut in ROM lists
+const NAME_COLUMNS: usize = 24;
+/// RAM search candidates listed, to freeze one
+pub const SHOWN_CANDIDATES: usize = 6;
+
+const DIM: Rgb = 0x00_0000;
+const DIM_ALPHA: u8 = 170;
+const BACKGROUND: Rgb = 0x14_1820;
+const TEXT: Rgb = 0xC8_D0E0;
+const TITLE: Rgb = 0x7F_8CA8;
+const HIGHLIGHT: Rgb = 0xFF_D75F;
+
+/// Speeds offered by the menu, in instructions per frame
+const SPEEDS: [u32; 16] = [1, 2, 3, 5, 7, 9, 11, 15, 20, 30, 50, 100, 200, 500, 1000, 5000];
+
+
+/**
+Menu navigation, independent of the keys each frontend maps to it.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+
+/**
+What the user picked in the menu, for the frontend to carry out.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Close the menu and carry on
+    Resume,
+    Quit,
+    Load(String),
+    Reset,
+    HardReset,
+    SetPlatform(String),
+    SetSpeed(u32),
+    Display(DisplayCommand),
+    NewSearch,
+    FilterSearch(Comparison),
+    AddCheat(Cheat),
+    ToggleCheat(usize),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Resume,
+    LoadRom,
+    Recent,
+    Reset,
+    HardReset,
+    Platform,
+    Speed,
+    Palette,
+    Cheats,
+    Search,
+    Quit,
+}
+
+const ITEMS: [Item; 11] = [
+    Item::Resume, Item::LoadRom, Item::Recent, Item::Reset, Item::HardReset,
+    Item::Platform, Item::Speed, Item::Palette, Item::Cheats, Item::Search, Item::Quit,
+];
+
+/// Steps offered by the RAM search page after "New search", the value to look for being
+/// picked on the page
+const COMPARISONS: [Comparison; 5] = [
+    Comparison::Equal(0), Comparison::Changed, Comparison::Unchanged, Comparison::Increased, Comparison::Decreased,
+];
+
+
+/**
+A page of ROMs to pick one to load.
+*/
+struct List {
+    title: String,
+    /// Labels and paths
+    entries: Vec<(String, String)>,
+    selected: usize,
+}
+
+
+/**
+What the menu shows besides its items.
+*/
+enum Page {
+    Items,
+    Roms(List),
+    /// Toggles the running ROM's cheats
+    Cheats { selected: usize },
+    /// Narrows down a RAM search and freezes what it found. `value` is the one to look for
+    Search { selected: usize, value: u8 },
+}
+
+
+/**
+The runner's RAM search, as the search page shows it.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchView {
+    pub count: usize,
+    /// The first `SHOWN_CANDIDATES` candidates and their value now
+    pub candidates: Vec<(u16, u8)>,
+}
+
+
+/**
+Pause menu of the windowed frontends, to load another ROM, switch platform and adjust
+settings without the command line. Emulation is paused while it is open.
+*/
+pub struct Menu {
+    pub open: bool,
+    /// Instructions per frame, kept in sync with the runner by the frontend
+    pub speed: u32,
+    /// Name of the current palette, kept in sync with the renderer by the frontend
+    pub palette: String,
+    /// The running ROM's cheats, kept in sync with the runner by the frontend
+    pub cheats: Vec<Cheat>,
+    /// Kept in sync with the runner by the frontend, `None` until a search starts
+    pub search: Option<SearchView>,
+    text: Text,
+    selected: usize,
+    /// Platform ids and names
+    platforms: Vec<(String, String)>,
+    platform: Option<usize>,
+    /// The running ROM, whose directory "Load ROM" lists
+    rom_path: PathBuf,
+    /// Most recent first
+    recent: Vec<String>,
+    page: Page,
+}
+
+impl Menu {
+    /**
+    `platforms` are the ids and names of the platforms to choose from.
+    */
+    pub fn new(platforms: Vec<(String, String)>) -> Self {
+        Menu {
+            open: false,
+            speed: 0,
+            palette: String::new(),
+            cheats: Vec::new(),
+            search: None,
+            text: Text::new(FONT_SIZE),
+            selected: 0,
+            platforms,
+            platform: None,
+            rom_path: PathBuf::from("."),
+            recent: Vec::new(),
+            page: Page::Items,
+        }
+    }
+
+
+    /**
+    Tells the menu which ROM is running, on which platform id, and the ROMs run last.
+    */
+    pub fn loaded(&mut self, rom_path: &str, platform: Option<&str>, recent: &[String]) {
+        self.rom_path = PathBuf::from(rom_path);
+        self.platform = platform.and_then(|id| self.platforms.iter().position(|(platform, _)| platform == id));
+        self.recent = recent.to_vec();
+    }
+
+
+    /**
+    Opens the menu on its first item.
+    */
+    pub fn show(&mut self) {
+        self.open = true;
+        self.selected = 0;
+        self.page = Page::Items;
+    }
+
+
+    /**
+    Handles a key press while the menu is open.
+    */
+    pub fn input(&mut self, key: MenuKey) -> Option<MenuAction> {
+        match &mut self.page {
+            Page::Items => {},
+            Page::Roms(list) => return match key {
+                MenuKey::Up => { list.selected = list.selected.saturating_sub(1); None },
+                MenuKey::Down => { list.selected = (list.selected + 1).min(list.entries.len().saturating_sub(1)); None },
+                MenuKey::Back | MenuKey::Left => { self.page = Page::Items; None },
+                MenuKey::Select | MenuKey::Right => {
+                    let (_, path) = list.entries.get(list.selected)?.clone();
+                    self.open = false;
+                    self.page = Page::Items;
+                    Some(MenuAction::Load(path))
+                },
+            },
+            Page::Cheats { selected } => return match key {
+                MenuKey::Up => { *selected = selected.saturating_sub(1); None },
+                MenuKey::Down => { *selected = (*selected + 1).min(self.cheats.len().saturating_sub(1)); None },
+                MenuKey::Back | MenuKey::Left => { self.page = Page::Items; None },
+                MenuKey::Select | MenuKey::Right => (*selected < self.cheats.len()).then_some(MenuAction::ToggleCheat(*selected)),
+            },
+            Page::Search { selected, value } => {
+                let candidates = self.search.as_ref().map_or(&[][..], |search| &search.candidates[..]);
+                let rows = 1 + COMPARISONS.len() + candidates.len();
+                let equal = *selected == 1;
+                return match key {
+                    MenuKey::Up => { *selected = selected.saturating_sub(1); None },
+                    MenuKey::Down => { *selected = (*selected + 1).min(rows - 1); None },
+                    MenuKey::Left if equal => { *value = value.wrapping_sub(1); None },
+                    MenuKey::Right if equal => { *value = value.wrapping_add(1); None },
+                    MenuKey::Left | MenuKey::Back => { self.page = Page::Items; None },
+                    MenuKey::Right => None,
+                    MenuKey::Select => Some(match *selected {
+                        0 => MenuAction::NewSearch,
+                        1 => MenuAction::FilterSearch(Comparison::Equal(*value)),
+                        row if row <= COMPARISONS.len() => MenuAction::FilterSearch(COMPARISONS[row - 1]),
+                        row => {
+                            let (addr, value) = candidates[row - 1 - COMPARISONS.len()];
+                            let code = Code { target: Target::Memory(addr), value };
+                            MenuAction::AddCheat(Cheat { name: format!("{:03X}", addr), code, enabled: true })
+                        },
+                    }),
+                };
+            },
+        }
+
+        let item = ITEMS[self.selected];
+        match key {
+            MenuKey::Up => self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len(),
+            MenuKey::Down => self.selected = (self.selected + 1) % ITEMS.len(),
+            MenuKey::Back => {
+                self.open = false;
+                return Some(MenuAction::Resume);
+            },
+            MenuKey::Left => return self.change(item, false),
+            MenuKey::Right => return self.change(item, true),
+            MenuKey::Select => return match item {
+                Item::Resume => {
+                    self.open = false;
+                    Some(MenuAction::Resume)
+                },
+                Item::LoadRom => {
+                    self.page = Page::Roms(self.browser());
+                    None
+                },
+                Item::Recent => {
+                    self.page = Page::Roms(self.recent_list());
+                    None
+                },
+                Item::Cheats => {
+                    self.page = Page::Cheats { selected: 0 };
+                    None
+                },
+                Item::Search => {
+                    self.page = Page::Search { selected: 0, value: 0 };
+                    None
+                },
+                Item::Reset => {
+                    self.open = false;
+                    Some(MenuAction::Reset)
+                },
+                Item::HardReset => {
+                    self.open = false;
+                    Some(MenuAction::HardReset)
+                },
+                Item::Quit => Some(MenuAction::Quit),
+                _ => self.change(item, true),
+            },
+        }
+        None
+    }
+
+
+    fn rom_directory(&self) -> &Path {
+        match self.rom_path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        }
+    }
+
+
+    /**
+    The ROMs next to the running one, with their size and guessed platform.
+    */
+    fn browser(&self) -> List {
+        let entries = library::scan(self.rom_directory()).into_iter()
+            .map(|rom| {
+                let label = format!("{:<width$} {:>6} B  {}", cut(&rom.name, NAME_COLUMNS), rom.size, rom.platform, width = NAME_COLUMNS);
+                (label, rom.path.to_string_lossy().into_owned())
+            })
+            .collect();
+        List { title: format!("Load ROM from {}", self.rom_directory().display()), entries, selected: 0 }
+    }
+
+
+    fn recent_list(&self) -> List {
+        let entries = self.recent.iter()
+            .map(|path| {
+                let path_buf = Path::new(path);
+                let name = path_buf.file_name().map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+                let directory = path_buf.parent().map(|directory| directory.display().to_string()).unwrap_or_default();
+                (format!("{:<width$} {}", cut(&name, NAME_COLUMNS), directory, width = NAME_COLUMNS), path.clone())
+            })
+            .collect();
+        List { title: "Recent ROMs".to_string(), entries, selected: 0 }
+    }
+
+
+    /**
+    Steps the setting of `item` forwards or backwards.
+    */
+    fn change(&mut self, item: Item, forwards: bool) -> Option<MenuAction> {
+        match item {
+            Item::Platform if !self.platforms.is_empty() => {
+                let count = self.platforms.len();
+                let next = match (self.platform, forwards) {
+                    (Some(i), true) => (i + 1) % count,
+                    (Some(i), false) => (i + count - 1) % count,
+                    (None, _) => 0,
+                };
+                self.platform = Some(next);
+                Some(MenuAction::SetPlatform(self.platforms[next].0.clone()))
+            },
+            Item::Speed => {
+                let speed = if forwards {
+                    SPEEDS.iter().find(|&&speed| speed > self.speed).copied().unwrap_or(SPEEDS[SPEEDS.len() - 1])
+                } else {
+                    SPEEDS.iter().rev().find(|&&speed| speed < self.speed).copied().unwrap_or(SPEEDS[0])
+                };
+                (speed != self.speed).then_some(MenuAction::SetSpeed(speed))
+            },
+            Item::Palette => Some(MenuAction::Display(DisplayCommand::NextPalette)),
+            _ => None,
+        }
+    }
+
+
+    /**
+    The menu lines and which one is selected.
+    */
+    fn lines(&self) -> (String, Vec<String>, usize) {
+        match &self.page {
+            Page::Items => {},
+            Page::Roms(list) => {
+                if list.entries.is_empty() {
+                    return (list.title.clone(), vec!["No ROMs found".to_string()], usize::MAX);
+                }
+                let first = list.selected.saturating_sub(VISIBLE_ROMS - 1);
+                let labels = list.entries.iter().skip(first).take(VISIBLE_ROMS).map(|(label, _)| label.clone()).collect();
+                return (list.title.clone(), labels, list.selected - first);
+            },
+            Page::Cheats { selected } => {
+                if self.cheats.is_empty() {
+                    return ("Cheats".to_string(), vec!["None yet, freeze one from RAM search".to_string()], usize::MAX);
+                }
+                let lines = self.cheats.iter().map(|cheat| {
+                    let check = if cheat.enabled { "x" } else { " " };
+                    format!("[{}] {:<width$} {}", check, cut(&cheat.name, NAME_COLUMNS), cheat.code, width = NAME_COLUMNS)
+                }).collect();
+                return ("Cheats".to_string(), lines, *selected);
+            },
+            Page::Search { selected, value } => {
+                let title = match &self.search {
+                    Some(search) => format!("RAM search: {} candidates", search.count),
+                    None => "RAM search".to_string(),
+                };
+                let comparisons = COMPARISONS.iter().map(|comparison| match comparison {
+                    Comparison::Equal(_) => format!("Equal to < {:02X} >", value),
+                    comparison => capitalize(&comparison.to_string()),
+                });
+                let candidates = self.search.iter().flat_map(|search| &search.candidates)
+                    .map(|(addr, value)| format!("Freeze {:03X} = {:02X}", addr, value));
+                let lines = std::iter::once("New search".to_string()).chain(comparisons).chain(candidates).collect();
+                return (title, lines, *selected);
+            },
+        }
+
+        let platform = match self.platform {
+            Some(i) => self.platforms[i].1.clone(),
+            None => "auto".to_string(),
+        };
+        let lines = ITEMS.iter().map(|item| match item {
+            Item::Resume => "Resume".to_string(),
+            Item::LoadRom => "Load ROM...".to_string(),
+            Item::Recent => "Recent ROMs...".to_string(),
+            Item::Reset => "Reset".to_string(),
+            Item::HardReset => "Reload from disk".to_string(),
+            Item::Platform => format!("Platform  < {} >", platform),
+            Item::Speed => format!("Speed     < {} >", self.speed),
+            Item::Palette => format!("Palette   < {} >", self.palette),
+            Item::Cheats => format!("Cheats... ({} on)", self.cheats.iter().filter(|cheat| cheat.enabled).count()),
+            Item::Search => "RAM search...".to_string(),
+            Item::Quit => "Quit".to_string(),
+        }).collect();
+        ("Paused".to_string(), lines, self.selected)
+    }
+
+
+    /**
+    Dims the game and draws the menu centered over it.
+    */
+    pub fn draw(&mut self, buffer: &mut [u32], width: usize, height: usize) {
+        fill_rect(buffer, width, height, 0, 0, width, height, DIM, DIM_ALPHA);
+
+        let (title, lines, selected) = self.lines();
+        let line_height = self.text.line_height;
+        let text_width = lines.iter().chain(std::iter::once(&title))
+            .map(|line| self.text.width(line) + 2 * self.text.advance)
+            .max()
+            .unwrap_or(0);
+        let box_width = (text_width + 2 * PADDING).min(width);
+        let box_height = ((lines.len() + 2) * line_height + 2 * PADDING).min(height);
+        let x = (width - box_width) / 2;
+        let y = (height - box_height) / 2;
+        fill_rect(buffer, width, height, x, y, box_width, box_height, BACKGROUND, 255);
+
+        let left = (x + PADDING) as i32;
+        self.text.draw(buffer, width, height, left, (y + PADDING) as i32, &title, TITLE);
+        for (i, line) in lines.iter().enumerate() {
+            let line_y = (y + PADDING + (i + 2) * line_height) as i32;
+            let (line, color) = if i == selected { (format!("> {}", line), HIGHLIGHT) } else { (format!("  {}", line), TEXT) };
+            self.text.draw(buffer, width, height, left, line_y, &line, color);
+        }
+    }
+}
+
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+}
+
+
+/**
+`text` cut to `columns` characters, ending in `~` when it was longer.
+*/
+fn cut(text: &str, columns: usize) -> String {
+    if text.chars().count() <= columns {
+        return text.to_string();
+    }
+    text.chars().take(columns - 1).chain(std::iter::once('~')).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu() -> Menu {
+        let platforms = vec![
+            ("originalChip8".to_string(), "Cosmac VIP".to_string()),
+            ("superchip".to_string(), "SUPER-CHIP".to_string()),
+        ];
+        let mut menu = Menu::new(platforms);
+        menu.loaded("roms/pong.ch8", Some("superchip"), &["/roms/pong.ch8".to_string(), "/roms/a-long-game-name-for-a-rom.ch8".to_string()]);
+        menu.speed = 11;
+        menu.show();
+        menu
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut menu = menu();
+        assert_eq!(menu.input(MenuKey::Up), None);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::Quit));
+        assert_eq!(menu.input(MenuKey::Down), None);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::Resume));
+        assert!(!menu.open);
+
+        let mut menu = self::menu();
+        assert_eq!(menu.input(MenuKey::Back), Some(MenuAction::Resume));
+        assert!(!menu.open);
+
+        menu.show();
+        for _ in 0..3 {
+            menu.input(MenuKey::Down);
+        }
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::Reset));
+        assert!(!menu.open);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut menu = menu();
+        for _ in 0..5 {
+            menu.input(MenuKey::Down);
+        }
+        assert_eq!(menu.input(MenuKey::Right), Some(MenuAction::SetPlatform("originalChip8".to_string())));
+        assert_eq!(menu.input(MenuKey::Left), Some(MenuAction::SetPlatform("superchip".to_string())));
+        assert!(menu.lines().1[5].contains("SUPER-CHIP"));
+
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Right), Some(MenuAction::SetSpeed(15)));
+        assert_eq!(menu.input(MenuKey::Left), Some(MenuAction::SetSpeed(9)));
+        menu.speed = 1;
+        assert_eq!(menu.input(MenuKey::Left), None);
+
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::Display(DisplayCommand::NextPalette)));
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let directory = std::env::temp_dir().join(format!("octorust-menu-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for name in ["b.ch8", "a.SC8", "notes.txt"] {
+            std::fs::write(directory.join(name), [0x00, 0xE0]).unwrap();
+        }
+
+        let mut menu = Menu::new(Vec::new());
+        menu.loaded(directory.join("b.ch8").to_str().unwrap(), None, &[]);
+        menu.show();
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Select), None);
+        assert_eq!(menu.lines().1, [
+            "a.SC8                         2 B  CHIP-8",
+            "b.ch8                         2 B  CHIP-8",
+        ]);
+        menu.input(MenuKey::Down);
+        let action = menu.input(MenuKey::Select);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(action, Some(MenuAction::Load(directory.join("b.ch8").to_string_lossy().into_owned())));
+        assert!(!menu.open);
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut menu = menu();
+        menu.input(MenuKey::Down);
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Select), None);
+        assert_eq!(menu.lines().1, [
+            "pong.ch8                 /roms",
+            "a-long-game-name-for-a-~ /roms",
+        ]);
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::Load("/roms/a-long-game-name-for-a-rom.ch8".to_string())));
+
+        // Back to the items
+        menu.show();
+        menu.input(MenuKey::Down);
+        menu.input(MenuKey::Down);
+        menu.input(MenuKey::Select);
+        menu.input(MenuKey::Back);
+        assert_eq!(menu.lines().0, "Paused");
+        assert!(menu.open);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut menu = menu();
+        menu.cheats = vec![Cheat { name: "Infinite lives".to_string(), code: Code::parse("3F0=03").unwrap(), enabled: true }];
+        for _ in 0..8 {
+            menu.input(MenuKey::Down);
+        }
+        assert_eq!(menu.lines().1[8], "Cheats... (1 on)");
+        assert_eq!(menu.input(MenuKey::Select), None);
+        assert_eq!(menu.lines(), ("Cheats".to_string(), vec!["[x] Infinite lives           3F0=03".to_string()], 0));
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::ToggleCheat(0)));
+        assert!(menu.open);
+
+        menu.cheats.clear();
+        assert_eq!(menu.input(MenuKey::Select), None);
+        menu.input(MenuKey::Back);
+        assert_eq!(menu.lines().0, "Paused");
+    }
+
+    #[test]
+    fn test_search() {
+        let mut menu = menu();
+        for _ in 0..9 {
+            menu.input(MenuKey::Down);
+        }
+        menu.input(MenuKey::Select);
+        assert_eq!(menu.lines().0, "RAM search");
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::NewSearch));
+
+        menu.input(MenuKey::Down);
+        menu.input(MenuKey::Left);
+        assert_eq!(menu.lines().1[1], "Equal to < FF >");
+        menu.input(MenuKey::Right);
+        menu.input(MenuKey::Right);
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::FilterSearch(Comparison::Equal(1))));
+        menu.input(MenuKey::Down);
+        assert_eq!(menu.lines().1[2], "Changed");
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::FilterSearch(Comparison::Changed)));
+
+        menu.search = Some(SearchView { count: 2, candidates: vec![(0x3F0, 3), (0x3F4, 9)] });
+        assert_eq!(menu.lines().0, "RAM search: 2 candidates");
+        for _ in 0..10 {
+            menu.input(MenuKey::Down);
+        }
+        assert_eq!(menu.lines().1[7], "Freeze 3F4 = 09");
+        let code = Code::parse("3F4=09").unwrap();
+        assert_eq!(menu.input(MenuKey::Select), Some(MenuAction::AddCheat(Cheat { name: "3F4".to_string(), code, enabled: true })));
+        assert_eq!(menu.input(MenuKey::Left), None);
+        assert_eq!(menu.lines().0, "Paused");
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut menu = menu();
+        let (width, height) = (640, 320);
+        let mut buffer = vec![0xFF_FFFF; width * height];
+        menu.draw(&mut buffer, width, height);
+        assert_ne!(buffer[0], 0xFF_FFFF);
+        assert!(buffer.contains(&BACKGROUND));
+
+        // Too small for the menu, but doesn't panic
+        let mut buffer = vec![0; 16 * 8];
+        menu.draw(&mut buffer, 16, 8);
+    }
+}
@@ -0,0 +1,10 @@
+pub mod palette;
+pub mod persistence;
+pub mod framebuffer;
+pub mod effects;
+pub mod renderer;
+pub mod text;
+pub mod inspector;
+pub mod osd;
+pub mod menu;
+pub mod overlay;
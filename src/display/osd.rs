@@ -0,0 +1,172 @@
+// osd.rs
+
+use crate::display::palette::Rgb;
+use crate::display::text::{fill_rect, Text};
+
+use std::time::{Duration, Instant};
+
+
+const FONT_SIZE: f32 = 14.0;
+const MARGIN: usize = 6;
+/// Space between the text and the edge of its backing box
+const PADDING: usize = 3;
+
+const BACKGROUND: Rgb = 0x00_0000;
+const BACKGROUND_ALPHA: u8 = 160;
+const TEXT: Rgb = 0xF0_F0F0;
+
+/// How long a message stays on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+/// Older messages are dropped when more than this are showing
+const MAX_MESSAGES: usize = 4;
+/// How often the FPS/IPS counter is refreshed
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/**
+On-screen display drawn over the game: transient status messages in the bottom left
+corner (palette changed, speed changed, ROM loaded...) and an optional frames and
+instructions per second counter in the top left one.
+*/
+pub struct Osd {
+    pub stats_visible: bool,
+    text: Text,
+    /// Messages and when they expire, oldest first
+    messages: Vec<(String, Instant)>,
+    /// The FPS/IPS counter as shown, empty until the first interval has passed
+    stats: String,
+    /// Start of the current counting interval, with the frame and instruction counts then
+    interval: Option<(Instant, u64, u64)>,
+    frames: u64,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd {
+            stats_visible: false,
+            text: Text::new(FONT_SIZE),
+            messages: Vec::new(),
+            stats: String::new(),
+            interval: None,
+            frames: 0,
+        }
+    }
+
+
+    /**
+    Shows `message` for a couple of seconds from `now`.
+    */
+    pub fn show(&mut self, message: &str, now: Instant) {
+        self.messages.push((message.to_string(), now + MESSAGE_DURATION));
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+
+    /**
+    Counts a presented frame, given the total instructions run so far. The counter is
+    refreshed once per `STATS_INTERVAL`.
+    */
+    pub fn count_frame(&mut self, instructions: u64, now: Instant) {
+        self.frames += 1;
+        let Some((start, frames, start_instructions)) = self.interval else {
+            self.interval = Some((now, self.frames, instructions));
+            return;
+        };
+
+        let elapsed = now.duration_since(start);
+        if elapsed >= STATS_INTERVAL {
+            let seconds = elapsed.as_secs_f64();
+            let fps = (self.frames - frames) as f64 / seconds;
+            let ips = instructions.saturating_sub(start_instructions) as f64 / seconds;
+            self.stats = format!("{:.0} FPS  {:.0} IPS", fps, ips);
+            self.interval = Some((now, self.frames, instructions));
+        }
+    }
+
+
+    /**
+    Draws the counter and the messages that haven't expired at `now`.
+    */
+    pub fn draw(&mut self, buffer: &mut [u32], width: usize, height: usize, now: Instant) {
+        self.messages.retain(|(_, expiry)| *expiry > now);
+
+        if self.stats_visible && !self.stats.is_empty() {
+            let stats = self.stats.clone();
+            self.draw_line(buffer, width, height, MARGIN, &stats);
+        }
+
+        let line_height = self.text.line_height + 2 * PADDING;
+        let messages: Vec<String> = self.messages.iter().map(|(message, _)| message.clone()).collect();
+        for (i, message) in messages.iter().rev().enumerate() {
+            let Some(y) = height.checked_sub(MARGIN + (i + 1) * line_height) else {
+                break;
+            };
+            self.draw_line(buffer, width, height, y, message);
+        }
+    }
+
+
+    fn draw_line(&mut self, buffer: &mut [u32], width: usize, height: usize, y: usize, line: &str) {
+        let box_width = self.text.width(line) + 2 * PADDING;
+        let box_height = self.text.line_height + 2 * PADDING;
+        fill_rect(buffer, width, height, MARGIN, y, box_width, box_height, BACKGROUND, BACKGROUND_ALPHA);
+        self.text.draw(buffer, width, height, (MARGIN + PADDING) as i32, (y + PADDING) as i32, line, TEXT);
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_expire() {
+        let mut osd = Osd::new();
+        let (width, height) = (320, 160);
+        let mut buffer = vec![0xFF_FFFF; width * height];
+        let start = Instant::now();
+
+        osd.show("Palette: amber", start);
+        osd.draw(&mut buffer, width, height, start);
+        // The backing box darkens the bottom left corner
+        assert_ne!(buffer[(height - MARGIN - 1) * width + MARGIN], 0xFF_FFFF);
+        assert_eq!(buffer[MARGIN * width + MARGIN], 0xFF_FFFF);
+
+        let mut buffer = vec![0xFF_FFFF; width * height];
+        osd.draw(&mut buffer, width, height, start + MESSAGE_DURATION);
+        assert!(buffer.iter().all(|&pixel| pixel == 0xFF_FFFF));
+    }
+
+    #[test]
+    fn test_message_limit() {
+        let mut osd = Osd::new();
+        let now = Instant::now();
+        for i in 0..MAX_MESSAGES + 2 {
+            osd.show(&i.to_string(), now);
+        }
+        assert_eq!(osd.messages.len(), MAX_MESSAGES);
+        assert_eq!(osd.messages[0].0, "2");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut osd = Osd::new();
+        let start = Instant::now();
+        osd.count_frame(0, start);
+        for frame in 1..60 {
+            osd.count_frame(frame * 11, start + Duration::from_secs(1) * frame as u32 / 120);
+        }
+        assert_eq!(osd.stats, "");
+
+        osd.count_frame(60 * 11, start + Duration::from_secs(2));
+        assert_eq!(osd.stats, "30 FPS  330 IPS");
+    }
+}
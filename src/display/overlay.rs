@@ -0,0 +1,79 @@
+// overlay.rs
+
+use crate::display::framebuffer::Viewport;
+use crate::display::palette::Rgb;
+use crate::display::text::{fill_rect, Text};
+
+
+/// How opaque rectangles are, so the game shows through them
+const RECT_ALPHA: u8 = 128;
+
+
+/// Only scripts draw shapes for now
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Text { x: i32, y: i32, text: String, color: Rgb },
+    Rect { x: i32, y: i32, width: i32, height: i32, color: Rgb },
+}
+
+
+/**
+Shapes drawn over the game, e.g. by a script marking hitboxes or showing a timer. They
+are placed in CHIP-8 pixels, so they follow the game as the window is resized.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overlay {
+    pub shapes: Vec<Shape>,
+}
+
+impl Overlay {
+    /**
+    Draws the shapes over a game shown in the `width` leftmost columns of `buffer`, which
+    is `stride` pixels wide.
+    */
+    pub fn draw(&self, buffer: &mut [u32], stride: usize, width: usize, height: usize, text: &mut Text) {
+        let viewport = Viewport::fit(width, height);
+        let place = |x: i32, y: i32| {
+            let scale = viewport.scale as i32;
+            (viewport.offset_x as i32 + x * scale, viewport.offset_y as i32 + y * scale)
+        };
+        for shape in &self.shapes {
+            match shape {
+                Shape::Text { x, y, text: line, color } => {
+                    let (x, y) = place(*x, *y);
+                    text.draw(buffer, stride, height, x, y, line, *color);
+                },
+                Shape::Rect { x, y, width: w, height: h, color } => {
+                    let (left, top) = place(*x, *y);
+                    let (right, bottom) = place(x + w, y + h);
+                    let clamp = |value: i32, max: usize| value.clamp(0, max as i32) as usize;
+                    let (left, right) = (clamp(left, width), clamp(right, width));
+                    let (top, bottom) = (clamp(top, height), clamp(bottom, height));
+                    fill_rect(buffer, stride, height, left, top, right - left, bottom.saturating_sub(top), *color, RECT_ALPHA);
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::constants::{WIDTH, HEIGHT};
+
+    #[test]
+    fn test_draw() {
+        let (stride, width, height) = (WIDTH * 4 + 10, WIDTH * 4, HEIGHT * 4);
+        let mut buffer = vec![0; stride * height];
+        let mut overlay = Overlay::default();
+        overlay.shapes.push(Shape::Rect { x: 2, y: 1, width: 3, height: 100, color: 0xFF_FFFF });
+        overlay.draw(&mut buffer, stride, width, height, &mut Text::new(8.0));
+
+        let lit: Vec<usize> = (0..stride).filter(|&x| buffer[4 * stride + x] != 0).collect();
+        assert_eq!(lit, (8..20).collect::<Vec<_>>());
+        assert_eq!(buffer[3 * stride + 8], 0);
+        assert_ne!(buffer[(height - 1) * stride + 8], 0, "clipped to the screen");
+    }
+}
@@ -0,0 +1,201 @@
+// palette.rs
+
+/**
+A `0x00RRGGBB` colour, the format used by softbuffer and easy to split for SDL.
+*/
+pub type Rgb = u32;
+
+
+/**
+Four colours indexed by the value of a pixel across the two XO-CHIP bit planes:
+
+* 0: background, no plane set
+* 1: plane 1 only (the only foreground colour in plain CHIP-8)
+* 2: plane 2 only
+* 3: both planes
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+
+pub const BUILT_IN: [(&str, [Rgb; 4]); 7] = [
+    ("octorust", [0x37_4A75, 0xCB_D8F5, 0x86_9AC8, 0x1E_2A47]),
+    ("classic", [0x00_0000, 0xFF_FFFF, 0xAA_AAAA, 0x55_5555]),
+    ("amber", [0x1A_0F00, 0xFF_B000, 0xB3_6B00, 0x66_3D00]),
+    ("green-phosphor", [0x0A_1A0A, 0x33_FF66, 0x1F_A040, 0x10_5020]),
+    ("lcd", [0x9B_BC0F, 0x0F_380F, 0x30_6230, 0x8B_AC0F]),
+    ("high-contrast", [0x00_0000, 0xFF_FF00, 0x00_FFFF, 0xFF_FFFF]),
+    ("octo", [0x99_6600, 0xFF_CC00, 0xFF_6600, 0x66_2200]),
+];
+
+
+impl Palette {
+    pub fn built_in(name: &str) -> Option<Self> {
+        BUILT_IN.iter()
+            .find(|(built_in_name, _)| *built_in_name == name)
+            .map(|(name, colors)| Palette { name: name.to_string(), colors: *colors })
+    }
+
+
+    /**
+    Parses either the name of a built-in palette or a custom one written as 2 to 4
+    comma-separated hex colours, e.g. `"#000000,#FFFFFF"`. Missing plane colours repeat
+    the foreground.
+    */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if let Some(palette) = Self::built_in(value) {
+            return Ok(palette);
+        }
+        if !value.contains(',') {
+            return Err(format!("Unknown palette: {} (available: {})", value, Self::names().join(", ")));
+        }
+        Self::from_hex(&value.split(',').collect::<Vec<_>>())
+    }
+
+
+    pub fn from_hex<S: AsRef<str>>(values: &[S]) -> Result<Self, String> {
+        if !(2..=4).contains(&values.len()) {
+            return Err(format!("A palette needs 2 to 4 colours, got {}", values.len()));
+        }
+
+        let parsed = values.iter()
+            .map(|value| parse_hex_color(value.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut colors = [parsed[1]; 4];
+        colors[..parsed.len()].copy_from_slice(&parsed);
+        Ok(Palette { name: "custom".to_string(), colors })
+    }
+
+
+    /**
+    The next built-in palette, wrapping around. Custom palettes go back to the first one.
+    */
+    pub fn next(&self) -> Self {
+        let position = BUILT_IN.iter().position(|(name, _)| *name == self.name);
+        let (name, colors) = match position {
+            Some(i) => BUILT_IN[(i + 1) % BUILT_IN.len()],
+            None => BUILT_IN[0],
+        };
+        Palette { name: name.to_string(), colors }
+    }
+
+
+    pub fn names() -> Vec<&'static str> {
+        BUILT_IN.iter().map(|(name, _)| *name).collect()
+    }
+
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+
+
+    /**
+    Blends from the background (0) to the foreground (255) colour, for pixels that are
+    fading out.
+    */
+    pub fn shade(&self, brightness: u8) -> Rgb {
+        match brightness {
+            0 => self.background(),
+            u8::MAX => self.foreground(),
+            _ => blend(self.background(), self.foreground(), brightness),
+        }
+    }
+}
+
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::built_in("octorust").unwrap()
+    }
+}
+
+
+/**
+Parses `#RRGGBB`, `RRGGBB` or the short `#RGB` form.
+*/
+pub fn parse_hex_color(value: &str) -> Result<Rgb, String> {
+    let hex = value.trim().trim_start_matches('#');
+    let invalid = || format!("Invalid colour: {}", value);
+
+    let expanded: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return Err(invalid()),
+    };
+    u32::from_str_radix(&expanded, 16).map_err(|_| invalid())
+}
+
+
+/**
+Mixes `color` over `background`, from 0 (only the background) to 255 (only `color`).
+*/
+pub fn blend(background: Rgb, color: Rgb, alpha: u8) -> Rgb {
+    let (bg_r, bg_g, bg_b) = split_rgb(background);
+    let (fg_r, fg_g, fg_b) = split_rgb(color);
+    let mix = |bg: u8, fg: u8| -> u32 {
+        (bg as u32 * (255 - alpha as u32) + fg as u32 * alpha as u32) / 255
+    };
+    (mix(bg_r, fg_r) << 16) | (mix(bg_g, fg_g) << 8) | mix(bg_b, fg_b)
+}
+
+
+pub fn split_rgb(color: Rgb) -> (u8, u8, u8) {
+    ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(parse_hex_color("#FFCC00"), Ok(0xFF_CC00));
+        assert_eq!(parse_hex_color("ffcc00"), Ok(0xFF_CC00));
+        assert_eq!(parse_hex_color("#fc0"), Ok(0xFF_CC00));
+        assert!(parse_hex_color("#FFCC0").is_err());
+        assert!(parse_hex_color("#GGGGGG").is_err());
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(Palette::parse("amber").unwrap().foreground(), 0xFF_B000);
+        assert!(Palette::parse("sepia").is_err());
+
+        let custom = Palette::parse("#000,#fff").unwrap();
+        assert_eq!(custom.colors, [0x00_0000, 0xFF_FFFF, 0xFF_FFFF, 0xFF_FFFF]);
+
+        let custom = Palette::parse("#000,#fff,#f00,#0f0").unwrap();
+        assert_eq!(custom.colors, [0x00_0000, 0xFF_FFFF, 0xFF_0000, 0x00_FF00]);
+
+        assert!(Palette::parse("#000,#111,#222,#333,#444").is_err());
+    }
+
+    #[test]
+    fn test_next_wraps_around() {
+        let mut palette = Palette::default();
+        for _ in 0..BUILT_IN.len() {
+            palette = palette.next();
+        }
+        assert_eq!(palette, Palette::default());
+        assert_eq!(Palette::parse("#000,#fff").unwrap().next(), Palette::default());
+    }
+
+    #[test]
+    fn test_shade() {
+        let palette = Palette::parse("#000000,#FF8000").unwrap();
+        assert_eq!(palette.shade(0), 0x00_0000);
+        assert_eq!(palette.shade(255), 0xFF_8000);
+        assert_eq!(palette.shade(51), 0x33_1900);
+    }
+}
@@ -0,0 +1,183 @@
+// persistence.rs
+
+use crate::chip8::constants::{WIDTH, HEIGHT};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+
+/**
+Brightness of every pixel, from 0 (background) to 255 (fully lit).
+*/
+pub type Frame = [[u8; WIDTH]; HEIGHT];
+
+
+pub const DEFAULT_FADE_FRAMES: u8 = 4;
+pub const DEFAULT_BLEND_FRAMES: usize = 2;
+
+
+/**
+How pixels that were just turned off are shown. CHIP-8 games move sprites by XOR-ing
+them out and back in, so without a filter they blink on every frame they're redrawn.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Raw `pixel_array`
+    None,
+    /// Unlit pixels fade out linearly over this many frames, like phosphor
+    Fade(u8),
+    /// A pixel is lit if it was lit in any of the last N frames
+    Blend(usize),
+}
+
+impl FilterMode {
+    /**
+    Parses `none`, `fade`, `fade:N`, `blend` or `blend:N`.
+    */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, count) = match value.split_once(':') {
+            Some((name, count)) => (name, Some(count)),
+            None => (value, None),
+        };
+        let invalid = || format!("Invalid filter: {} (expected none, fade[:N] or blend[:N])", value);
+        let frames = |default: usize| -> Result<usize, String> {
+            match count {
+                Some(count) => count.parse().ok().filter(|&n| n > 0).ok_or_else(invalid),
+                None => Ok(default),
+            }
+        };
+
+        match name {
+            "none" if count.is_none() => Ok(FilterMode::None),
+            "fade" => {
+                let frames = frames(DEFAULT_FADE_FRAMES.into())?;
+                Ok(FilterMode::Fade(u8::try_from(frames).map_err(|_| invalid())?))
+            },
+            "blend" => Ok(FilterMode::Blend(frames(DEFAULT_BLEND_FRAMES)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+
+impl fmt::Display for FilterMode {
+    /**
+    The inverse of `parse`.
+    */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterMode::None => write!(f, "none"),
+            FilterMode::Fade(frames) => write!(f, "fade:{}", frames),
+            FilterMode::Blend(frames) => write!(f, "blend:{}", frames),
+        }
+    }
+}
+
+
+pub struct Persistence {
+    pub mode: FilterMode,
+    frame: Frame,
+    history: VecDeque<[[bool; WIDTH]; HEIGHT]>,
+}
+
+impl Persistence {
+    pub fn new(mode: FilterMode) -> Self {
+        Persistence {
+            mode,
+            frame: [[0; WIDTH]; HEIGHT],
+            history: VecDeque::new(),
+        }
+    }
+
+
+    /**
+    Feeds the next emulated frame through the filter. Must be called once per frame.
+
+    # Returns
+    Returns the brightness of every pixel for this frame.
+    */
+    pub fn apply(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT]) -> &Frame {
+        match self.mode {
+            FilterMode::None => {
+                for (frame_row, row) in self.frame.iter_mut().zip(pixel_array) {
+                    for (brightness, &pixel) in frame_row.iter_mut().zip(row) {
+                        *brightness = if pixel { u8::MAX } else { 0 };
+                    }
+                }
+            },
+            FilterMode::Fade(frames) => {
+                let step = u8::MAX / frames.max(1);
+                for (frame_row, row) in self.frame.iter_mut().zip(pixel_array) {
+                    for (brightness, &pixel) in frame_row.iter_mut().zip(row) {
+                        *brightness = if pixel { u8::MAX } else { brightness.saturating_sub(step) };
+                    }
+                }
+            },
+            FilterMode::Blend(frames) => {
+                self.history.push_back(*pixel_array);
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+                for (y, frame_row) in self.frame.iter_mut().enumerate() {
+                    for (x, brightness) in frame_row.iter_mut().enumerate() {
+                        let lit = self.history.iter().any(|past| past[y][x]);
+                        *brightness = if lit { u8::MAX } else { 0 };
+                    }
+                }
+            },
+        }
+        &self.frame
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_with(lit: bool) -> [[bool; WIDTH]; HEIGHT] {
+        let mut pixel_array = [[false; WIDTH]; HEIGHT];
+        pixel_array[3][5] = lit;
+        pixel_array
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(FilterMode::parse("none"), Ok(FilterMode::None));
+        assert_eq!(FilterMode::parse("fade"), Ok(FilterMode::Fade(DEFAULT_FADE_FRAMES)));
+        assert_eq!(FilterMode::parse("fade:8"), Ok(FilterMode::Fade(8)));
+        assert_eq!(FilterMode::parse("blend:3"), Ok(FilterMode::Blend(3)));
+        assert!(FilterMode::parse("fade:0").is_err());
+        assert!(FilterMode::parse("fade:300").is_err());
+        assert!(FilterMode::parse("blur").is_err());
+
+        for mode in [FilterMode::None, FilterMode::Fade(8), FilterMode::Blend(3)] {
+            assert_eq!(FilterMode::parse(&mode.to_string()), Ok(mode));
+        }
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut persistence = Persistence::new(FilterMode::Fade(3));
+        assert_eq!(persistence.apply(&frame_with(true))[3][5], 255);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 170);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 85);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 0);
+        assert_eq!(persistence.apply(&frame_with(true))[3][5], 255);
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut persistence = Persistence::new(FilterMode::Blend(2));
+        assert_eq!(persistence.apply(&frame_with(true))[3][5], 255);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 255);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 0);
+    }
+
+    #[test]
+    fn test_none() {
+        let mut persistence = Persistence::new(FilterMode::None);
+        assert_eq!(persistence.apply(&frame_with(true))[3][5], 255);
+        assert_eq!(persistence.apply(&frame_with(false))[3][5], 0);
+    }
+}
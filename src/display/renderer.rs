@@ -0,0 +1,97 @@
+// renderer.rs
+
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::effects::Effect;
+use crate::display::framebuffer::{self, Viewport};
+use crate::display::palette::Palette;
+use crate::display::persistence::Persistence;
+
+
+/**
+Display settings that can be changed while playing, independently of the frontend.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayCommand {
+    NextPalette,
+    ToggleEffect(Effect),
+}
+
+
+/**
+The software pipeline between `pixel_array` and a window:
+persistence filter -> palette -> integer scaling -> post-processing effects.
+*/
+pub struct Renderer {
+    pub palette: Palette,
+    pub persistence: Persistence,
+    pub effects: Vec<Effect>,
+    scratch: Vec<u32>,
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, persistence: Persistence, effects: Vec<Effect>) -> Self {
+        Renderer {
+            palette,
+            persistence,
+            effects,
+            scratch: Vec::new(),
+        }
+    }
+
+
+    /**
+    Renders the next frame into a `0x00RRGGBB` buffer of `width * height` pixels.
+    Must be called once per emulated frame, since the persistence filter keeps state.
+    */
+    pub fn render(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT], buffer: &mut [u32], width: usize, height: usize) {
+        let frame = self.persistence.apply(pixel_array);
+        framebuffer::blit(frame, &self.palette, buffer, width, height);
+
+        let viewport = Viewport::fit(width, height);
+        for effect in &self.effects {
+            effect.apply(buffer, &mut self.scratch, width, height, &viewport, &self.palette);
+        }
+    }
+
+
+    /**
+    Applies a runtime display change.
+
+    # Returns
+    Returns a short description of the new state, to be shown to the user.
+    */
+    pub fn handle(&mut self, command: DisplayCommand) -> String {
+        match command {
+            DisplayCommand::NextPalette => {
+                self.palette = self.palette.next();
+                format!("Palette: {}", self.palette.name)
+            },
+            DisplayCommand::ToggleEffect(effect) => {
+                if let Some(position) = self.effects.iter().position(|&e| e == effect) {
+                    self.effects.remove(position);
+                } else {
+                    self.effects.push(effect);
+                }
+                let chain: Vec<&str> = self.effects.iter().map(|effect| effect.name()).collect();
+                format!("Effects: {}", if chain.is_empty() { "none".to_string() } else { chain.join(" -> ") })
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::persistence::FilterMode;
+
+    #[test]
+    fn test_toggle_effect() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::new(FilterMode::None), vec![Effect::Grid]);
+
+        assert_eq!(renderer.handle(DisplayCommand::ToggleEffect(Effect::Bloom)), "Effects: grid -> bloom");
+        assert_eq!(renderer.handle(DisplayCommand::ToggleEffect(Effect::Grid)), "Effects: bloom");
+        assert_eq!(renderer.handle(DisplayCommand::ToggleEffect(Effect::Bloom)), "Effects: none");
+        assert!(renderer.effects.is_empty());
+    }
+}
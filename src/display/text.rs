@@ -0,0 +1,158 @@
+// text.rs
+
+use crate::display::palette::{blend, Rgb};
+
+use rusttype::{point, Font, Scale};
+
+use std::collections::HashMap;
+
+
+/// DejaVu Sans Mono, see `data/fonts/LICENSE`
+const FONT: &[u8] = include_bytes!("../../data/fonts/DejaVuSansMono.ttf");
+
+
+/**
+Coverage of a rasterized character, relative to the pen position at the top of the line.
+*/
+struct Glyph {
+    left: i32,
+    top: i32,
+    width: usize,
+    height: usize,
+    coverage: Vec<u8>,
+}
+
+
+/**
+Draws monospaced text into `0x00RRGGBB` buffers, for overlays in the windowed frontends.
+Characters are rasterized once per size and cached.
+*/
+pub struct Text {
+    font: Font<'static>,
+    scale: Scale,
+    ascent: f32,
+    glyphs: HashMap<char, Glyph>,
+    /// Width of every character in pixels
+    pub advance: usize,
+    pub line_height: usize,
+}
+
+impl Text {
+    /**
+    Loads the built-in font at `size` pixels.
+    */
+    pub fn new(size: f32) -> Self {
+        let font = Font::try_from_bytes(FONT).expect("Built-in font is valid");
+        let scale = Scale::uniform(size);
+        let metrics = font.v_metrics(scale);
+        let advance = font.glyph('0').scaled(scale).h_metrics().advance_width;
+
+        Text {
+            font,
+            scale,
+            ascent: metrics.ascent,
+            glyphs: HashMap::new(),
+            advance: advance.ceil() as usize,
+            line_height: (metrics.ascent - metrics.descent + metrics.line_gap).ceil() as usize,
+        }
+    }
+
+
+    /**
+    Width of `text` in pixels.
+    */
+    pub fn width(&self, text: &str) -> usize {
+        text.chars().count() * self.advance
+    }
+
+
+    fn glyph(&mut self, c: char) -> &Glyph {
+        let (font, scale, ascent) = (&self.font, self.scale, self.ascent);
+        self.glyphs.entry(c).or_insert_with(|| {
+            let glyph = font.glyph(c).scaled(scale).positioned(point(0.0, ascent));
+            let Some(bounds) = glyph.pixel_bounding_box() else {
+                // Blank, e.g. a space
+                return Glyph { left: 0, top: 0, width: 0, height: 0, coverage: Vec::new() };
+            };
+            let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+            let mut coverage = vec![0; width * height];
+            glyph.draw(|x, y, value| coverage[y as usize * width + x as usize] = (value * 255.0).round() as u8);
+            Glyph { left: bounds.min.x, top: bounds.min.y, width, height, coverage }
+        })
+    }
+
+
+    /**
+    Draws `text` on a single line with its top left corner at (`x`, `y`), clipped to the
+    buffer.
+    */
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(&mut self, buffer: &mut [u32], width: usize, height: usize, x: i32, y: i32, text: &str, color: Rgb) {
+        let advance = self.advance as i32;
+        for (i, c) in text.chars().enumerate() {
+            let glyph = self.glyph(c);
+            let origin_x = x + i as i32 * advance + glyph.left;
+            let origin_y = y + glyph.top;
+
+            for row in 0..glyph.height {
+                let py = origin_y + row as i32;
+                if py < 0 || py >= height as i32 {
+                    continue;
+                }
+                for column in 0..glyph.width {
+                    let px = origin_x + column as i32;
+                    let alpha = glyph.coverage[row * glyph.width + column];
+                    if px < 0 || px >= width as i32 || alpha == 0 {
+                        continue;
+                    }
+                    let pixel = &mut buffer[py as usize * width + px as usize];
+                    *pixel = blend(*pixel, color, alpha);
+                }
+            }
+        }
+    }
+}
+
+
+/**
+Fills a rectangle, clipped to the buffer, blending `color` over it by `alpha` (255 is opaque).
+*/
+#[allow(clippy::too_many_arguments)]
+pub fn fill_rect(buffer: &mut [u32], width: usize, height: usize, x: usize, y: usize, w: usize, h: usize, color: Rgb, alpha: u8) {
+    for row in buffer.chunks_mut(width).take(height).skip(y).take(h) {
+        for pixel in row.iter_mut().skip(x).take(w) {
+            *pixel = blend(*pixel, color, alpha);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let mut text = Text::new(16.0);
+        assert!(text.advance > 0 && text.line_height >= 16);
+
+        let (width, height) = (64, 20);
+        let mut buffer = vec![0; width * height];
+        text.draw(&mut buffer, width, height, 2, 0, "I", 0xFF_FFFF);
+        assert!(buffer.iter().any(|&pixel| pixel != 0));
+        // Nothing outside the first character cell
+        assert!(buffer.chunks(width).all(|row| row[2 + text.advance..].iter().all(|&pixel| pixel == 0)));
+
+        // Clipped at the edges without panicking
+        text.draw(&mut buffer, width, height, -5, -5, "WWWWWWWWWW", 0xFF_FFFF);
+        text.draw(&mut buffer, width, height, 60, 18, "W", 0xFF_FFFF);
+    }
+
+    #[test]
+    fn test_fill_rect() {
+        let mut buffer = vec![0; 4 * 4];
+        fill_rect(&mut buffer, 4, 4, 2, 2, 10, 10, 0xFF_FFFF, 255);
+        assert_eq!(buffer.iter().filter(|&&pixel| pixel == 0xFF_FFFF).count(), 4);
+        assert_eq!(buffer[15], 0xFF_FFFF);
+    }
+}
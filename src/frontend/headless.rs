@@ -0,0 +1,39 @@
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::frontend::{Frontend, FrontendEvent};
+
+use std::time::Instant;
+
+
+/**
+Frontend that displays nothing, never presses keys and runs uncapped. Useful for test
+ROMs and batch runs together with `Runner::max_frames`.
+*/
+pub struct Headless {
+    pub presented_frames: u64,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Headless { presented_frames: 0 }
+    }
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frontend for Headless {
+    fn present(&mut self, _pixel_array: &[[bool; WIDTH]; HEIGHT]) {
+        self.presented_frames += 1;
+    }
+
+
+    fn poll_input(&mut self, _keypad: &mut [bool; 16]) -> FrontendEvent {
+        FrontendEvent::Continue
+    }
+
+
+    fn wait_until(&mut self, _deadline: Instant) {}
+}
@@ -0,0 +1,109 @@
+// keymap.rs
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+
+/**
+Semantic game buttons, mapped to the arrow keys and Space/Enter on top of the hex keypad
+layout, so games with odd controls are playable without learning their keys.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+}
+
+
+/**
+Which CHIP-8 key each `Button` presses, if any.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keymap {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b: Option<u8>,
+}
+
+impl Keymap {
+    /**
+    Builds a keymap from button names as used by the chip-8-database (`up`, `down`,
+    `left`, `right`, `a`, `b`). Unknown names and keys above 0xF are ignored.
+    */
+    pub fn from_names(keys: &HashMap<String, u8>) -> Self {
+        let key = |name: &str| keys.get(name).copied().filter(|&key| key <= 0xF);
+        Keymap {
+            up: key("up"),
+            down: key("down"),
+            left: key("left"),
+            right: key("right"),
+            a: key("a"),
+            b: key("b"),
+        }
+    }
+
+
+    /**
+    `self` with the buttons mapped in `other` replaced.
+    */
+    pub fn merge(self, other: &Keymap) -> Self {
+        Keymap {
+            up: other.up.or(self.up),
+            down: other.down.or(self.down),
+            left: other.left.or(self.left),
+            right: other.right.or(self.right),
+            a: other.a.or(self.a),
+            b: other.b.or(self.b),
+        }
+    }
+
+
+    pub fn key(&self, button: Button) -> Option<usize> {
+        let key = match button {
+            Button::Up => self.up,
+            Button::Down => self.down,
+            Button::Left => self.left,
+            Button::Right => self.right,
+            Button::A => self.a,
+            Button::B => self.b,
+        };
+        key.filter(|&key| key <= 0xF).map(|key| key as usize)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_names() {
+        let keys = HashMap::from([
+            ("up".to_string(), 5),
+            ("a".to_string(), 6),
+            ("player2Up".to_string(), 8),
+            ("down".to_string(), 0x10),
+        ]);
+        let keymap = Keymap::from_names(&keys);
+
+        assert_eq!(keymap.key(Button::Up), Some(5));
+        assert_eq!(keymap.key(Button::A), Some(6));
+        assert_eq!(keymap.key(Button::Down), None);
+        assert_eq!(keymap.key(Button::Left), None);
+    }
+}
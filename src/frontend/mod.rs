@@ -0,0 +1,124 @@
+pub mod runner;
+pub mod keymap;
+pub mod headless;
+pub mod terminal;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "winit")]
+pub mod winit;
+pub mod ui;
+pub mod watch;
+
+use crate::cheat::cheats::Cheat;
+use crate::cheat::search::Comparison;
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::frontend::runner::Runner;
+
+use std::time::Instant;
+
+
+/**
+What the frontend asks the runner to do after polling input.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrontendEvent {
+    Continue,
+    Quit,
+    /// Replace the running ROM with the one at this path
+    Load(String),
+    /// Restart the running ROM, see `Chip8::reset`
+    Reset,
+    /// Load the running ROM again from disk, picking up changes to it
+    HardReset,
+    /// Switch to the quirks and speed of the platform with this id
+    SetPlatform(String),
+    /// Change the instructions run per frame
+    SetSpeed(u32),
+    /// Start a RAM search over the whole memory
+    NewSearch,
+    /// Narrow down the RAM search
+    FilterSearch(Comparison),
+    /// Add a cheat for the running ROM and save it
+    AddCheat(Cheat),
+    /// Turn the running ROM's cheat at this index on or off and save it
+    ToggleCheat(usize),
+}
+
+
+/**
+Buzzer settings shared by the frontends that can beep.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Audio {
+    /// 0 to 100, 0 mutes the buzzer
+    pub volume: u8,
+    /// Pitch in Hz
+    pub frequency: u32,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio { volume: 15, frequency: 440 }
+    }
+}
+
+
+/**
+A backend the `Runner` drives once per frame. Backends only adapt the emulator to a
+window, a terminal or nothing at all; timing and the interpreter loop live in the runner.
+*/
+pub trait Frontend {
+    /**
+    Shows the current framebuffer.
+    */
+    fn present(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT]);
+
+    /**
+    Updates `keypad` with the state of the 16 CHIP-8 keys and reports whether the user
+    asked to quit.
+    */
+    fn poll_input(&mut self, keypad: &mut [bool; 16]) -> FrontendEvent;
+
+    /**
+    Called every frame before `present`, for views that show the machine state or the
+    emulation speed.
+    */
+    fn inspect(&mut self, _runner: &Runner) {}
+
+    /**
+    Whether emulation is paused, e.g. while a menu is open. The runner keeps presenting
+    and polling input but doesn't run instructions.
+    */
+    fn paused(&self) -> bool {
+        false
+    }
+
+    /**
+    Called when a ROM starts running, with the id of its platform if known and the ROMs
+    run last, most recent first.
+    */
+    fn loaded(&mut self, _rom_path: &str, _platform: Option<&str>, _recent: &[String]) {}
+
+    /**
+    Shows a short status message to the user, e.g. after changing the speed.
+    */
+    fn notify(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    /**
+    Starts or stops the buzzer. Called every frame with whether the sound timer is active.
+    */
+    fn play_audio(&mut self, _playing: bool) {}
+
+    /**
+    Blocks until `deadline`, the start of the next frame. Backends that should run
+    uncapped (e.g. headless) override this with a no-op.
+    */
+    fn wait_until(&mut self, deadline: Instant) {
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+    }
+}
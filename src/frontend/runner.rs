@@ -0,0 +1,498 @@
+use crate::cheat::cheats::{self, Cheat};
+use crate::cheat::search::RamSearch;
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::{FRAME_RATE, CYCLES_PER_FRAME};
+use crate::chip8::recompiler::Recompiler;
+use crate::control::server::ControlServer;
+use crate::debug::coverage::Coverage;
+use crate::debug::gdb::{Debugger, GdbStub};
+use crate::debug::profiler::Profiler;
+use crate::display::overlay::Overlay;
+use crate::frontend::watch::Watcher;
+use crate::frontend::{Frontend, FrontendEvent};
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+
+use std::time::{Duration, Instant};
+
+
+/**
+Owns the emulator and its timing: runs `cycles_per_frame` instructions, ticks the 60 Hz
+timers and hands the result to a `Frontend` once per frame.
+*/
+pub struct Runner {
+    pub chip8: Chip8,
+    pub cycles_per_frame: u32,
+    pub frame_duration: Duration,
+    pub max_frames: Option<u64>,
+    pub frames: u64,
+    /// Instructions run so far, for speed counters
+    pub instructions: u64,
+    /// Counts every instruction run when set
+    pub profiler: Option<Profiler>,
+    /// Records how memory is used when set
+    pub coverage: Option<Coverage>,
+    /// Stops the run with a `HardReset` when the ROM changes on disk, if set
+    pub watcher: Option<Watcher>,
+    /// Hands control of the run to an attached debugger when set
+    pub gdb: Option<GdbStub>,
+    /// Answers scripts' requests between frames when set
+    pub server: Option<ControlServer>,
+    /// Runs a script's hooks during the run when set
+    #[cfg(feature = "scripting")]
+    pub script: Option<Script>,
+    /// What the script drew over the last frame, shown by the windowed frontends
+    pub overlay: Overlay,
+    /// Applied at the end of every frame
+    pub cheats: Vec<Cheat>,
+    /// The RAM search started from the frontend, if any
+    pub search: Option<RamSearch>,
+    /// Instructions run so far in the current frame
+    frame_cycles: u32,
+}
+
+impl Runner {
+    pub fn new(chip8: Chip8) -> Self {
+        Runner {
+            chip8,
+            cycles_per_frame: CYCLES_PER_FRAME,
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            max_frames: None,
+            frames: 0,
+            instructions: 0,
+            profiler: None,
+            coverage: None,
+            watcher: None,
+            gdb: None,
+            server: None,
+            #[cfg(feature = "scripting")]
+            script: None,
+            overlay: Overlay::default(),
+            cheats: Vec::new(),
+            search: None,
+            frame_cycles: 0,
+        }
+    }
+
+
+    /**
+    Runs a single instruction, ending the frame after `cycles_per_frame` of them or
+    earlier if a draw is waiting for vblank. Once the machine has a `Chip8::fault`
+    nothing runs and every step ends a frame, so time goes on while it's stopped.
+
+    # Returns
+    Returns whether the frame ended, i.e. the timers were just ticked.
+    */
+    pub fn step(&mut self) -> bool {
+        if self.instrumented() {
+            self.instrumented_cycle();
+        } else {
+            self.chip8.cycle();
+        }
+        if self.chip8.fault().is_none() {
+            self.instructions += 1;
+            self.frame_cycles += 1;
+        }
+        self.end_frame()
+    }
+
+
+    /**
+    Same as `step` with the recompiler, running as much of a basic block as fits in the
+    frame.
+    */
+    fn step_block(&mut self) -> bool {
+        let budget = self.cycles_per_frame.saturating_sub(self.frame_cycles);
+        let executed = self.chip8.run_block(budget);
+        self.instructions += executed as u64;
+        self.frame_cycles += executed;
+        self.end_frame()
+    }
+
+
+    /**
+    Ticks the timers if the frame is over.
+
+    # Returns
+    Returns whether it was.
+    */
+    fn end_frame(&mut self) -> bool {
+        let stopped = self.chip8.waiting_for_vblank || self.chip8.fault().is_some();
+        if self.frame_cycles < self.cycles_per_frame && !stopped {
+            return false;
+        }
+
+        self.frame_cycles = 0;
+        self.chip8.waiting_for_vblank = false;
+        self.chip8.tick_timers();
+        self.frames += 1;
+        cheats::apply(&self.cheats, &mut self.chip8);
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.end_frame(&mut self.chip8, self.frames, &mut self.overlay);
+        }
+        true
+    }
+
+
+    /**
+    Whether something has to see every instruction, so they can't be run as blocks.
+    */
+    fn instrumented(&self) -> bool {
+        #[cfg(feature = "scripting")]
+        if self.script.as_ref().is_some_and(|script| script.watches_instructions()) {
+            return true;
+        }
+        self.profiler.is_some() || self.coverage.is_some()
+    }
+
+
+    /**
+    `Chip8::cycle` that also feeds the profiler, the coverage and the script's hooks, which
+    need the decoded instruction and the state before it runs.
+    */
+    fn instrumented_cycle(&mut self) {
+        if !Chip8::is_valid_pc(self.chip8.pc()) || self.chip8.fault().is_some() {
+            return self.chip8.cycle();
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.before_instruction(&mut self.chip8, self.frames);
+        }
+        let pc = self.chip8.pc();
+        let opcode = Chip8::read_memory_address(*self.chip8.memory(), pc);
+        let instruction = self.chip8.decode(opcode);
+        let index = self.chip8.index();
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, instruction, index);
+        }
+        self.chip8.cycle();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, instruction, self.chip8.pc(), self.chip8.stack().len());
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.after_write(&mut self.chip8, index, instruction.bytes_written(), self.frames);
+        }
+    }
+
+
+    /**
+    Swaps in a freshly loaded machine, keeping the tracer and restarting the profiler and
+    the coverage so they describe the new ROM only. The RAM search is dropped, the cheats
+    are left for the caller to replace.
+    */
+    pub fn replace(&mut self, mut chip8: Chip8) {
+        chip8.tracer = self.chip8.tracer.take();
+        chip8.recompiler = self.chip8.recompiler.take().map(|_| Recompiler::new());
+        self.chip8 = chip8;
+        self.frame_cycles = 0;
+        self.search = None;
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new());
+        }
+    }
+
+
+    /**
+    Restarts the ROM with `Chip8::reset`, at the start of a frame.
+    */
+    pub fn reset(&mut self) {
+        self.chip8.reset();
+        self.frame_cycles = 0;
+    }
+
+
+    /**
+    Emulates a single frame without presenting it.
+    */
+    pub fn step_frame(&mut self) {
+        if self.chip8.recompiler.is_some() && !self.instrumented() {
+            while !self.step_block() {}
+        } else {
+            while !self.step() {}
+        }
+    }
+
+
+    /**
+    Runs until `max_frames` frames have been emulated or the frontend asks for something
+    the runner can't do on its own, like quitting or loading another ROM.
+
+    # Returns
+    Returns the event that stopped the run, `Quit` if `max_frames` was reached.
+    */
+    pub fn run(&mut self, frontend: &mut dyn Frontend) -> FrontendEvent {
+        let mut deadline = Instant::now();
+        let mut stopped_by = FrontendEvent::Quit;
+        let mut faulted = false;
+
+        while self.max_frames.is_none_or(|max| self.frames < max) {
+            let keypad = self.chip8.keypad;
+            let event = frontend.poll_input(&mut self.chip8.keypad);
+            self.keys_changed(keypad);
+            match event {
+                FrontendEvent::Continue => {},
+                FrontendEvent::SetSpeed(speed) => {
+                    self.cycles_per_frame = speed.max(1);
+                    frontend.notify(&format!("Speed: {} instructions per frame", self.cycles_per_frame));
+                },
+                FrontendEvent::Reset => {
+                    self.reset();
+                    frontend.notify("Reset");
+                },
+                FrontendEvent::NewSearch => {
+                    let search = RamSearch::new(self.chip8.memory());
+                    frontend.notify(&format!("RAM search: {} addresses", search.candidates().len()));
+                    self.search = Some(search);
+                },
+                FrontendEvent::FilterSearch(comparison) => match &mut self.search {
+                    Some(search) => {
+                        search.filter(self.chip8.memory(), comparison);
+                        frontend.notify(&format!("RAM search ({}): {} left", comparison, search.candidates().len()));
+                    },
+                    None => frontend.notify("Start a new RAM search first"),
+                },
+                event => {
+                    stopped_by = event;
+                    break;
+                },
+            }
+
+            if self.watcher.as_mut().is_some_and(|watcher| watcher.poll(Instant::now())) {
+                stopped_by = FrontendEvent::HardReset;
+                break;
+            }
+
+            if let Some(mut server) = self.server.take() {
+                server.poll(self);
+                self.server = Some(server);
+            }
+
+            let paused = frontend.paused() || self.server.as_ref().is_some_and(|server| server.paused);
+            if !paused {
+                match self.gdb.take() {
+                    Some(mut gdb) => match gdb.frame(self) {
+                        Debugger::Attached => self.gdb = Some(gdb),
+                        Debugger::Detached => frontend.notify("Debugger detached"),
+                        Debugger::Killed => break,
+                    },
+                    None => self.step_frame(),
+                }
+            }
+            if let Some(fault) = self.chip8.fault().filter(|_| !faulted) {
+                frontend.notify(&format!("Emulation stopped: {}", fault));
+            }
+            faulted = self.chip8.fault().is_some();
+
+            #[cfg(feature = "scripting")]
+            for message in self.script.as_mut().map(Script::take_messages).unwrap_or_default() {
+                frontend.notify(&message);
+            }
+            frontend.play_audio(self.chip8.sound_timer > 0 && !paused);
+            frontend.inspect(self);
+            frontend.present(&self.chip8.pixel_array);
+
+            deadline += self.frame_duration;
+            frontend.wait_until(deadline);
+            // Don't try to catch up after a long stall (e.g. window dragged)
+            let now = Instant::now();
+            if now > deadline + self.frame_duration {
+                deadline = now;
+            }
+        }
+
+        frontend.play_audio(false);
+        stopped_by
+    }
+
+
+    /**
+    Tells the script about the keys the player pressed or released since `before`.
+    */
+    #[cfg(feature = "scripting")]
+    fn keys_changed(&mut self, before: [bool; 16]) {
+        if let Some(script) = &mut self.script {
+            let after = self.chip8.keypad;
+            for key in (0..16).filter(|&key| before[key] != after[key]) {
+                script.key_changed(&mut self.chip8, key, after[key], self.frames);
+            }
+        }
+    }
+
+
+    #[cfg(not(feature = "scripting"))]
+    fn keys_changed(&mut self, _before: [bool; 16]) {}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::constants::{WIDTH, HEIGHT};
+    use crate::cheat::cheats::Code;
+    use crate::cheat::search::Comparison;
+    use crate::frontend::headless::Headless;
+
+    #[test]
+    fn test_run_stops_after_max_frames() {
+        let mut runner = Runner::new(Chip8::new());
+        runner.cycles_per_frame = 0;
+        runner.max_frames = Some(3);
+        runner.chip8.sound_timer = 2;
+
+        runner.run(&mut Headless::new());
+
+        assert_eq!(runner.frames, 3);
+        assert_eq!(runner.chip8.sound_timer, 0);
+    }
+
+    /// Replays a list of events, one per frame, pausing while `pause` is set
+    struct Scripted {
+        events: Vec<FrontendEvent>,
+        pause: bool,
+        messages: Vec<String>,
+    }
+
+    impl Frontend for Scripted {
+        fn present(&mut self, _pixel_array: &[[bool; WIDTH]; HEIGHT]) {}
+
+        fn poll_input(&mut self, _keypad: &mut [bool; 16]) -> FrontendEvent {
+            if self.events.is_empty() { FrontendEvent::Continue } else { self.events.remove(0) }
+        }
+
+        fn paused(&self) -> bool {
+            self.pause
+        }
+
+        fn notify(&mut self, message: &str) {
+            self.messages.push(message.to_string());
+        }
+
+        fn wait_until(&mut self, _deadline: Instant) {}
+    }
+
+    #[test]
+    fn test_run_handles_events() {
+        let mut runner = Runner::new(Chip8::new());
+        let mut frontend = Scripted {
+            events: vec![FrontendEvent::SetSpeed(3), FrontendEvent::Continue, FrontendEvent::Load("pong.ch8".to_string())],
+            pause: false,
+            messages: Vec::new(),
+        };
+
+        assert_eq!(runner.run(&mut frontend), FrontendEvent::Load("pong.ch8".to_string()));
+        assert_eq!(runner.cycles_per_frame, 3);
+        assert_eq!(runner.frames, 2);
+        assert_eq!(runner.instructions, 6);
+        assert_eq!(frontend.messages, ["Speed: 3 instructions per frame"]);
+    }
+
+    #[test]
+    fn test_run_reset() {
+        // VA += 1 in a loop, two instructions per frame
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x7A, 0x01, 0x12, 0x00]).unwrap();
+        let mut runner = Runner::new(chip8);
+        runner.cycles_per_frame = 4;
+        let mut frontend = Scripted {
+            events: vec![FrontendEvent::Continue, FrontendEvent::Reset, FrontendEvent::Quit],
+            pause: false,
+            messages: Vec::new(),
+        };
+
+        runner.run(&mut frontend);
+        assert_eq!(runner.chip8.v()[0xA], 2);
+        assert_eq!(frontend.messages, ["Reset"]);
+    }
+
+    #[test]
+    fn test_run_fault() {
+        // V0 += 1, then RET with an empty stack
+        for recompiler in [None, Some(Recompiler::new())] {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(&[0x70, 0x01, 0x00, 0xEE]).unwrap();
+            chip8.recompiler = recompiler;
+            chip8.sound_timer = 3;
+            let mut runner = Runner::new(chip8);
+            runner.max_frames = Some(3);
+            let mut frontend = Scripted { events: Vec::new(), pause: false, messages: Vec::new() };
+
+            runner.run(&mut frontend);
+            assert_eq!((runner.frames, runner.instructions), (3, 1), "frames go on without running anything");
+            assert_eq!(runner.chip8.sound_timer, 0);
+            assert_eq!((runner.chip8.pc(), runner.chip8.v()[0]), (0x202, 1));
+            assert_eq!(frontend.messages, ["Emulation stopped: return with an empty stack"]);
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_run_script() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x7A, 0x01, 0x12, 0x00]).unwrap();
+        chip8.recompiler = Some(Recompiler::new());
+        let script = r#"
+            on_pc(0x200, || set_v(0xB, v(0xB) + 1));
+            on_frame(|| text(0, 0, `VA ${v(0xA)}`));
+        "#;
+        let mut runner = Runner::new(chip8);
+        runner.script = Some(Script::new(script, &mut runner.chip8).unwrap());
+        runner.cycles_per_frame = 4;
+        runner.max_frames = Some(2);
+
+        runner.run(&mut Headless::new());
+        assert_eq!(runner.chip8.v()[0xB], 4, "hooks see every instruction, even with the recompiler");
+        assert_eq!(runner.overlay.shapes.len(), 1);
+    }
+
+    #[test]
+    fn test_run_search_and_cheats() {
+        // V0 += 1 and stored at 300, once per frame
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00]).unwrap();
+        let mut runner = Runner::new(chip8);
+        runner.cycles_per_frame = 4;
+        runner.cheats = vec![Cheat { name: "Answer".to_string(), code: Code::parse("301=2A").unwrap(), enabled: true }];
+        let mut frontend = Scripted {
+            events: vec![
+                FrontendEvent::FilterSearch(Comparison::Changed),
+                FrontendEvent::NewSearch,
+                FrontendEvent::FilterSearch(Comparison::Increased),
+                FrontendEvent::ToggleCheat(0),
+            ],
+            pause: false,
+            messages: Vec::new(),
+        };
+
+        assert_eq!(runner.run(&mut frontend), FrontendEvent::ToggleCheat(0));
+        assert_eq!(runner.search.as_ref().unwrap().candidates(), [0x300]);
+        assert_eq!(runner.chip8.memory()[0x301], 0x2A);
+        assert_eq!(frontend.messages, [
+            "Start a new RAM search first",
+            "RAM search: 4096 addresses",
+            "RAM search (increased): 1 left",
+        ]);
+
+        runner.replace(Chip8::new());
+        assert!(runner.search.is_none());
+    }
+
+    #[test]
+    fn test_run_paused() {
+        let mut runner = Runner::new(Chip8::new());
+        let mut frontend = Scripted {
+            events: vec![FrontendEvent::Continue, FrontendEvent::Continue, FrontendEvent::Quit],
+            pause: true,
+            messages: Vec::new(),
+        };
+
+        assert_eq!(runner.run(&mut frontend), FrontendEvent::Quit);
+        assert_eq!(runner.frames, 0);
+        assert_eq!(runner.instructions, 0);
+    }
+}
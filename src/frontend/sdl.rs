@@ -0,0 +1,259 @@
+use crate::chip8::constants::{
+    WIDTH,
+    HEIGHT,
+};
+use crate::display::inspector::Inspector;
+use crate::frontend::runner::Runner;
+use crate::frontend::ui::{Key, Response, Ui};
+use crate::frontend::{Audio, Frontend, FrontendEvent};
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+/// Amplitude of the square wave at volume 100
+const MAX_AMPLITUDE: f32 = 1.0;
+
+
+/**
+Maps the left side of a QWERTY keyboard to the CHIP-8 hex keypad, by physical key
+position so it works with any layout:
+
+```text
+1 2 3 4        1 2 3 C
+Q W E R   ->   4 5 6 D
+A S D F        7 8 9 E
+Z X C V        A 0 B F
+```
+*/
+pub fn keypad_index(scancode: Scancode) -> Option<usize> {
+    match scancode {
+        Scancode::Num1 => Some(0x1),
+        Scancode::Num2 => Some(0x2),
+        Scancode::Num3 => Some(0x3),
+        Scancode::Num4 => Some(0xC),
+        Scancode::Q => Some(0x4),
+        Scancode::W => Some(0x5),
+        Scancode::E => Some(0x6),
+        Scancode::R => Some(0xD),
+        Scancode::A => Some(0x7),
+        Scancode::S => Some(0x8),
+        Scancode::D => Some(0x9),
+        Scancode::F => Some(0xE),
+        Scancode::Z => Some(0xA),
+        Scancode::X => Some(0x0),
+        Scancode::C => Some(0xB),
+        Scancode::V => Some(0xF),
+        _ => None,
+    }
+}
+
+
+/**
+The keys `Ui` handles: the keypad, arrows, Space, Enter, Esc and the function keys.
+*/
+pub fn key(scancode: Scancode) -> Option<Key> {
+    if let Some(index) = keypad_index(scancode) {
+        return Some(Key::Keypad(index));
+    }
+    match scancode {
+        Scancode::Up => Some(Key::Up),
+        Scancode::Down => Some(Key::Down),
+        Scancode::Left => Some(Key::Left),
+        Scancode::Right => Some(Key::Right),
+        Scancode::Space => Some(Key::Space),
+        Scancode::Return => Some(Key::Enter),
+        Scancode::Escape => Some(Key::Escape),
+        Scancode::F1 => Some(Key::Function(1)),
+        Scancode::F2 => Some(Key::Function(2)),
+        Scancode::F3 => Some(Key::Function(3)),
+        Scancode::F4 => Some(Key::Function(4)),
+        Scancode::F5 => Some(Key::Function(5)),
+        Scancode::F6 => Some(Key::Function(6)),
+        Scancode::F7 => Some(Key::Function(7)),
+        Scancode::F8 => Some(Key::Function(8)),
+        Scancode::F9 => Some(Key::Function(9)),
+        Scancode::F10 => Some(Key::Function(10)),
+        Scancode::F11 => Some(Key::Function(11)),
+        Scancode::F12 => Some(Key::Function(12)),
+        _ => None,
+    }
+}
+
+
+/**
+Widens the window by the width of the inspector when it is shown, so the game keeps its
+size, and narrows it back when it is hidden.
+*/
+fn fit_inspector(inspector: &Inspector, window: &mut Window) {
+    let (width, height) = window.size();
+    let panel = inspector.width() as u32;
+    let width = if inspector.visible { width + panel } else { width.saturating_sub(panel).max(WIDTH as u32) };
+    if let Err(e) = window.set_size(width, height) {
+        eprintln!("Error resizing window: {}", e);
+    }
+}
+
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase <= 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+
+pub struct Sdl {
+    pub ui: Ui,
+    canvas: Canvas<Window>,
+    event_pump: EventPump,
+    audio: Option<AudioDevice<SquareWave>>,
+    buffer: Vec<u32>,
+    bytes: Vec<u8>,
+}
+
+impl Sdl {
+    /**
+    Opens a window `scale` times the CHIP-8 resolution. The buzzer is left closed when
+    `audio` is muted.
+    */
+    pub fn new(ui: Ui, scale: u32, audio: Audio) -> Result<Self, String> {
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+
+        let window = video_subsystem.window("Octorust Chip8 Interpreter!", WIDTH as u32 * scale, HEIGHT as u32 * scale)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let canvas = window.into_canvas().build()
+            .map_err(|e| e.to_string())?;
+
+        let event_pump = sdl_context.event_pump()?;
+
+        // A missing audio device shouldn't stop the game from running
+        let audio = if audio.volume == 0 { None } else { sdl_context.audio().and_then(|audio_subsystem| {
+            let desired_spec = AudioSpecDesired {
+                freq: Some(44_100),
+                channels: Some(1),
+                samples: None,
+            };
+            audio_subsystem.open_playback(None, &desired_spec, |spec| SquareWave {
+                phase_inc: audio.frequency as f32 / spec.freq as f32,
+                phase: 0.0,
+                volume: MAX_AMPLITUDE * audio.volume as f32 / 100.0,
+            })
+        }).map_err(|e| eprintln!("Audio disabled: {}", e)).ok() };
+
+        Ok(Sdl {
+            ui,
+            canvas,
+            event_pump,
+            audio,
+            buffer: Vec::new(),
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl Frontend for Sdl {
+    /**
+    Updates screen with pixel_array values, rendered on the CPU and uploaded as a
+    streaming texture the size of the window.
+    */
+    fn present(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT]) {
+        let (width, height) = match self.canvas.output_size() {
+            Ok((width, height)) if width > 0 && height > 0 => (width, height),
+            _ => return,
+        };
+
+        self.buffer.resize((width * height) as usize, 0);
+        self.ui.render(pixel_array, &mut self.buffer, width as usize, height as usize);
+
+        self.bytes.clear();
+        self.bytes.extend(self.buffer.iter().flat_map(|pixel| pixel.to_ne_bytes()));
+
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB888, width, height)
+            .expect("Error creating texture");
+        texture.update(None, &self.bytes, width as usize * 4).expect("Error updating texture");
+
+        self.canvas.copy(&texture, None, None).expect("Error rendering frame");
+        self.canvas.present();
+    }
+
+
+    fn poll_input(&mut self, keypad: &mut [bool; 16]) -> FrontendEvent {
+        let mut result = FrontendEvent::Continue;
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => {
+                    return FrontendEvent::Quit;
+                },
+                Event::DropFile { filename, .. } => {
+                    result = self.ui.drop_file(&filename);
+                },
+                Event::KeyDown { scancode: Some(scancode), .. } => {
+                    match key(scancode).and_then(|key| self.ui.key_down(key, keypad)) {
+                        Some(Response::Event(event)) => result = event,
+                        Some(Response::InspectorToggled) => fit_inspector(&self.ui.inspector, self.canvas.window_mut()),
+                        None => {},
+                    }
+                },
+                Event::KeyUp { scancode: Some(scancode), .. } => {
+                    if let Some(key) = key(scancode) {
+                        self.ui.key_up(key, keypad);
+                    }
+                },
+                _ => {}
+            }
+        }
+        result
+    }
+
+
+    fn inspect(&mut self, runner: &Runner) {
+        self.ui.inspect(runner);
+    }
+
+
+    fn paused(&self) -> bool {
+        self.ui.menu.open
+    }
+
+
+    fn loaded(&mut self, rom_path: &str, platform: Option<&str>, recent: &[String]) {
+        self.ui.menu.loaded(rom_path, platform, recent);
+    }
+
+
+    fn notify(&mut self, message: &str) {
+        self.ui.notify(message);
+    }
+
+
+    fn play_audio(&mut self, playing: bool) {
+        if let Some(audio) = &self.audio {
+            if playing {
+                audio.resume();
+            } else {
+                audio.pause();
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::palette::split_rgb;
+use crate::display::persistence::Frame;
+use crate::display::renderer::Renderer;
+use crate::frontend::{Audio, Frontend, FrontendEvent};
+
+use std::io::{Write, stdout};
+
+
+/**
+Draws the screen to an ANSI terminal, two CHIP-8 rows per text line using 24-bit coloured
+half blocks, so palettes and fading pixels are shown as in a window. Post-processing
+effects need more resolution than a terminal has and are ignored.
+Has no keyboard input: the terminal is line-buffered without a raw-mode dependency,
+so quit with Ctrl+C.
+*/
+pub struct Terminal {
+    renderer: Renderer,
+    last_frame: Option<Frame>,
+    beeping: bool,
+    muted: bool,
+}
+
+impl Terminal {
+    pub fn new(renderer: Renderer, audio: Audio) -> Self {
+        // Clear the screen and hide the cursor
+        print!("\x1b[2J\x1b[?25l");
+        Terminal { renderer, last_frame: None, beeping: false, muted: audio.volume == 0 }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h");
+        let _ = stdout().flush();
+    }
+}
+
+impl Frontend for Terminal {
+    fn present(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT]) {
+        let frame = *self.renderer.persistence.apply(pixel_array);
+        if self.last_frame == Some(frame) {
+            return;
+        }
+
+        let mut out = String::with_capacity(WIDTH * HEIGHT * 8);
+        out.push_str("\x1b[H");
+        for rows in frame.chunks(2) {
+            // Upper half block: foreground is the top pixel, background the bottom one.
+            // Colours are only emitted when they change from the previous cell.
+            let mut current = None;
+            for x in 0..WIDTH {
+                let top = self.renderer.palette.shade(rows[0][x]);
+                let bottom = self.renderer.palette.shade(rows.get(1).map_or(0, |row| row[x]));
+                if current != Some((top, bottom)) {
+                    let (fg_r, fg_g, fg_b) = split_rgb(top);
+                    let (bg_r, bg_g, bg_b) = split_rgb(bottom);
+                    out.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fg_r, fg_g, fg_b, bg_r, bg_g, bg_b));
+                    current = Some((top, bottom));
+                }
+                out.push('▀');
+            }
+            out.push_str("\x1b[0m\n");
+        }
+
+        let mut stdout = stdout().lock();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+        self.last_frame = Some(frame);
+    }
+
+
+    fn poll_input(&mut self, _keypad: &mut [bool; 16]) -> FrontendEvent {
+        FrontendEvent::Continue
+    }
+
+
+    fn play_audio(&mut self, playing: bool) {
+        if playing && !self.beeping && !self.muted {
+            // Terminal bell, once per beep
+            print!("\x07");
+        }
+        self.beeping = playing;
+    }
+}
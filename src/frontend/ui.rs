@@ -0,0 +1,301 @@
+// ui.rs
+
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::effects::Effect;
+use crate::display::inspector::Inspector;
+use crate::display::menu::{Menu, MenuAction, MenuKey, SearchView, SHOWN_CANDIDATES};
+use crate::display::osd::Osd;
+use crate::display::overlay::Overlay;
+use crate::display::renderer::{DisplayCommand, Renderer};
+use crate::display::text::Text;
+use crate::frontend::keymap::{Button, Keymap};
+use crate::frontend::runner::Runner;
+use crate::frontend::FrontendEvent;
+
+use std::time::Instant;
+
+
+const OVERLAY_FONT_SIZE: f32 = 14.0;
+
+
+/**
+Keys the windowed frontends care about, translated from each backend's own key codes.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A key of the CHIP-8 hex keypad
+    Keypad(usize),
+    Up,
+    Down,
+    Left,
+    Right,
+    Space,
+    Enter,
+    Escape,
+    /// F1 to F12
+    Function(u8),
+}
+
+
+/**
+What the frontend has to do after a key press, beyond what `Ui` did itself.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Event(FrontendEvent),
+    /// The inspector was shown or hidden, so the window should be resized
+    InspectorToggled,
+}
+
+
+fn display_command(key: Key) -> Option<DisplayCommand> {
+    match key {
+        Key::Function(2) => Some(DisplayCommand::NextPalette),
+        Key::Function(5) => Some(DisplayCommand::ToggleEffect(Effect::Grid)),
+        Key::Function(6) => Some(DisplayCommand::ToggleEffect(Effect::Scanlines)),
+        Key::Function(7) => Some(DisplayCommand::ToggleEffect(Effect::Bloom)),
+        Key::Function(8) => Some(DisplayCommand::ToggleEffect(Effect::Rounded)),
+        Key::Function(9) => Some(DisplayCommand::ToggleEffect(Effect::Curvature)),
+        _ => None,
+    }
+}
+
+
+fn button(key: Key) -> Option<Button> {
+    match key {
+        Key::Up => Some(Button::Up),
+        Key::Down => Some(Button::Down),
+        Key::Left => Some(Button::Left),
+        Key::Right => Some(Button::Right),
+        Key::Space => Some(Button::A),
+        Key::Enter => Some(Button::B),
+        _ => None,
+    }
+}
+
+
+fn menu_key(key: Key) -> Option<MenuKey> {
+    match key {
+        Key::Up => Some(MenuKey::Up),
+        Key::Down => Some(MenuKey::Down),
+        Key::Left => Some(MenuKey::Left),
+        Key::Right => Some(MenuKey::Right),
+        Key::Space | Key::Enter => Some(MenuKey::Select),
+        Key::Escape => Some(MenuKey::Back),
+        _ => None,
+    }
+}
+
+
+/**
+Everything the windowed frontends share on top of their window: rendering with the
+inspector, script overlay, on-screen display and pause menu drawn over the game, and what
+each key does.
+*/
+pub struct Ui {
+    pub renderer: Renderer,
+    pub keymap: Keymap,
+    pub inspector: Inspector,
+    pub osd: Osd,
+    pub menu: Menu,
+    /// The runner's overlay as of the last `inspect`
+    overlay: Overlay,
+    overlay_text: Text,
+}
+
+impl Ui {
+    pub fn new(renderer: Renderer, keymap: Keymap, menu: Menu) -> Self {
+        Ui {
+            renderer,
+            keymap,
+            inspector: Inspector::new(),
+            osd: Osd::new(),
+            menu,
+            overlay: Overlay::default(),
+            overlay_text: Text::new(OVERLAY_FONT_SIZE),
+        }
+    }
+
+
+    fn keypad_key(&self, key: Key) -> Option<usize> {
+        match key {
+            Key::Keypad(index) => Some(index),
+            _ => button(key).and_then(|button| self.keymap.key(button)),
+        }
+    }
+
+
+    /**
+    Handles a key press. While the menu is open keys drive it and the keypad is released.
+    Otherwise, on top of the keypad and the keymap buttons:
+
+    * `Esc`: pause menu
+    * `F1`: inspector
+    * `F2`: next palette
+    * `F3`: FPS/IPS counter
+    * `F4`: reset
+    * `F12`: reload the ROM from disk
+    * `F5` to `F9`: toggle effects
+    */
+    pub fn key_down(&mut self, key: Key, keypad: &mut [bool; 16]) -> Option<Response> {
+        if self.menu.open {
+            let action = self.menu.input(menu_key(key)?)?;
+            return self.perform(action);
+        }
+
+        if key == Key::Escape {
+            self.menu.show();
+            keypad.fill(false);
+        } else if key == Key::Function(1) {
+            self.inspector.visible = !self.inspector.visible;
+            return Some(Response::InspectorToggled);
+        } else if key == Key::Function(3) {
+            self.osd.stats_visible = !self.osd.stats_visible;
+        } else if key == Key::Function(4) {
+            return Some(Response::Event(FrontendEvent::Reset));
+        } else if key == Key::Function(12) {
+            return Some(Response::Event(FrontendEvent::HardReset));
+        } else if let Some(index) = self.keypad_key(key) {
+            keypad[index] = true;
+        } else if let Some(command) = display_command(key) {
+            let message = self.renderer.handle(command);
+            self.osd.show(&message, Instant::now());
+        }
+        None
+    }
+
+
+    /**
+    A file dropped on the window replaces the running ROM.
+    */
+    pub fn drop_file(&mut self, path: &str) -> FrontendEvent {
+        self.menu.open = false;
+        FrontendEvent::Load(path.to_string())
+    }
+
+
+    pub fn key_up(&mut self, key: Key, keypad: &mut [bool; 16]) {
+        if let Some(index) = self.keypad_key(key) {
+            keypad[index] = false;
+        }
+    }
+
+
+    fn perform(&mut self, action: MenuAction) -> Option<Response> {
+        let event = match action {
+            MenuAction::Resume => return None,
+            MenuAction::Quit => FrontendEvent::Quit,
+            MenuAction::Load(path) => FrontendEvent::Load(path),
+            MenuAction::Reset => FrontendEvent::Reset,
+            MenuAction::HardReset => FrontendEvent::HardReset,
+            MenuAction::SetPlatform(id) => FrontendEvent::SetPlatform(id),
+            MenuAction::SetSpeed(speed) => FrontendEvent::SetSpeed(speed),
+            MenuAction::NewSearch => FrontendEvent::NewSearch,
+            MenuAction::FilterSearch(comparison) => FrontendEvent::FilterSearch(comparison),
+            MenuAction::AddCheat(cheat) => FrontendEvent::AddCheat(cheat),
+            MenuAction::ToggleCheat(index) => FrontendEvent::ToggleCheat(index),
+            MenuAction::Display(command) => {
+                let message = self.renderer.handle(command);
+                self.osd.show(&message, Instant::now());
+                return None;
+            },
+        };
+        Some(Response::Event(event))
+    }
+
+
+    /**
+    See `Frontend::inspect`.
+    */
+    pub fn inspect(&mut self, runner: &Runner) {
+        self.inspector.update(&runner.chip8);
+        self.osd.count_frame(runner.instructions, Instant::now());
+        self.menu.speed = runner.cycles_per_frame;
+        self.overlay.clone_from(&runner.overlay);
+        if self.menu.open {
+            self.menu.cheats.clone_from(&runner.cheats);
+            self.menu.search = runner.search.as_ref().map(|search| SearchView {
+                count: search.candidates().len(),
+                candidates: search.candidates().iter()
+                    .take(SHOWN_CANDIDATES)
+                    .map(|&addr| (addr, runner.chip8.memory()[addr as usize]))
+                    .collect(),
+            });
+        }
+    }
+
+
+    pub fn notify(&mut self, message: &str) {
+        self.osd.show(message, Instant::now());
+    }
+
+
+    /**
+    Renders the next frame with everything that is showing drawn over it.
+    */
+    pub fn render(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT], buffer: &mut [u32], width: usize, height: usize) {
+        self.inspector.render(&mut self.renderer, pixel_array, buffer, width, height);
+        let game_width = self.inspector.game_width(width);
+        self.overlay.draw(buffer, width, game_width, height, &mut self.overlay_text);
+        self.osd.draw(buffer, width, height, Instant::now());
+        if self.menu.open {
+            self.menu.palette.clone_from(&self.renderer.palette.name);
+            self.menu.draw(buffer, width, height);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::palette::Palette;
+    use crate::display::persistence::{FilterMode, Persistence};
+
+    fn ui() -> Ui {
+        let renderer = Renderer::new(Palette::default(), Persistence::new(FilterMode::None), Vec::new());
+        let keymap = Keymap { a: Some(0x5), ..Keymap::default() };
+        Ui::new(renderer, keymap, Menu::new(Vec::new()))
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut ui = ui();
+        let mut keypad = [false; 16];
+
+        assert_eq!(ui.key_down(Key::Keypad(0xC), &mut keypad), None);
+        assert_eq!(ui.key_down(Key::Space, &mut keypad), None);
+        assert!(keypad[0xC] && keypad[0x5]);
+        ui.key_up(Key::Space, &mut keypad);
+        assert!(!keypad[0x5]);
+
+        assert_eq!(ui.key_down(Key::Function(1), &mut keypad), Some(Response::InspectorToggled));
+        assert!(ui.inspector.visible);
+        assert_eq!(ui.key_down(Key::Function(4), &mut keypad), Some(Response::Event(FrontendEvent::Reset)));
+        ui.key_down(Key::Function(2), &mut keypad);
+        assert_ne!(ui.renderer.palette.name, Palette::default().name);
+    }
+
+    #[test]
+    fn test_menu_pauses_and_takes_keys() {
+        let mut ui = ui();
+        let mut keypad = [false; 16];
+        keypad[0xC] = true;
+
+        ui.key_down(Key::Escape, &mut keypad);
+        assert!(ui.menu.open);
+        assert_eq!(keypad, [false; 16]);
+
+        // Arrows move through the menu instead of pressing keys
+        ui.key_down(Key::Up, &mut keypad);
+        assert_eq!(keypad, [false; 16]);
+        assert_eq!(ui.key_down(Key::Enter, &mut keypad), Some(Response::Event(FrontendEvent::Quit)));
+
+        ui.key_down(Key::Escape, &mut keypad);
+        assert!(!ui.menu.open);
+
+        ui.key_down(Key::Escape, &mut keypad);
+        assert_eq!(ui.drop_file("pong.ch8"), FrontendEvent::Load("pong.ch8".to_string()));
+        assert!(!ui.menu.open);
+    }
+}
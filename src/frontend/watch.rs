@@ -0,0 +1,116 @@
+// watch.rs
+
+use crate::library::SOURCE_EXTENSION;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+
+/// How often the files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+
+/**
+Modification time and size of a file, `None` while it doesn't exist.
+*/
+type Stamp = Option<(SystemTime, u64)>;
+
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+
+/**
+Polls a ROM and the Octo source next to it (same name, `.8o` extension) for changes, for
+an edit-run loop while developing a game. A change is only reported once the files have
+stopped changing for a poll, so a ROM isn't reloaded while it is half written.
+*/
+pub struct Watcher {
+    files: Vec<(PathBuf, Stamp)>,
+    last_poll: Instant,
+    /// Something changed at the last poll, waiting for it to settle
+    settling: bool,
+}
+
+impl Watcher {
+    pub fn new(rom_path: &str) -> Self {
+        let rom_path = PathBuf::from(rom_path);
+        let source = rom_path.with_extension(SOURCE_EXTENSION);
+        let mut paths = vec![rom_path];
+        if source != paths[0] {
+            paths.push(source);
+        }
+        Watcher {
+            files: paths.into_iter().map(|path| { let stamp = stamp(&path); (path, stamp) }).collect(),
+            last_poll: Instant::now(),
+            settling: false,
+        }
+    }
+
+
+    /**
+    Checks the files if `POLL_INTERVAL` has passed since the last check.
+
+    # Returns
+    Returns whether they changed and have since settled, so the ROM should be reloaded.
+    */
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_poll) < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = now;
+
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+            if current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+
+        let settled = self.settling && !changed;
+        self.settling = changed;
+        settled
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll() {
+        let directory = std::env::temp_dir().join(format!("octorust-watch-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rom = directory.join("game.ch8");
+        fs::write(&rom, [0x12, 0x00]).unwrap();
+
+        let mut watcher = Watcher::new(rom.to_str().unwrap());
+        let mut now = Instant::now();
+        let mut poll = |watcher: &mut Watcher| {
+            now += POLL_INTERVAL;
+            watcher.poll(now)
+        };
+        assert!(!poll(&mut watcher));
+        // Not checked again before the interval
+        assert!(!watcher.poll(Instant::now() - POLL_INTERVAL));
+
+        // Reported once the change has settled
+        fs::write(&rom, [0x12, 0x00, 0x00, 0xE0]).unwrap();
+        assert!(!poll(&mut watcher));
+        assert!(poll(&mut watcher));
+        assert!(!poll(&mut watcher));
+
+        // The source next to the ROM appearing counts too
+        fs::write(directory.join("game.8o"), ": main").unwrap();
+        assert!(!poll(&mut watcher));
+        assert!(poll(&mut watcher));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
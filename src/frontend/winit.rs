@@ -0,0 +1,210 @@
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::display::inspector::Inspector;
+use crate::frontend::runner::Runner;
+use crate::frontend::ui::{Response, Key, Ui};
+use crate::frontend::{Frontend, FrontendEvent};
+
+use softbuffer::{Context, Surface};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Window, WindowBuilder};
+
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+
+/**
+Same physical layout as the SDL frontend, see `sdl::keypad_index`.
+*/
+pub fn keypad_index(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::Digit1 => Some(0x1),
+        KeyCode::Digit2 => Some(0x2),
+        KeyCode::Digit3 => Some(0x3),
+        KeyCode::Digit4 => Some(0xC),
+        KeyCode::KeyQ => Some(0x4),
+        KeyCode::KeyW => Some(0x5),
+        KeyCode::KeyE => Some(0x6),
+        KeyCode::KeyR => Some(0xD),
+        KeyCode::KeyA => Some(0x7),
+        KeyCode::KeyS => Some(0x8),
+        KeyCode::KeyD => Some(0x9),
+        KeyCode::KeyF => Some(0xE),
+        KeyCode::KeyZ => Some(0xA),
+        KeyCode::KeyX => Some(0x0),
+        KeyCode::KeyC => Some(0xB),
+        KeyCode::KeyV => Some(0xF),
+        _ => None,
+    }
+}
+
+
+/**
+Same keys as the SDL frontend, see `sdl::key`.
+*/
+pub fn key(code: KeyCode) -> Option<Key> {
+    if let Some(index) = keypad_index(code) {
+        return Some(Key::Keypad(index));
+    }
+    match code {
+        KeyCode::ArrowUp => Some(Key::Up),
+        KeyCode::ArrowDown => Some(Key::Down),
+        KeyCode::ArrowLeft => Some(Key::Left),
+        KeyCode::ArrowRight => Some(Key::Right),
+        KeyCode::Space => Some(Key::Space),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Escape => Some(Key::Escape),
+        KeyCode::F1 => Some(Key::Function(1)),
+        KeyCode::F2 => Some(Key::Function(2)),
+        KeyCode::F3 => Some(Key::Function(3)),
+        KeyCode::F4 => Some(Key::Function(4)),
+        KeyCode::F5 => Some(Key::Function(5)),
+        KeyCode::F6 => Some(Key::Function(6)),
+        KeyCode::F7 => Some(Key::Function(7)),
+        KeyCode::F8 => Some(Key::Function(8)),
+        KeyCode::F9 => Some(Key::Function(9)),
+        KeyCode::F10 => Some(Key::Function(10)),
+        KeyCode::F11 => Some(Key::Function(11)),
+        KeyCode::F12 => Some(Key::Function(12)),
+        _ => None,
+    }
+}
+
+
+/**
+Same as `sdl::fit_inspector`.
+*/
+fn fit_inspector(inspector: &Inspector, window: &Window) {
+    let size = window.inner_size();
+    let panel = inspector.width() as u32;
+    let width = if inspector.visible { size.width + panel } else { size.width.saturating_sub(panel).max(WIDTH as u32) };
+    let _ = window.request_inner_size(PhysicalSize::new(width, size.height));
+}
+
+
+/**
+Windowed frontend that draws on the CPU into a softbuffer surface, so it runs without
+SDL2 or a GPU. The window can be resized freely; the image keeps an integer scale and
+is letterboxed to preserve the 2:1 aspect ratio.
+*/
+pub struct Winit {
+    pub ui: Ui,
+    event_loop: EventLoop<()>,
+    // Declared before `window` so it's dropped first
+    surface: Surface,
+    _context: Context,
+    window: Window,
+}
+
+impl Winit {
+    /**
+    Opens a window `scale` times the CHIP-8 resolution.
+    */
+    pub fn new(ui: Ui, scale: u32) -> Result<Self, String> {
+        let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+        let window = WindowBuilder::new()
+            .with_title("Octorust Chip8 Interpreter!")
+            .with_inner_size(LogicalSize::new(WIDTH as u32 * scale, HEIGHT as u32 * scale))
+            .with_min_inner_size(LogicalSize::new(WIDTH as u32, HEIGHT as u32))
+            .build(&event_loop)
+            .map_err(|e| e.to_string())?;
+
+        // SAFETY: `window` outlives both the context and the surface, they are dropped first
+        let context = unsafe { Context::new(&window) }.map_err(|e| e.to_string())?;
+        let surface = unsafe { Surface::new(&context, &window) }.map_err(|e| e.to_string())?;
+
+        Ok(Winit { ui, event_loop, surface, _context: context, window })
+    }
+}
+
+impl Frontend for Winit {
+    fn present(&mut self, pixel_array: &[[bool; WIDTH]; HEIGHT]) {
+        let size = self.window.inner_size();
+        let (Some(width), Some(height)) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) else {
+            // Minimized
+            return;
+        };
+
+        if let Err(e) = self.surface.resize(width, height) {
+            eprintln!("Error resizing surface: {}", e);
+            return;
+        }
+
+        let mut buffer = match self.surface.buffer_mut() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                eprintln!("Error getting surface buffer: {}", e);
+                return;
+            }
+        };
+        self.ui.render(pixel_array, &mut buffer, width.get() as usize, height.get() as usize);
+
+        if let Err(e) = buffer.present() {
+            eprintln!("Error presenting frame: {}", e);
+        }
+    }
+
+
+    fn poll_input(&mut self, keypad: &mut [bool; 16]) -> FrontendEvent {
+        let mut result = FrontendEvent::Continue;
+        let ui = &mut self.ui;
+        let window = &self.window;
+
+        let status = self.event_loop.pump_events(Some(Duration::ZERO), |event, _| {
+            let Event::WindowEvent { event, .. } = event else {
+                return;
+            };
+
+            match event {
+                WindowEvent::CloseRequested => result = FrontendEvent::Quit,
+                WindowEvent::DroppedFile(path) => result = ui.drop_file(&path.to_string_lossy()),
+                WindowEvent::KeyboardInput {
+                    event: KeyEvent { physical_key: PhysicalKey::Code(code), state, .. }, ..
+                } => {
+                    let Some(key) = key(code) else {
+                        return;
+                    };
+                    if state == ElementState::Released {
+                        ui.key_up(key, keypad);
+                        return;
+                    }
+                    match ui.key_down(key, keypad) {
+                        Some(Response::Event(event)) => result = event,
+                        Some(Response::InspectorToggled) => fit_inspector(&ui.inspector, window),
+                        None => {},
+                    }
+                },
+                _ => {}
+            }
+        });
+
+        if matches!(status, PumpStatus::Exit(_)) {
+            FrontendEvent::Quit
+        } else {
+            result
+        }
+    }
+
+
+    fn inspect(&mut self, runner: &Runner) {
+        self.ui.inspect(runner);
+    }
+
+
+    fn paused(&self) -> bool {
+        self.ui.menu.open
+    }
+
+
+    fn loaded(&mut self, rom_path: &str, platform: Option<&str>, recent: &[String]) {
+        self.ui.menu.loaded(rom_path, platform, recent);
+    }
+
+
+    fn notify(&mut self, message: &str) {
+        self.ui.notify(message);
+    }
+}
@@ -0,0 +1,21 @@
+// lib.rs
+//
+// The emulator as a library: the core, shared with the WebAssembly build, and everything
+// the binary drives it with, so other programs can too, e.g. training agents with `rl`.
+
+#[allow(clippy::module_inception)]
+pub mod chip8;
+
+pub mod cheat;
+pub mod config;
+pub mod control;
+pub mod debug;
+pub mod display;
+pub mod frontend;
+pub mod library;
+pub mod rl;
+pub mod rom_db;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod session;
+pub mod web;
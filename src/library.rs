@@ -0,0 +1,224 @@
+// library.rs
+
+use crate::chip8::constants::{MEMORY_SIZE, ROM_OFFSET};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+/// File extensions of ROMs and Octo sources listed by the ROM browser
+pub const ROM_EXTENSIONS: [&str; 4] = ["ch8", "sc8", "xo8", "8o"];
+
+/// Octo assembly source, which has to be assembled into a ROM before it can run
+pub const SOURCE_EXTENSION: &str = "8o";
+
+/// ROMs remembered by `RecentRoms`
+const MAX_RECENT: usize = 10;
+
+
+/**
+A ROM file as listed by the ROM browser.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomFile {
+    pub path: PathBuf,
+    pub name: String,
+    /// In bytes
+    pub size: u64,
+    /// Guessed from the opcodes, see `guess_platform`
+    pub platform: &'static str,
+}
+
+
+/**
+The ROMs and Octo sources in `directory`, sorted by name. Unreadable directories and
+files are skipped.
+*/
+pub fn scan(directory: &Path) -> Vec<RomFile> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut roms: Vec<RomFile> = entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && extension(path).is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.as_str())))
+        .filter_map(|path| {
+            let bytes = fs::read(&path).ok()?;
+            let platform = if is_source(&path) { "Octo source" } else { guess_platform(&bytes) };
+            Some(RomFile {
+                name: path.file_name()?.to_string_lossy().into_owned(),
+                size: bytes.len() as u64,
+                platform,
+                path,
+            })
+        })
+        .collect();
+    roms.sort_by_key(|rom| rom.name.to_lowercase());
+    roms
+}
+
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase)
+}
+
+
+/**
+Whether `path` is an Octo source rather than a ROM.
+*/
+pub fn is_source(path: &Path) -> bool {
+    extension(path).as_deref() == Some(SOURCE_EXTENSION)
+}
+
+
+/**
+Guesses which platform a ROM was written for from the instructions it contains, checking
+every aligned pair of bytes. Sprite data can look like instructions too, so this is only
+a hint for ROMs the database doesn't know.
+
+# Returns
+Returns `XO-CHIP` for ROMs using its instructions or too large for the other platforms,
+`SUPER-CHIP` for ROMs using high resolution, scrolling or its flag registers, else `CHIP-8`.
+*/
+pub fn guess_platform(rom: &[u8]) -> &'static str {
+    if rom.len() > MEMORY_SIZE - ROM_OFFSET as usize {
+        return "XO-CHIP";
+    }
+
+    let mut superchip = false;
+    for pair in rom.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([pair[0], pair[1]]);
+        let (n, nn) = (opcode & 0xF, opcode & 0xFF);
+        match opcode >> 12 {
+            // Scroll up, long I, select plane, audio pattern, save/load register ranges
+            0x0 if opcode & 0xFFF0 == 0x00D0 => return "XO-CHIP",
+            0xF if opcode == 0xF000 || opcode == 0xF002 || nn == 0x01 || nn == 0x3A => return "XO-CHIP",
+            0x5 if n == 2 || n == 3 => return "XO-CHIP",
+            // Scroll down/right/left, exit, low and high resolution
+            0x0 if opcode & 0xFFF0 == 0x00C0 || (0x00FB..=0x00FF).contains(&opcode) => superchip = true,
+            // Large font, flag registers
+            0xF if nn == 0x30 || nn == 0x75 || nn == 0x85 => superchip = true,
+            // 16x16 sprites
+            0xD if n == 0 => superchip = true,
+            _ => {},
+        }
+    }
+    if superchip { "SUPER-CHIP" } else { "CHIP-8" }
+}
+
+
+/**
+The ROMs run last, most recent first, kept in a text file with one path per line.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentRoms {
+    pub paths: Vec<String>,
+}
+
+impl RecentRoms {
+    /**
+    Reads the list at `path`. A missing or unreadable file is an empty list.
+    */
+    pub fn load(path: &Path) -> Self {
+        let paths = fs::read_to_string(path).unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .take(MAX_RECENT)
+            .map(str::to_string)
+            .collect();
+        RecentRoms { paths }
+    }
+
+
+    /**
+    Moves `rom_path` to the top of the list, as an absolute path when it exists.
+    */
+    pub fn push(&mut self, rom_path: &str) {
+        let rom_path = fs::canonicalize(rom_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| rom_path.to_string());
+        self.paths.retain(|path| *path != rom_path);
+        self.paths.insert(0, rom_path);
+        self.paths.truncate(MAX_RECENT);
+    }
+
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+        }
+        let contents: String = self.paths.iter().map(|path| format!("{}\n", path)).collect();
+        fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+
+/**
+`recent.txt` next to the config file.
+*/
+pub fn default_recent_path() -> Option<PathBuf> {
+    crate::config::default_path().and_then(|path| path.parent().map(|dir| dir.join("recent.txt")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("octorust-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_guess_platform() {
+        assert_eq!(guess_platform(&[0x00, 0xE0, 0xD0, 0x15, 0x12, 0x00]), "CHIP-8");
+        assert_eq!(guess_platform(&[0x00, 0xFF, 0xD0, 0x10]), "SUPER-CHIP");
+        assert_eq!(guess_platform(&[0xF0, 0x85]), "SUPER-CHIP");
+        assert_eq!(guess_platform(&[0x00, 0xFF, 0xF0, 0x00, 0x12, 0x34]), "XO-CHIP");
+        assert_eq!(guess_platform(&[0x52, 0x32]), "XO-CHIP");
+        assert_eq!(guess_platform(&vec![0; 4000]), "XO-CHIP");
+        // Misaligned opcodes aren't seen
+        assert_eq!(guess_platform(&[0x12, 0x00, 0xFF, 0x00]), "CHIP-8");
+    }
+
+    #[test]
+    fn test_scan() {
+        let directory = temp_dir("scan");
+        fs::write(directory.join("b.ch8"), [0x00, 0xFF]).unwrap();
+        fs::write(directory.join("A.xo8"), [0x00, 0xE0, 0xF0, 0x01]).unwrap();
+        fs::write(directory.join("game.8o"), ": main\n  loop again").unwrap();
+        fs::write(directory.join("notes.txt"), "").unwrap();
+        let roms = scan(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let listed: Vec<_> = roms.iter().map(|rom| (rom.name.as_str(), rom.size, rom.platform)).collect();
+        assert_eq!(listed, [("A.xo8", 4, "XO-CHIP"), ("b.ch8", 2, "SUPER-CHIP"), ("game.8o", 19, "Octo source")]);
+    }
+
+    #[test]
+    fn test_recent_roms() {
+        let directory = temp_dir("recent");
+        let rom = directory.join("pong.ch8");
+        fs::write(&rom, [0x12, 0x00]).unwrap();
+        let file = directory.join("config").join("recent.txt");
+
+        let mut recent = RecentRoms::load(&file);
+        assert!(recent.paths.is_empty());
+        for i in 0..MAX_RECENT {
+            recent.push(&format!("missing{}.ch8", i));
+        }
+        recent.push(rom.to_str().unwrap());
+        recent.push("missing3.ch8");
+        recent.save(&file).unwrap();
+
+        let loaded = RecentRoms::load(&file);
+        let rom = fs::canonicalize(&rom).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(loaded.paths.len(), MAX_RECENT);
+        assert_eq!(loaded.paths[0], "missing3.ch8");
+        assert_eq!(loaded.paths[1], rom.to_string_lossy());
+        assert!(!loaded.paths.contains(&"missing0.ch8".to_string()));
+    }
+}
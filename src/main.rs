@@ -1,92 +1,474 @@
-mod chip8;
-
-use chip8::chip8::Chip8;
-
-use sdl2::rect::Rect;
-
-use std::time::Duration;
+mod cli;
 
+use octorust::{cheat, chip8, config, control, debug, display, frontend, library, rl, rom_db, session};
+#[cfg(feature = "scripting")]
+use octorust::script;
 
-extern crate sdl2;
-
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use cheat::cheats::{Cheat, CheatFile};
+use chip8::chip8::Chip8;
+use chip8::recompiler::Recompiler;
+use chip8::trace::Tracer;
+use control::server::ControlServer;
+use debug::control_flow::ControlFlow;
+use debug::coverage::Coverage;
+use debug::gdb::GdbStub;
+use debug::profiler::Profiler;
 
+use cli::FrontendKind;
+use config::{Config, Settings};
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use display::menu::Menu;
+use display::persistence::Persistence;
+use display::renderer::Renderer;
+use frontend::{Frontend, FrontendEvent};
+use frontend::runner::Runner;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use frontend::ui::Ui;
+use frontend::watch::Watcher;
+use library::RecentRoms;
+use rl::batch::BatchEnv;
+use rl::env::Env;
+use frontend::headless::Headless;
+use frontend::terminal::Terminal;
+use rom_db::RomDatabase;
+use session::Session;
 
-use chip8::constants::{
-    WIDTH,
-    HEIGHT,
-    SCALE_FACTOR,
-    PADDING,
-};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
 
-const SCREEN_WIDTH: u32 = (WIDTH * SCALE_FACTOR as usize) as u32;
-const SCREEN_HEIGHT: u32  = (HEIGHT * SCALE_FACTOR as usize) as u32;
 
 fn main() {
     println!("Octorust  Copyright (C) 2023 https://github.com/esfacumon/
     This program comes with ABSOLUTELY NO WARRANTY.
     This is free software, and you are welcome to redistribute it
     under certain conditions.");
-    let mut chip8 = Chip8::new();
 
-    let sdl_context = sdl2::init().expect("Init SDL2 error");
-    let video_subsystem = sdl_context.video().expect("Init video subsystem error");
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprintln!("{}", cli::USAGE);
+            exit(2);
+        }
+    };
+
+    let config = Config::load(options.config_path.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Error reading config: {}", e);
+        exit(1);
+    });
+
+    let mut rom_db = RomDatabase::embedded();
+    if let Err(e) = rom_db.add_local(options.rom_db_path.as_deref()) {
+        eprintln!("Error reading ROM database: {}", e);
+        exit(1);
+    }
+
+    let session = Session { config, rom_db, overrides: options.overrides.clone() };
+    let loaded = session.load(&options.rom_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    let (mut chip8, settings, mut sha1, detected) = (loaded.chip8, loaded.settings, loaded.sha1, loaded.detected);
+
+    if options.print_config {
+        println!("# rom = \"{}\"", options.rom_path);
+        println!("# sha1 = \"{}\"", sha1);
+        if let Some(rom) = &detected {
+            println!("# detected = \"{}\"", rom.title);
+        }
+        print!("{}", settings.to_toml());
+        return;
+    }
+    if let Some(path) = &options.cfg_path {
+        let flow = ControlFlow::analyse(chip8.rom());
+        write_output(path, &flow.dot());
+        // Keep the summary out of the graph when that goes to stdout
+        if path == "-" {
+            eprint!("{}", flow.summary());
+        } else {
+            print!("{}", flow.summary());
+        }
+        return;
+    }
+    if let Some(rom) = &detected {
+        println!("Detected {} ({})", rom.title, rom.platform.name);
+    }
 
-    let window = video_subsystem.window("Octorust Chip8 Interpreter!", SCREEN_WIDTH, SCREEN_HEIGHT)
-        .position_centered()
-        .build()
-        .expect("Window init error");
+    if let Some(path) = &options.trace_path {
+        let tracer = Tracer::create(path, options.trace_filter.clone()).unwrap_or_else(|e| {
+            eprintln!("Error creating trace {}: {}", path, e);
+            exit(1);
+        });
+        chip8.tracer = Some(tracer);
+    }
+    if options.recompiler {
+        chip8.recompiler = Some(Recompiler::new());
+    }
+    let renderer = Renderer::new(settings.palette.clone(), Persistence::new(settings.filter), settings.effects.clone());
 
-    let mut canvas = window.into_canvas().build()
-            .expect("Canvas build error");
+    let mut runner = Runner::new(chip8);
+    runner.max_frames = options.frames;
+    runner.cycles_per_frame = settings.speed;
 
+    if options.profile_path.is_some() || options.profile_folded_path.is_some() {
+        runner.profiler = Some(Profiler::new());
+    }
+    if options.coverage_path.is_some() || options.coverage_map_path.is_some() {
+        runner.coverage = Some(Coverage::new());
+    }
 
-    let mut event_pump = sdl_context.event_pump().expect("Event pump error");
+    if let Some(path) = &options.diff_path {
+        exit(run_diff(&mut runner, path));
+    }
+    if let Some(instructions) = options.benchmark {
+        exit(run_benchmark(&session, &options.rom_path, settings.speed, instructions));
+    }
+    if let Some(episodes) = options.rl_episodes {
+        exit(run_random_agent(&runner.chip8, settings.speed, &options, episodes));
+    }
+    if let Some(port) = options.server_port {
+        let server = ControlServer::bind(port).unwrap_or_else(|e| {
+            eprintln!("Error starting the control server on port {}: {}", port, e);
+            exit(1);
+        });
+        // Port 0 picks a free one
+        println!("Control server listening on 127.0.0.1:{}", server.port().unwrap_or(port));
+        runner.server = Some(server);
+    }
+    if let Some(path) = &options.script_path {
+        load_script(&mut runner, path);
+    }
+    if let Some(port) = options.gdb_port {
+        runner.gdb = Some(wait_for_gdb(port).unwrap_or_else(|e| {
+            eprintln!("Error waiting for GDB on port {}: {}", port, e);
+            exit(1);
+        }));
+    }
 
+    let cheats_path = cheat::cheats::default_path();
+    let mut cheat_file = match cheats_path.as_deref().map(CheatFile::load).transpose() {
+        Ok(file) => file.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error reading cheats: {}", e);
+            exit(1);
+        },
+    };
+    runner.cheats = cheat_file.cheats(&sha1);
+
+    let mut frontend: Box<dyn Frontend> = match options.frontend {
+        FrontendKind::Sdl => sdl_frontend(renderer, &settings, &session),
+        FrontendKind::Winit => winit_frontend(renderer, &settings, &session),
+        FrontendKind::Terminal => Box::new(Terminal::new(renderer, settings.audio)),
+        FrontendKind::Headless => Box::new(Headless::new()),
+    };
+
+    // Only ROMs played in a window are worth remembering, not batch runs
+    let recent_path = library::default_recent_path()
+        .filter(|_| matches!(options.frontend, FrontendKind::Sdl | FrontendKind::Winit));
+    let mut recent = recent_path.as_deref().map(RecentRoms::load).unwrap_or_default();
+    let mut rom_path = options.rom_path.clone();
+    remember(&mut recent, recent_path.as_deref(), &rom_path);
+    frontend.loaded(&rom_path, settings.platform.as_deref(), &recent.paths);
+
+    if options.watch {
+        runner.watcher = Some(Watcher::new(&rom_path));
+    }
     loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    // break 'running
+        let (path, verb) = match runner.run(frontend.as_mut()) {
+            FrontendEvent::Load(path) => (path, "Loaded"),
+            FrontendEvent::HardReset => (rom_path.clone(), "Reloaded"),
+            FrontendEvent::SetPlatform(id) => match session.settings(&rom_path, &sha1, Some(&id)) {
+                Ok(settings) => {
+                    runner.chip8.quirks = settings.quirks;
+                    runner.cycles_per_frame = settings.speed;
+                    let name = session.rom_db.platform(&id).map_or(id.as_str(), |platform| platform.name.as_str());
+                    frontend.notify(&format!("Platform: {} quirks, speed {}", name, settings.speed));
+                    frontend.loaded(&rom_path, Some(&id), &recent.paths);
+                    continue;
                 },
-                _ => {}
-            }
+                Err(e) => {
+                    frontend.notify(&e);
+                    continue;
+                },
+            },
+            FrontendEvent::AddCheat(cheat) => {
+                frontend.notify(&format!("Cheat {} on", cheat.code));
+                runner.cheats.push(cheat);
+                save_cheats(&mut cheat_file, cheats_path.as_deref(), &sha1, &runner.cheats);
+                continue;
+            },
+            FrontendEvent::ToggleCheat(index) => {
+                if let Some(cheat) = runner.cheats.get_mut(index) {
+                    cheat.enabled = !cheat.enabled;
+                    frontend.notify(&format!("Cheat {} {}", cheat.name, if cheat.enabled { "on" } else { "off" }));
+                    save_cheats(&mut cheat_file, cheats_path.as_deref(), &sha1, &runner.cheats);
+                }
+                continue;
+            },
+            _ => break,
+        };
+
+        match session.load(&path) {
+            Ok(loaded) => {
+                runner.replace(loaded.chip8);
+                runner.cheats = cheat_file.cheats(&loaded.sha1);
+                runner.cycles_per_frame = loaded.settings.speed;
+                if options.watch && path != rom_path {
+                    runner.watcher = Some(Watcher::new(&path));
+                }
+                let title = loaded.detected.map_or_else(|| path.clone(), |rom| rom.title);
+                frontend.notify(&format!("{} {}", verb, title));
+                remember(&mut recent, recent_path.as_deref(), &path);
+                frontend.loaded(&path, loaded.settings.platform.as_deref(), &recent.paths);
+                rom_path = path;
+                sha1 = loaded.sha1;
+            },
+            Err(e) => frontend.notify(&e),
         }
+    }
+    drop(frontend);
 
-        chip8.cycle();
+    if let Some(profiler) = &runner.profiler {
+        write_profile(profiler, runner.chip8.memory(), &options);
+    }
+    if let Some(coverage) = &runner.coverage {
+        write_coverage(coverage, runner.chip8.memory(), &options);
+    }
+}
 
-        render(&chip8, &mut canvas);
 
-        ::std::thread::sleep(Duration::new(0, 10_000_000u32 / 60) * 60);
+/**
+Adds `rom_path` to the recent ROMs and saves them to `path`, if any.
+*/
+fn remember(recent: &mut RecentRoms, path: Option<&Path>, rom_path: &str) {
+    let Some(path) = path else {
+        return;
+    };
+    recent.push(rom_path);
+    if let Err(e) = recent.save(path) {
+        eprintln!("Error saving recent ROMs: {}", e);
     }
 }
 
+
 /**
-Updates screen with pixel_array values
- */
-fn render(chip8: &Chip8, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
-    canvas.clear();
-    for (j, row) in chip8.pixel_array.iter().enumerate() {
-        for (i, &pixel) in row.iter().enumerate() {
-            let x = i as u32;
-            let y = j as u32;
-
-            if pixel {
-                canvas.set_draw_color(Color::RGB(203, 216, 245));
-            } else {
-                canvas.set_draw_color(Color::RGB(55, 74, 117));
-            }
+Stores the running ROM's cheats and saves them all to `path`, if any.
+*/
+fn save_cheats(file: &mut CheatFile, path: Option<&Path>, sha1: &str, cheats: &[Cheat]) {
+    let Some(path) = path else {
+        return;
+    };
+    file.set(sha1, cheats);
+    if let Err(e) = file.save(path) {
+        eprintln!("Error saving cheats: {}", e);
+    }
+}
+
+
+fn write_profile(profiler: &Profiler, memory: &[u8], options: &cli::Options) {
+    if let Some(path) = &options.profile_path {
+        write_output(path, &profiler.report(memory));
+    }
+    if let Some(path) = &options.profile_folded_path {
+        write_output(path, &profiler.folded());
+    }
+}
+
+
+fn write_coverage(coverage: &Coverage, memory: &[u8], options: &cli::Options) {
+    if let Some(path) = &options.coverage_path {
+        write_output(path, &coverage.hex_dump(memory));
+    }
+    if let Some(path) = &options.coverage_map_path {
+        write_output(path, &(coverage.map(memory) + "\n"));
+    }
+}
+
 
-            let rect = Rect::new(
-                PADDING as i32 + (x as i32 * SCALE_FACTOR as i32),
-                PADDING as i32 + (y as i32 * SCALE_FACTOR as i32),
-                (SCALE_FACTOR - PADDING) as u32,
-                (SCALE_FACTOR - PADDING) as u32);
-            canvas.fill_rect(rect).expect("Error rendering pixel");
+/**
+Writes `contents` to the file at `path`, or to stdout if it is `-`.
+*/
+fn write_output(path: &str, contents: &str) {
+    if path == "-" {
+        print!("{}", contents);
+    } else if let Err(e) = std::fs::write(path, contents) {
+        eprintln!("Error writing {}: {}", path, e);
+    }
+}
+
+
+fn wait_for_gdb(port: u16) -> std::io::Result<GdbStub> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on 127.0.0.1:{}", port);
+    let stub = GdbStub::accept(&listener)?;
+    println!("GDB connected");
+    Ok(stub)
+}
+
+
+/**
+Runs the ROM against the reference trace at `path`, see `debug::reference::State`.
+
+# Returns
+Returns the exit code: 0 if the whole trace matched, 1 on a divergence or error.
+*/
+fn run_diff(runner: &mut Runner, path: &str) -> i32 {
+    let reference = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| debug::reference::parse_reference(&text))
+        .map_err(|e| eprintln!("Error reading reference trace {}: {}", path, e));
+    let Ok(reference) = reference else {
+        return 1;
+    };
+
+    match debug::reference::compare(runner, &reference) {
+        Ok(matched) => {
+            println!("Matched all {} states of {}", matched, path);
+            0
+        },
+        Err(divergence) => {
+            print!("{}", divergence);
+            1
+        },
+    }
+}
+
+
+/// How to set up a freshly loaded machine for one of the benchmarked backends
+type Backend = (&'static str, fn(&mut Chip8));
+
+
+/**
+Runs the ROM uncapped with each execution backend: decoding every instruction, with the
+decode cache and recompiled. Prints how many instructions per second each managed.
+
+# Returns
+Returns the exit code.
+*/
+fn run_benchmark(session: &Session, rom_path: &str, speed: u32, instructions: u64) -> i32 {
+    let backends: [Backend; 3] = [
+        ("Interpreter", |chip8| chip8.decode_cache = None),
+        ("Decode cache", |_| {}),
+        ("Recompiler", |chip8| chip8.recompiler = Some(Recompiler::new())),
+    ];
+
+    let mut measurements = Vec::new();
+    for (name, setup) in backends {
+        let mut chip8 = match session.load(rom_path) {
+            Ok(loaded) => loaded.chip8,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            },
+        };
+        setup(&mut chip8);
+        measurements.push((name, debug::benchmark::measure(chip8, speed, instructions)));
+    }
+    print!("{}", debug::benchmark::report(&measurements));
+    0
+}
+
+
+/**
+Plays episodes of the ROM as a reinforcement learning environment with random actions,
+printing each one's reward and length, to try out a task before training on it.
+
+# Returns
+Returns the exit code.
+*/
+fn run_random_agent(chip8: &Chip8, speed: u32, options: &cli::Options, episodes: usize) -> i32 {
+    let mut task = options.task.clone();
+    if let Some(frames) = options.frames {
+        task.max_frames = frames;
+    }
+    let mut envs = Vec::new();
+    for _ in 0..options.rl_envs.max(1) {
+        let mut instance = Chip8::new();
+        instance.quirks = chip8.quirks;
+        if let Err(e) = instance.load_rom_bytes(chip8.rom()) {
+            eprintln!("{}", e);
+            return 1;
         }
+        if options.recompiler {
+            instance.recompiler = Some(Recompiler::new());
+        }
+        envs.push(Env::new(instance, speed, task.clone()));
+    }
+    let threads = options.rl_threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()));
+    let mut batch = BatchEnv::new(envs, threads);
+
+    let start = Instant::now();
+    let played = rl::batch::play_random(&mut batch, episodes, options.rl_seed);
+    let elapsed = start.elapsed();
+
+    println!("{:>8} {:>10} {:>8}", "Episode", "Reward", "Frames");
+    for (i, episode) in played.iter().enumerate() {
+        println!("{:>8} {:>10.1} {:>8}", i + 1, episode.reward, episode.frames);
+    }
+    let frames: u64 = played.iter().map(|episode| episode.frames).sum();
+    let mean = played.iter().map(|episode| episode.reward).sum::<f32>() / played.len().max(1) as f32;
+    println!("Mean reward {:.2} over {} episodes, {:.0} frames/s on {} threads",
+             mean, played.len(), frames as f64 / elapsed.as_secs_f64(), threads);
+    0
+}
+
+
+#[cfg(feature = "scripting")]
+fn load_script(runner: &mut Runner, path: &str) {
+    match script::Script::load(path, &mut runner.chip8) {
+        Ok(script) => runner.script = Some(script),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        },
     }
-    canvas.present();
+}
+
+
+#[cfg(not(feature = "scripting"))]
+fn load_script(_runner: &mut Runner, _path: &str) {
+    eprintln!("Octorust was built without scripting support, rebuild with --features scripting");
+    exit(1);
+}
+
+
+/**
+The UI shared by the windowed frontends: the screen, inspector, messages and pause menu.
+*/
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn window_ui(renderer: Renderer, settings: &Settings, session: &Session) -> Ui {
+    Ui::new(renderer, settings.keymap, Menu::new(session.platforms()))
+}
+
+
+#[cfg(feature = "sdl")]
+fn sdl_frontend(renderer: Renderer, settings: &Settings, session: &Session) -> Box<dyn Frontend> {
+    let ui = window_ui(renderer, settings, session);
+    Box::new(frontend::sdl::Sdl::new(ui, settings.scale, settings.audio).expect("Init SDL2 error"))
+}
+
+
+#[cfg(not(feature = "sdl"))]
+fn sdl_frontend(_renderer: Renderer, _settings: &Settings, _session: &Session) -> Box<dyn Frontend> {
+    eprintln!("Octorust was built without SDL support, use --frontend winit, terminal or headless");
+    exit(1);
+}
+
+
+#[cfg(feature = "winit")]
+fn winit_frontend(renderer: Renderer, settings: &Settings, session: &Session) -> Box<dyn Frontend> {
+    let ui = window_ui(renderer, settings, session);
+    Box::new(frontend::winit::Winit::new(ui, settings.scale).expect("Init winit error"))
+}
+
+
+#[cfg(not(feature = "winit"))]
+fn winit_frontend(_renderer: Renderer, _settings: &Settings, _session: &Session) -> Box<dyn Frontend> {
+    eprintln!("Octorust was built without winit support, use another --frontend");
+    exit(1);
 }
@@ -0,0 +1,204 @@
+// batch.rs
+
+use crate::rl::env::{Env, Observation};
+
+use std::thread;
+
+
+/**
+Many environments stepped together, split across `threads` threads, as training loops
+that collect experience from several episodes at once want. An environment whose episode
+ended is reset before its next step, so the batch never needs resetting one by one.
+*/
+pub struct BatchEnv {
+    envs: Vec<Env>,
+    /// Whether each environment's episode ended on its last step
+    done: Vec<bool>,
+    threads: usize,
+}
+
+impl BatchEnv {
+    pub fn new(envs: Vec<Env>, threads: usize) -> Self {
+        let done = vec![false; envs.len()];
+        BatchEnv { envs, done, threads: threads.max(1) }
+    }
+
+
+    pub fn reset(&mut self) -> Vec<Observation> {
+        self.done.fill(false);
+        self.map(|env, ()| env.reset(), &[])
+    }
+
+
+    /**
+    Steps each environment with its action in `actions`.
+
+    # Returns
+    Returns what `Env::step` returned for each, in order.
+    */
+    pub fn step(&mut self, actions: &[usize]) -> Vec<(Observation, f32, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per environment");
+        let done = std::mem::take(&mut self.done);
+        let steps = self.map(|env, (action, done)| {
+            if done {
+                env.reset();
+            }
+            env.step(action)
+        }, &actions.iter().copied().zip(done).collect::<Vec<_>>());
+        self.done = steps.iter().map(|&(_, _, done)| done).collect();
+        steps
+    }
+
+
+    /**
+    Runs `f` on every environment with its argument, each thread taking a contiguous share
+    of them.
+    */
+    fn map<A, R, F>(&mut self, f: F, arguments: &[A]) -> Vec<R>
+    where
+        A: Copy + Default + Sync,
+        R: Send,
+        F: Fn(&mut Env, A) -> R + Sync,
+    {
+        let share = self.envs.len().div_ceil(self.threads).max(1);
+        let f = &f;
+        thread::scope(|scope| {
+            let handles: Vec<_> = self.envs.chunks_mut(share).enumerate().map(|(chunk, envs)| {
+                scope.spawn(move || {
+                    envs.iter_mut().enumerate().map(|(i, env)| {
+                        f(env, arguments.get(chunk * share + i).copied().unwrap_or_default())
+                    }).collect::<Vec<R>>()
+                })
+            }).collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+}
+
+
+/**
+A fast pseudo-random generator (xorshift64*), good enough to pick actions.
+*/
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero would stay zero forever
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize % bound.max(1)
+    }
+}
+
+
+/**
+An episode as played by `play_random`.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Episode {
+    pub reward: f32,
+    pub frames: u64,
+}
+
+
+/**
+Plays `episodes` episodes across the batch pressing random actions, a baseline to check
+a task's rewards and end conditions before training on it.
+
+# Returns
+Returns the episodes in the order they ended.
+*/
+pub fn play_random(batch: &mut BatchEnv, episodes: usize, seed: u64) -> Vec<Episode> {
+    let mut rng = Rng::new(seed);
+    let mut rewards = vec![0.0; batch.envs.len()];
+    let mut finished = Vec::new();
+    batch.reset();
+
+    while finished.len() < episodes {
+        let actions: Vec<usize> = batch.envs.iter().map(|env| rng.below(env.task().actions.len())).collect();
+        for (i, (_, reward, done)) in batch.step(&actions).into_iter().enumerate() {
+            rewards[i] += reward;
+            if done {
+                finished.push(Episode { reward: rewards[i], frames: batch.envs[i].frames() });
+                rewards[i] = 0.0;
+            }
+        }
+    }
+    finished.truncate(episodes);
+    finished
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+    use crate::rl::task::{Reward, Task};
+
+    /// Counts frames into V0 and stores it at 0x300 with `LD [I], V0`, waiting for
+    /// vblank with a draw, and halts when the key of V0's low nibble is pressed at the
+    /// start of a frame
+    const ROM: [u8; 16] = [
+        0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0xA3, 0x10, 0xD1, 0x11, 0xE0, 0x9E, 0x12, 0x00, 0x12, 0x0E,
+    ];
+
+    fn batch(count: usize, threads: usize) -> BatchEnv {
+        let envs = (0..count).map(|_| {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom_bytes(&ROM).unwrap();
+            chip8.quirks.vblank = true;
+            let mut task = Task::new();
+            task.rewards = vec![Reward::parse("byte:300").unwrap()];
+            task.max_frames = 100;
+            Env::new(chip8, 10, task)
+        }).collect();
+        BatchEnv::new(envs, threads)
+    }
+
+    #[test]
+    fn test_batch_matches_single_thread() {
+        let mut threaded = batch(5, 3);
+        let mut single = batch(5, 1);
+        assert_eq!(threaded.reset(), single.reset());
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let actions: Vec<usize> = (0..5).map(|_| rng.below(17)).collect();
+            assert_eq!(threaded.step(&actions), single.step(&actions));
+        }
+    }
+
+    #[test]
+    fn test_fault_ends_the_episode() {
+        let mut batch = batch(3, 2);
+        // RET with an empty stack after a frame
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0xA3, 0x10, 0xD1, 0x11, 0x00, 0xEE]).unwrap();
+        chip8.quirks.vblank = true;
+        batch.envs[1] = Env::new(chip8, 10, batch.envs[1].task().clone());
+        batch.reset();
+
+        let done = |steps: Vec<(Observation, f32, bool)>| steps.into_iter().map(|(_, _, done)| done).collect::<Vec<_>>();
+        assert_eq!(done(batch.step(&[0, 0, 0])), [false, true, false]);
+        assert_eq!(batch.envs[1].frames(), 2, "stopped in the second frame");
+        assert_eq!(done(batch.step(&[0, 0, 0])), [false, true, false], "reset, then stopped again");
+        assert_eq!(batch.envs[0].frames(), 8);
+    }
+
+    #[test]
+    fn test_play_random() {
+        let episodes = play_random(&mut batch(3, 2), 7, 1);
+        assert_eq!(episodes.len(), 7);
+        for episode in episodes {
+            // V0 counts the frames but the one halting, so the reward is about the length
+            assert!(episode.frames <= 100);
+            assert!((episode.frames - 1..=episode.frames).contains(&(episode.reward as u64)), "{:?}", episode);
+        }
+        assert_eq!(play_random(&mut batch(3, 2), 7, 1), play_random(&mut batch(3, 3), 7, 1));
+    }
+}
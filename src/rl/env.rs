@@ -0,0 +1,166 @@
+// env.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::{WIDTH, HEIGHT, MEMORY_SIZE};
+use crate::frontend::runner::Runner;
+use crate::rl::task::Task;
+
+
+/**
+What the agent sees after each step: the screen, and the RAM for agents learning from
+the game's own variables.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observation {
+    pub pixels: [[bool; WIDTH]; HEIGHT],
+    pub memory: [u8; MEMORY_SIZE],
+}
+
+
+/**
+A gym-style environment over a ROM: `reset` starts an episode and `step` plays an action
+for `frame_skip` frames, returning what happened. Runs as fast as the machine allows.
+*/
+pub struct Env {
+    runner: Runner,
+    task: Task,
+    /// `Task::value` after the last step, to reward its change
+    value: f32,
+}
+
+impl Env {
+    /**
+    An environment playing the ROM loaded in `chip8` at `cycles_per_frame`.
+    */
+    pub fn new(chip8: Chip8, cycles_per_frame: u32, task: Task) -> Self {
+        let mut runner = Runner::new(chip8);
+        runner.cycles_per_frame = cycles_per_frame;
+        let value = task.value(runner.chip8.memory());
+        Env { runner, task, value }
+    }
+
+
+    pub fn task(&self) -> &Task {
+        &self.task
+    }
+
+
+    /**
+    Frames played since the episode started.
+    */
+    pub fn frames(&self) -> u64 {
+        self.runner.frames
+    }
+
+
+    /**
+    Restarts the ROM for a new episode.
+    */
+    pub fn reset(&mut self) -> Observation {
+        self.runner.reset();
+        self.runner.frames = 0;
+        self.value = self.task.value(self.runner.chip8.memory());
+        self.observation()
+    }
+
+
+    /**
+    Holds the keys of `action` for `frame_skip` frames, or until the episode ends: the
+    task says it's over, `max_frames` is reached or the ROM stops the machine with a
+    `Chip8::fault`.
+
+    # Returns
+    Returns the screen afterwards, the reward earned and whether the episode is over.
+    */
+    pub fn step(&mut self, action: usize) -> (Observation, f32, bool) {
+        let chip8 = &mut self.runner.chip8;
+        chip8.keypad = [false; 16];
+        for &key in self.task.actions.pressed(action) {
+            chip8.keypad[key] = true;
+        }
+
+        let mut done = false;
+        for _ in 0..self.task.frame_skip.max(1) {
+            self.runner.step_frame();
+            let chip8 = &self.runner.chip8;
+            done = self.task.done(chip8) || chip8.fault().is_some() || self.runner.frames >= self.task.max_frames;
+            if done {
+                break;
+            }
+        }
+
+        let value = self.task.value(self.runner.chip8.memory());
+        let reward = value - self.value;
+        self.value = value;
+        (self.observation(), reward, done)
+    }
+
+
+    fn observation(&self) -> Observation {
+        Observation { pixels: self.runner.chip8.pixel_array, memory: *self.runner.chip8.memory() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rl::task::{ActionSet, Reward};
+
+    /**
+    Adds one to a BCD score at 0x300 for every frame key 5 is held, and halts once it
+    reaches 10: `LD V1, 1`, then a loop that waits for vblank with a draw, skips the
+    add unless 5 is pressed, stores the score with `LD B, V0` and jumps to the halt
+    at 0x216 when V0 is 10.
+    */
+    const ROM: [u8; 24] = [
+        0x61, 0x01, 0xA3, 0x10, 0xD0, 0x01, 0x62, 0x05, 0xE2, 0xA1, 0x80, 0x14, 0xA3, 0x00,
+        0xF0, 0x33, 0x40, 0x0A, 0x12, 0x16, 0x12, 0x02, 0x12, 0x16,
+    ];
+
+    fn env() -> Env {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8.quirks.vblank = true;
+        let mut task = Task::new();
+        task.actions = ActionSet::keys(&[5]);
+        task.rewards = vec![Reward::parse("bcd:300:3").unwrap()];
+        task.frame_skip = 2;
+        Env::new(chip8, 20, task)
+    }
+
+    #[test]
+    fn test_episode() {
+        let mut env = env();
+        let first = env.reset();
+        assert_eq!(env.step(0), (first.clone(), 0.0, false));
+        assert_eq!(env.step(1).1, 2.0);
+
+        let mut total = 2.0;
+        let mut steps = 1;
+        loop {
+            let (_, reward, done) = env.step(1);
+            total += reward;
+            steps += 1;
+            if done {
+                break;
+            }
+        }
+        assert_eq!((total, steps), (10.0, 5));
+        assert_eq!(env.frames(), 12);
+
+        assert_eq!(env.reset(), first);
+        // The first frame only gets to the loop
+        assert_eq!(env.step(1).1, 1.0, "the score was reset");
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let mut env = env();
+        env.task.max_frames = 3;
+        env.reset();
+        assert!(!env.step(0).2);
+        assert!(env.step(0).2);
+        assert_eq!(env.frames(), 3);
+    }
+}
@@ -0,0 +1,281 @@
+// task.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::MEMORY_SIZE;
+
+use std::fmt;
+
+
+/// Frames each action is held for by default, as is usual for Atari-style training
+pub const DEFAULT_FRAME_SKIP: u32 = 4;
+
+/// Longest episode by default, a minute of play
+pub const DEFAULT_MAX_FRAMES: u64 = 3600;
+
+
+fn address(text: &str) -> Result<usize, String> {
+    let hex = text.trim_start_matches("0x");
+    usize::from_str_radix(hex, 16).ok().filter(|&addr| addr < MEMORY_SIZE)
+        .ok_or(format!("Invalid address: {}", text))
+}
+
+
+/**
+The actions an agent picks from, each a set of keypad keys held together. Action 0 is
+always "nothing pressed".
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionSet {
+    actions: Vec<Vec<usize>>,
+}
+
+impl ActionSet {
+    /**
+    Nothing pressed, plus one action per key in `keys`.
+    */
+    pub fn keys(keys: &[usize]) -> Self {
+        let mut actions = vec![Vec::new()];
+        actions.extend(keys.iter().map(|&key| vec![key]));
+        ActionSet { actions }
+    }
+
+
+    /**
+    Parses comma separated actions of hex keys, `+` joining keys pressed together,
+    e.g. `4,6,5,4+5`.
+    */
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut actions = vec![Vec::new()];
+        for action in text.split(',') {
+            let keys = action.split('+').map(|key| {
+                usize::from_str_radix(key.trim(), 16).ok().filter(|&key| key < 16)
+                    .ok_or(format!("Invalid key in action {:?}", action))
+            }).collect::<Result<Vec<_>, _>>()?;
+            actions.push(keys);
+        }
+        Ok(ActionSet { actions })
+    }
+
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+
+    /**
+    Never true, as action 0 is always there.
+    */
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+
+    /**
+    The keys `action` holds, nothing for actions out of range.
+    */
+    pub fn pressed(&self, action: usize) -> &[usize] {
+        self.actions.get(action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Default for ActionSet {
+    /**
+    Every key on its own.
+    */
+    fn default() -> Self {
+        ActionSet::keys(&(0..16).collect::<Vec<_>>())
+    }
+}
+
+
+/**
+A number the ROM keeps in RAM, such as a score or a lives counter.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Byte(usize),
+    /// Big-endian, like the rest of CHIP-8
+    Word(usize),
+    /// Decimal digits one per byte, most significant first, as `FX33` stores them
+    Bcd { addr: usize, digits: usize },
+}
+
+impl Score {
+    pub fn read(&self, memory: &[u8; MEMORY_SIZE]) -> i64 {
+        let byte = |addr: usize| memory[addr % MEMORY_SIZE] as i64;
+        match *self {
+            Score::Byte(addr) => byte(addr),
+            Score::Word(addr) => byte(addr) << 8 | byte(addr + 1),
+            Score::Bcd { addr, digits } => (addr..addr + digits).fold(0, |value, addr| value * 10 + byte(addr)),
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Score::Byte(addr) => write!(f, "byte:{:03X}", addr),
+            Score::Word(addr) => write!(f, "word:{:03X}", addr),
+            Score::Bcd { addr, digits } => write!(f, "bcd:{:03X}:{}", addr, digits),
+        }
+    }
+}
+
+
+/**
+A score whose changes are rewarded: each step earns `weight` times how much it grew, so
+a lives counter with a negative weight punishes losing one.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reward {
+    pub score: Score,
+    pub weight: f32,
+}
+
+impl Reward {
+    /**
+    Parses `byte:ADDR`, `word:ADDR` or `bcd:ADDR:DIGITS` with hex addresses, optionally
+    followed by `*WEIGHT`, e.g. `bcd:2F0:3` or `byte:2F4*-10`.
+    */
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (score, weight) = match text.split_once('*') {
+            Some((score, weight)) => (score, weight.parse().map_err(|_| format!("Invalid reward weight: {}", weight))?),
+            None => (text, 1.0),
+        };
+        let score = match score.split(':').collect::<Vec<_>>()[..] {
+            ["byte", addr] => Score::Byte(address(addr)?),
+            ["word", addr] => Score::Word(address(addr)?),
+            ["bcd", addr, digits] => Score::Bcd {
+                addr: address(addr)?,
+                digits: digits.parse().ok().filter(|digits| (1..=8).contains(digits)).ok_or(format!("Invalid digits: {}", digits))?,
+            },
+            _ => return Err(format!("Invalid reward: {} (expected byte:ADDR, word:ADDR or bcd:ADDR:DIGITS)", text)),
+        };
+        Ok(Reward { score, weight })
+    }
+}
+
+
+/**
+Ends an episode when the byte at `addr` holds `value`, e.g. when the lives run out.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Done {
+    pub addr: usize,
+    pub value: u8,
+}
+
+impl Done {
+    /**
+    Parses `ADDR=VALUE`, both in hex.
+    */
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (addr, value) = text.split_once('=').ok_or(format!("Invalid end condition: {} (expected ADDR=VALUE)", text))?;
+        let value = u8::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| format!("Invalid value: {}", value))?;
+        Ok(Done { addr: address(addr)?, value })
+    }
+}
+
+
+/**
+What an agent is trained to do on a ROM: the actions it has, what it's rewarded for and
+when an episode is over.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub actions: ActionSet,
+    pub rewards: Vec<Reward>,
+    /// Any of these ends the episode
+    pub done: Vec<Done>,
+    /// Frames each action is held for
+    pub frame_skip: u32,
+    /// Frames after which the episode is cut short
+    pub max_frames: u64,
+}
+
+impl Task {
+    pub fn new() -> Self {
+        Task {
+            actions: ActionSet::default(),
+            rewards: Vec::new(),
+            done: Vec::new(),
+            frame_skip: DEFAULT_FRAME_SKIP,
+            max_frames: DEFAULT_MAX_FRAMES,
+        }
+    }
+
+
+    /**
+    The weighted sum of the scores, whose change is the reward.
+    */
+    pub fn value(&self, memory: &[u8; MEMORY_SIZE]) -> f32 {
+        self.rewards.iter().map(|reward| reward.weight * reward.score.read(memory) as f32).sum()
+    }
+
+
+    /**
+    Whether the episode is over: an end condition holds, or the ROM halted in a jump to
+    itself, the usual way CHIP-8 programs stop.
+    */
+    pub fn done(&self, chip8: &Chip8) -> bool {
+        let memory = chip8.memory();
+        let pc = chip8.pc();
+        let halted = (pc as usize) + 1 < MEMORY_SIZE && Chip8::read_memory_address(*memory, pc) == 0x1000 | pc;
+        halted || self.done.iter().any(|done| memory[done.addr] == done.value)
+    }
+}
+
+impl Default for Task {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let actions = ActionSet::parse("4,6,4+5").unwrap();
+        assert_eq!(actions.len(), 4);
+        assert_eq!(actions.pressed(0), &[] as &[usize]);
+        assert_eq!(actions.pressed(3), &[4, 5]);
+        assert_eq!(actions.pressed(9), &[] as &[usize]);
+        assert_eq!(ActionSet::default().len(), 17);
+        assert!(ActionSet::parse("4,G").is_err());
+
+        assert_eq!(Reward::parse("bcd:2F0:3").unwrap(), Reward { score: Score::Bcd { addr: 0x2F0, digits: 3 }, weight: 1.0 });
+        assert_eq!(Reward::parse("byte:0x2F4*-10").unwrap(), Reward { score: Score::Byte(0x2F4), weight: -10.0 });
+        assert_eq!(Reward::parse("word:FFE").unwrap().score.to_string(), "word:FFE");
+        for invalid in ["bcd:2F0", "byte:1000", "nibble:200", "byte:200*x"] {
+            assert!(Reward::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(Done::parse("2F4=0").unwrap(), Done { addr: 0x2F4, value: 0 });
+        assert!(Done::parse("2F4").is_err());
+    }
+
+    #[test]
+    fn test_value_and_done() {
+        // LD V0, 123; LD I, 300; LD B, V0; JP 206
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&[0x60, 123, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x06]).unwrap();
+        let mut task = Task::new();
+        task.rewards = vec![Reward::parse("bcd:300:3").unwrap(), Reward::parse("word:301*0.5").unwrap()];
+        task.done = vec![Done::parse("302=9").unwrap()];
+
+        for _ in 0..3 {
+            assert!(!task.done(&chip8));
+            chip8.cycle();
+        }
+        assert_eq!(task.value(chip8.memory()), 123.0 + 0.5 * 0x0203 as f32);
+        assert!(task.done(&chip8), "halted");
+
+        chip8.set_pc(0x200);
+        assert!(!task.done(&chip8));
+        chip8.write_memory(0x302, 9);
+        assert!(task.done(&chip8));
+    }
+}
@@ -0,0 +1,238 @@
+// rom_db.rs
+
+use crate::chip8::quirks::{Quirks, QuirkOverrides};
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+
+const EMBEDDED_PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+const EMBEDDED_PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+
+/// Used when a ROM isn't in the database and no platform was given
+pub const DEFAULT_PLATFORM: &str = "modernChip8";
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub default_tickrate: u32,
+    pub quirks: Quirks,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RomEntry {
+    pub file: Option<String>,
+    pub platforms: Vec<String>,
+    pub tickrate: Option<u32>,
+    pub colors: Option<Colors>,
+    /// Semantic buttons (`up`, `down`, `left`, `right`, `a`, `b`) to CHIP-8 keys
+    pub keys: HashMap<String, u8>,
+    /// Quirks that differ from the platform's defaults when run on that platform
+    pub quirky_platforms: HashMap<String, QuirkOverrides>,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+struct Program {
+    title: String,
+    roms: HashMap<String, RomEntry>,
+}
+
+
+/**
+Everything the database knows about one ROM, resolved against its platform.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomMatch {
+    pub title: String,
+    pub entry: RomEntry,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub tickrate: u32,
+}
+
+
+pub struct RomDatabase {
+    platforms: Vec<Platform>,
+    /// SHA-1 to (title, entry)
+    roms: HashMap<String, (String, RomEntry)>,
+}
+
+impl RomDatabase {
+    /**
+    The database compiled into the binary.
+    */
+    pub fn embedded() -> Self {
+        let mut database = RomDatabase {
+            platforms: serde_json::from_str(EMBEDDED_PLATFORMS).expect("Invalid embedded platforms.json"),
+            roms: HashMap::new(),
+        };
+        database.add_programs(EMBEDDED_PROGRAMS).expect("Invalid embedded programs.json");
+        database
+    }
+
+
+    /**
+    Adds the ROMs of a `programs.json` document, replacing entries with the same hash.
+    */
+    pub fn add_programs(&mut self, json: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for program in programs {
+            for (hash, entry) in program.roms {
+                self.roms.insert(hash.to_lowercase(), (program.title.clone(), entry));
+            }
+        }
+        Ok(())
+    }
+
+
+    /**
+    Adds local entries from `path`, or from `~/.config/octorust/roms.json` if there is
+    one and no path was given.
+    */
+    pub fn add_local(&mut self, path: Option<&str>) -> Result<(), String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_local_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(()),
+            },
+        };
+        let json = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.add_programs(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+
+    pub fn platforms(&self) -> &[Platform] {
+        &self.platforms
+    }
+
+
+    pub fn platform_ids(&self) -> Vec<&str> {
+        self.platforms.iter().map(|platform| platform.id.as_str()).collect()
+    }
+
+
+    /**
+    Looks up a ROM by SHA-1. The ROM runs on the first of its platforms that is known,
+    or on `platform_override` if given, with that platform's quirks and any ROM-specific
+    quirk overrides for it.
+    */
+    pub fn lookup(&self, sha1: &str, platform_override: Option<&str>) -> Option<RomMatch> {
+        let (title, entry) = self.roms.get(&sha1.to_lowercase())?;
+
+        let platform = match platform_override {
+            Some(id) => self.platform(id)?,
+            None => entry.platforms.iter()
+                .find_map(|id| self.platform(id))
+                .or_else(|| self.platform(DEFAULT_PLATFORM))?,
+        };
+
+        let quirks = match entry.quirky_platforms.get(&platform.id) {
+            Some(overrides) => platform.quirks.with_overrides(overrides),
+            None => platform.quirks,
+        };
+
+        Some(RomMatch {
+            title: title.clone(),
+            entry: entry.clone(),
+            platform: platform.clone(),
+            quirks,
+            tickrate: entry.tickrate.unwrap_or(platform.default_tickrate),
+        })
+    }
+}
+
+
+pub fn default_local_path() -> Option<PathBuf> {
+    crate::config::default_path().and_then(|path| path.parent().map(|dir| dir.join("roms.json")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Test Game",
+            "roms": {
+                "0123456789ABCDEF0123456789ABCDEF01234567": {
+                    "file": "test.ch8",
+                    "platforms": ["superchip", "xochip"],
+                    "tickrate": 20,
+                    "colors": { "pixels": ["#000000", "#ffffff"] },
+                    "keys": { "up": 5, "a": 6 },
+                    "quirkyPlatforms": { "superchip": { "shift": false } }
+                }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn test_embedded_platforms() {
+        let database = RomDatabase::embedded();
+        assert!(database.platform(DEFAULT_PLATFORM).is_some());
+        assert!(database.platform("originalChip8").unwrap().quirks.vblank);
+        assert!(database.platform("superchip").unwrap().quirks.memory_leave_i_unchanged);
+    }
+
+    #[test]
+    fn test_embedded_programs() {
+        let database = RomDatabase::embedded();
+        let ibm_logo = database.lookup("1ba58656810b67fd131eb9af3e3987863bf26c90", None).unwrap();
+        assert_eq!((ibm_logo.title.as_str(), ibm_logo.platform.id.as_str()), ("IBM Logo", "originalChip8"));
+        assert!(ibm_logo.quirks.vblank);
+        for (hash, (title, entry)) in &database.roms {
+            let rom = database.lookup(hash, None).unwrap();
+            assert!(entry.platforms.is_empty() || entry.platforms.contains(&rom.platform.id), "{} runs on a known platform", title);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut database = RomDatabase::embedded();
+        database.add_programs(PROGRAMS).unwrap();
+
+        let rom = database.lookup("0123456789abcdef0123456789abcdef01234567", None).unwrap();
+        assert_eq!(rom.title, "Test Game");
+        assert_eq!(rom.platform.id, "superchip");
+        assert_eq!(rom.tickrate, 20);
+        assert_eq!(rom.entry.keys.get("up"), Some(&5));
+        // Overridden for this ROM
+        assert!(!rom.quirks.shift);
+        // Platform default
+        assert!(rom.quirks.jump);
+
+        let rom = database.lookup("0123456789abcdef0123456789abcdef01234567", Some("xochip")).unwrap();
+        assert_eq!(rom.platform.id, "xochip");
+        assert!(rom.quirks.wrap);
+
+        assert!(database.lookup("ffffffffffffffffffffffffffffffffffffffff", None).is_none());
+    }
+
+    #[test]
+    fn test_add_programs_rejects_invalid_json() {
+        assert!(RomDatabase::embedded().add_programs("{").is_err());
+    }
+}
@@ -0,0 +1,400 @@
+// script.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::MEMORY_SIZE;
+use crate::display::overlay::{Overlay, Shape};
+
+use rhai::{Dynamic, Engine, FnPtr, FuncArgs, AST, INT};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+
+/// Operations a single hook may run before it's stopped, so a runaway loop can't hang
+/// the emulator
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+const DEFAULT_TEXT_COLOR: INT = 0xFF_FF00;
+
+
+/**
+The functions scripts registered with `on_pc`, `on_write`, `on_frame` and `on_key`.
+*/
+#[derive(Default)]
+struct Hooks {
+    pc: BTreeMap<u16, Vec<FnPtr>>,
+    write: BTreeMap<u16, Vec<FnPtr>>,
+    frame: Vec<FnPtr>,
+    key: Vec<FnPtr>,
+}
+
+
+/**
+The machine as scripts see it: a copy of the state taken before each hook, whose changes
+are copied back after it.
+*/
+struct Machine {
+    v: [u8; 16],
+    index: u16,
+    pc: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    keypad: [bool; 16],
+    memory: Vec<u8>,
+    /// Addresses poked, to write back
+    poked: Vec<usize>,
+    frames: u64,
+    /// Shapes drawn since the last frame ended
+    overlay: Overlay,
+    /// From `notify` and script errors, for the frontend to show
+    messages: Vec<String>,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Machine {
+            v: [0; 16],
+            index: 0,
+            pc: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            keypad: [false; 16],
+            memory: vec![0; MEMORY_SIZE],
+            poked: Vec::new(),
+            frames: 0,
+            overlay: Overlay::default(),
+            messages: Vec::new(),
+        }
+    }
+
+
+    fn load(&mut self, chip8: &Chip8, frames: u64) {
+        self.v = *chip8.v();
+        self.index = chip8.index();
+        self.pc = chip8.pc();
+        self.delay_timer = chip8.delay_timer;
+        self.sound_timer = chip8.sound_timer;
+        self.keypad = chip8.keypad;
+        self.memory.copy_from_slice(chip8.memory());
+        self.frames = frames;
+    }
+
+
+    fn store(&mut self, chip8: &mut Chip8) {
+        for (register, &value) in self.v.iter().enumerate() {
+            chip8.set_v(register, value);
+        }
+        chip8.set_index(self.index);
+        chip8.set_pc(self.pc);
+        chip8.delay_timer = self.delay_timer;
+        chip8.sound_timer = self.sound_timer;
+        chip8.keypad = self.keypad;
+        for addr in self.poked.drain(..) {
+            chip8.write_memory(addr, self.memory[addr]);
+        }
+    }
+}
+
+
+type Shared<T> = Arc<Mutex<T>>;
+
+fn lock<T>(shared: &Shared<T>) -> MutexGuard<'_, T> {
+    // A hook that panicked can't have left the state half updated in a way that matters
+    shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+/**
+A Rhai script hooked into the run, for cheats, auto-splitters and test scenarios. The
+script's top level registers callbacks:
+
+- `on_pc(addr, || ...)`: before the instruction at `addr` runs
+- `on_write(addr, |addr, value| ...)`: after the ROM stores to `addr` with `FX33`/`FX55`
+- `on_frame(|| ...)`: at the end of every frame
+- `on_key(|key, pressed| ...)`: when the player presses or releases a key
+
+Callbacks and the top level can use `v(n)`, `set_v(n, value)`, `index()`, `set_index(value)`,
+`pc()`, `set_pc(addr)`, `delay_timer()`, `set_delay_timer(value)`, `sound_timer()`,
+`set_sound_timer(value)`, `peek(addr)`, `poke(addr, value)`, `key(key)`, `press(key)`,
+`release(key)` and `frame()`, and show things with `text(x, y, text[, color])`,
+`rect(x, y, width, height, color)` in CHIP-8 pixels and `notify(message)`. Out of range
+registers, keys and addresses wrap around, except that `set_pc` reports a pc the
+emulator can't run from as a script error and leaves the pc alone.
+*/
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    hooks: Shared<Hooks>,
+    machine: Shared<Machine>,
+}
+
+impl Script {
+    pub fn load(path: &str, chip8: &mut Chip8) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("Failed reading script {}: {}", path, e))?;
+        Self::new(&source, chip8).map_err(|e| format!("Error in script {}: {}", path, e))
+    }
+
+
+    /**
+    Compiles `source` and runs its top level against `chip8`.
+    */
+    pub fn new(source: &str, chip8: &mut Chip8) -> Result<Self, String> {
+        let hooks: Shared<Hooks> = Arc::default();
+        let machine = Arc::new(Mutex::new(Machine::new()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_hooks(&mut engine, &hooks);
+        register_machine(&mut engine, &machine);
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        lock(&machine).load(chip8, 0);
+        engine.run_ast(&ast).map_err(|e| e.to_string())?;
+        lock(&machine).store(chip8);
+        Ok(Script { engine, ast, hooks, machine })
+    }
+
+
+    /**
+    Whether the script has to see every instruction, for `on_pc` or `on_write` hooks.
+    */
+    pub fn watches_instructions(&self) -> bool {
+        let hooks = lock(&self.hooks);
+        !hooks.pc.is_empty() || !hooks.write.is_empty()
+    }
+
+
+    /**
+    Runs the `on_pc` hooks for the instruction about to run.
+    */
+    pub fn before_instruction(&mut self, chip8: &mut Chip8, frames: u64) {
+        let hooks = lock(&self.hooks).pc.get(&chip8.pc()).cloned().unwrap_or_default();
+        for hook in hooks {
+            self.call(&hook, chip8, frames, ());
+        }
+    }
+
+
+    /**
+    Runs the `on_write` hooks for `length` bytes just stored from `start`.
+    */
+    pub fn after_write(&mut self, chip8: &mut Chip8, start: u16, length: usize, frames: u64) {
+        for addr in (start as usize..start as usize + length).map(|addr| addr % MEMORY_SIZE) {
+            let hooks = lock(&self.hooks).write.get(&(addr as u16)).cloned().unwrap_or_default();
+            for hook in hooks {
+                let value = chip8.memory()[addr];
+                self.call(&hook, chip8, frames, (addr as INT, value as INT));
+            }
+        }
+    }
+
+
+    /**
+    Runs the `on_frame` hooks, then hands over what was drawn during the frame.
+    */
+    pub fn end_frame(&mut self, chip8: &mut Chip8, frames: u64, overlay: &mut Overlay) {
+        let hooks = lock(&self.hooks).frame.clone();
+        for hook in hooks {
+            self.call(&hook, chip8, frames, ());
+        }
+        *overlay = std::mem::take(&mut lock(&self.machine).overlay);
+    }
+
+
+    pub fn key_changed(&mut self, chip8: &mut Chip8, key: usize, pressed: bool, frames: u64) {
+        let hooks = lock(&self.hooks).key.clone();
+        for hook in hooks {
+            self.call(&hook, chip8, frames, (key as INT, pressed));
+        }
+    }
+
+
+    /**
+    Messages to show from `notify` and from errors in hooks since last asked.
+    */
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut lock(&self.machine).messages)
+    }
+
+
+    fn call(&mut self, hook: &FnPtr, chip8: &mut Chip8, frames: u64, args: impl FuncArgs) {
+        lock(&self.machine).load(chip8, frames);
+        let result = hook.call::<Dynamic>(&self.engine, &self.ast, args);
+        let mut machine = lock(&self.machine);
+        machine.store(chip8);
+        if let Err(e) = result {
+            eprintln!("Script error: {}", e);
+            machine.messages.push(format!("Script error: {}", e));
+        }
+    }
+}
+
+
+fn register_hooks(engine: &mut Engine, hooks: &Shared<Hooks>) {
+    let shared = hooks.clone();
+    engine.register_fn("on_pc", move |addr: INT, hook: FnPtr| {
+        lock(&shared).pc.entry(wrap(addr) as u16).or_default().push(hook);
+    });
+    let shared = hooks.clone();
+    engine.register_fn("on_write", move |addr: INT, hook: FnPtr| {
+        lock(&shared).write.entry(wrap(addr) as u16).or_default().push(hook);
+    });
+    let shared = hooks.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| lock(&shared).frame.push(hook));
+    let shared = hooks.clone();
+    engine.register_fn("on_key", move |hook: FnPtr| lock(&shared).key.push(hook));
+}
+
+
+fn wrap(addr: INT) -> usize {
+    addr.rem_euclid(MEMORY_SIZE as INT) as usize
+}
+
+
+fn register_machine(engine: &mut Engine, machine: &Shared<Machine>) {
+    let m = machine.clone();
+    engine.register_fn("v", move |register: INT| lock(&m).v[register as usize & 0xF] as INT);
+    let m = machine.clone();
+    engine.register_fn("set_v", move |register: INT, value: INT| lock(&m).v[register as usize & 0xF] = value as u8);
+    let m = machine.clone();
+    engine.register_fn("index", move || lock(&m).index as INT);
+    let m = machine.clone();
+    engine.register_fn("set_index", move |value: INT| lock(&m).index = value as u16);
+    let m = machine.clone();
+    engine.register_fn("pc", move || lock(&m).pc as INT);
+    let m = machine.clone();
+    engine.register_fn("set_pc", move |addr: INT| {
+        let mut machine = lock(&m);
+        match u16::try_from(addr) {
+            Ok(pc) if Chip8::is_valid_pc(pc) => machine.pc = pc,
+            _ => {
+                eprintln!("Script error: set_pc({:#X}) is out of range", addr);
+                machine.messages.push(format!("Script error: set_pc({:#X}) is out of range", addr));
+            }
+        }
+    });
+    let m = machine.clone();
+    engine.register_fn("delay_timer", move || lock(&m).delay_timer as INT);
+    let m = machine.clone();
+    engine.register_fn("set_delay_timer", move |value: INT| lock(&m).delay_timer = value as u8);
+    let m = machine.clone();
+    engine.register_fn("sound_timer", move || lock(&m).sound_timer as INT);
+    let m = machine.clone();
+    engine.register_fn("set_sound_timer", move |value: INT| lock(&m).sound_timer = value as u8);
+    let m = machine.clone();
+    engine.register_fn("peek", move |addr: INT| lock(&m).memory[wrap(addr)] as INT);
+    let m = machine.clone();
+    engine.register_fn("poke", move |addr: INT, value: INT| {
+        let mut machine = lock(&m);
+        machine.memory[wrap(addr)] = value as u8;
+        machine.poked.push(wrap(addr));
+    });
+    let m = machine.clone();
+    engine.register_fn("key", move |key: INT| lock(&m).keypad[key as usize & 0xF]);
+    let m = machine.clone();
+    engine.register_fn("press", move |key: INT| lock(&m).keypad[key as usize & 0xF] = true);
+    let m = machine.clone();
+    engine.register_fn("release", move |key: INT| lock(&m).keypad[key as usize & 0xF] = false);
+    let m = machine.clone();
+    engine.register_fn("frame", move || lock(&m).frames as INT);
+
+    let m = machine.clone();
+    engine.register_fn("text", move |x: INT, y: INT, text: &str, color: INT| {
+        lock(&m).overlay.shapes.push(Shape::Text { x: x as i32, y: y as i32, text: text.to_string(), color: color as u32 });
+    });
+    let m = machine.clone();
+    engine.register_fn("text", move |x: INT, y: INT, text: &str| {
+        lock(&m).overlay.shapes.push(Shape::Text { x: x as i32, y: y as i32, text: text.to_string(), color: DEFAULT_TEXT_COLOR as u32 });
+    });
+    let m = machine.clone();
+    engine.register_fn("rect", move |x: INT, y: INT, width: INT, height: INT, color: INT| {
+        let (x, y, width, height) = (x as i32, y as i32, width as i32, height as i32);
+        lock(&m).overlay.shapes.push(Shape::Rect { x, y, width, height, color: color as u32 });
+    });
+    let m = machine.clone();
+    engine.register_fn("notify", move |message: &str| lock(&m).messages.push(message.to_string()));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LD V0, 1; LD I, 300; loop: ADD V0, 1; LD B, V0; JP loop
+    const ROM: [u8; 10] = [0x60, 0x01, 0xA3, 0x00, 0x70, 0x01, 0xF0, 0x33, 0x12, 0x04];
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut chip8 = chip8();
+        let mut script = Script::new(r#"
+            set_v(5, 0x42);
+            let hits = 0;
+            on_pc(0x206, || { hits += 1; if hits == 3 { set_v(0, 50); } });
+            on_write(0x302, |addr, value| { if value == 3 { poke(0x400, addr - 0x300); } });
+            on_frame(|| { text(1, 2, `hits ${hits}`); rect(0, 0, 4, 4, 0xFF0000); });
+            on_key(|key, pressed| { if pressed { press(key + 1); notify(`key ${key}`); } });
+        "#, &mut chip8).unwrap();
+        assert_eq!(chip8.v()[5], 0x42);
+        assert!(script.watches_instructions());
+
+        let mut overlay = Overlay::default();
+        // The setup, then four times round the loop
+        for _ in 0..14 {
+            script.before_instruction(&mut chip8, 0);
+            let (pc, index) = (chip8.pc(), chip8.index());
+            chip8.cycle();
+            if pc == 0x206 {
+                script.after_write(&mut chip8, index, 3, 0);
+            }
+        }
+        // V0 was set to 50 before the third store, so the fourth stores 51
+        assert_eq!(chip8.memory()[0x300..0x303], [0, 5, 1]);
+        assert_eq!(chip8.memory()[0x400], 2, "V0 was 3 at the second store");
+
+        script.end_frame(&mut chip8, 1, &mut overlay);
+        assert_eq!(overlay.shapes.len(), 2);
+        assert_eq!(overlay.shapes[0], Shape::Text { x: 1, y: 2, text: "hits 4".to_string(), color: DEFAULT_TEXT_COLOR as u32 });
+        script.end_frame(&mut chip8, 2, &mut overlay);
+        assert_eq!(overlay.shapes.len(), 2, "the overlay is redrawn every frame");
+
+        script.key_changed(&mut chip8, 4, true, 2);
+        assert!(chip8.keypad[5]);
+        assert_eq!(script.take_messages(), ["key 4"]);
+        assert!(script.take_messages().is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Script::new("on_frame(|| {", &mut chip8()).is_err());
+        assert!(Script::new("undefined_function()", &mut chip8()).is_err());
+
+        let mut chip8 = chip8();
+        let mut script = Script::new("on_frame(|| { loop {} }); on_frame(|| poke(0x300, 7));", &mut chip8).unwrap();
+        assert!(!script.watches_instructions());
+        script.end_frame(&mut chip8, 1, &mut Overlay::default());
+        assert!(script.take_messages()[0].starts_with("Script error"));
+        assert_eq!(chip8.memory()[0x300], 7, "later hooks still run");
+    }
+
+    #[test]
+    fn test_set_pc_out_of_range() {
+        let mut chip8 = chip8();
+        let mut script = Script::new("on_frame(|| { set_pc(0xFFF); set_pc(0); set_pc(-2); set_pc(0x10202); });", &mut chip8).unwrap();
+        script.end_frame(&mut chip8, 1, &mut Overlay::default());
+        let messages = script.take_messages();
+        assert_eq!(messages.len(), 4);
+        assert!(messages.iter().all(|message| message.starts_with("Script error")));
+        assert_eq!(chip8.pc(), 0x200);
+        chip8.cycle();
+
+        let mut script = Script::new("on_frame(|| set_pc(0xFFD));", &mut chip8).unwrap();
+        script.end_frame(&mut chip8, 2, &mut Overlay::default());
+        assert!(script.take_messages().is_empty());
+        assert_eq!(chip8.pc(), 0xFFD);
+    }
+}
@@ -0,0 +1,127 @@
+// session.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::config::{Config, Layer, Settings};
+use crate::library;
+use crate::rom_db::{RomDatabase, RomMatch};
+
+
+/**
+A ROM loaded into a fresh machine, with the settings it resolved to.
+*/
+pub struct Loaded {
+    pub chip8: Chip8,
+    pub settings: Settings,
+    pub sha1: String,
+    /// The ROM database entry, if the ROM is known
+    pub detected: Option<RomMatch>,
+}
+
+
+/**
+What stays the same while ROMs are loaded and reloaded: the config file, the ROM database
+and the command line overrides.
+*/
+pub struct Session {
+    pub config: Config,
+    pub rom_db: RomDatabase,
+    pub overrides: Layer,
+}
+
+impl Session {
+    /**
+    Loads the ROM at `rom_path` and resolves its settings, with the machine's quirks set.
+    */
+    pub fn load(&self, rom_path: &str) -> Result<Loaded, String> {
+        if library::is_source(std::path::Path::new(rom_path)) {
+            return Err(format!("{} is an Octo source, assemble it into a ROM first", rom_path));
+        }
+        let mut chip8 = Chip8::new();
+        let sha1 = chip8.load_rom(rom_path).map_err(|e| format!("Failed loading rom {}: {}", rom_path, e))?;
+        let settings = self.settings(rom_path, &sha1, None)?;
+        chip8.quirks = settings.quirks;
+        let detected = self.rom_db.lookup(&sha1, settings.platform.as_deref());
+        Ok(Loaded { chip8, settings, sha1, detected })
+    }
+
+
+    /**
+    The settings for the ROM at `rom_path` with hash `sha1`, run on `platform` instead of
+    the configured or detected one if given.
+    */
+    pub fn settings(&self, rom_path: &str, sha1: &str, platform: Option<&str>) -> Result<Settings, String> {
+        let mut layer = self.config.for_rom(rom_path, sha1).merge(&self.overrides);
+        if let Some(platform) = platform {
+            layer.platform = Some(platform.to_string());
+        }
+        Settings::resolve(&layer, &self.rom_db, sha1)
+    }
+
+
+    /**
+    Ids and names of the known platforms, for menus.
+    */
+    pub fn platforms(&self) -> Vec<(String, String)> {
+        self.rom_db.platforms().iter().map(|platform| (platform.id.clone(), platform.name.clone())).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session { config: Config::parse("speed = 20").unwrap(), rom_db: RomDatabase::embedded(), overrides: Layer::default() }
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("octorust-session-{}.ch8", std::process::id()));
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+        let loaded = session().load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.sha1.len(), 40);
+        assert_eq!(loaded.settings.speed, 20);
+        assert_eq!(loaded.chip8.memory()[0x200], 0x12);
+        assert!(session().load("does-not-exist.ch8").err().unwrap().starts_with("Failed loading rom does-not-exist.ch8"));
+        assert_eq!(session().load("game.8o").err().unwrap(), "game.8o is an Octo source, assemble it into a ROM first");
+    }
+
+    #[test]
+    fn test_detects_known_rom() {
+        let rom = [0x00, 0xFF, 0x12, 0x02];
+        let sha1 = Chip8::new().load_rom_bytes(&rom).unwrap();
+        let mut session = session();
+        session.rom_db.add_programs(&format!(
+            r#"[{{ "title": "Hires Test", "roms": {{ "{}": {{ "platforms": ["superchip"], "quirkyPlatforms": {{ "superchip": {{ "jump": false }} }} }} }} }}]"#,
+            sha1.to_uppercase(),
+        )).unwrap();
+
+        let path = std::env::temp_dir().join(format!("octorust-known-{}.ch8", std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+        let loaded = session.load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        let detected = loaded.detected.unwrap();
+        assert_eq!(detected.title, "Hires Test");
+        assert_eq!(detected.platform.id, "superchip");
+        assert_eq!(loaded.settings.platform.as_deref(), Some("superchip"));
+        let superchip = session.rom_db.platform("superchip").unwrap().quirks;
+        assert!(superchip.jump && !loaded.settings.quirks.jump, "the ROM's override applies");
+        assert_eq!(loaded.settings.quirks.memory_leave_i_unchanged, superchip.memory_leave_i_unchanged);
+    }
+
+    #[test]
+    fn test_settings_for_platform() {
+        let session = session();
+        let (id, _) = session.platforms().into_iter().next().unwrap();
+        let settings = session.settings("pong.ch8", "ffff", Some(&id)).unwrap();
+        assert_eq!(settings.platform, Some(id.clone()));
+        assert_eq!(settings.quirks, session.rom_db.platform(&id).unwrap().quirks);
+        assert!(session.settings("pong.ch8", "ffff", Some("nope")).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// web.rs
+
+use crate::chip8::chip8::Chip8;
+use crate::chip8::constants::{WIDTH, HEIGHT};
+use crate::frontend::runner::Runner;
+
+use std::cell::RefCell;
+
+
+/**
+The emulator as a web page drives it, one `run_frame` per 60 Hz tick, on the same `Runner`
+as the desktop. The page reaches it through the `octorust_*` functions below, a plain C
+ABI over the WebAssembly memory so the build needs no bindings generator;
+`web/octorust.js` wraps them for JavaScript.
+*/
+pub struct Web {
+    pub runner: Runner,
+    /// Where the page copies a ROM before loading it
+    rom: Vec<u8>,
+    /// One byte per pixel, 1 when lit, row by row from the top left
+    pixels: Vec<u8>,
+}
+
+impl Web {
+    pub fn new() -> Self {
+        Web { runner: Runner::new(Chip8::new()), rom: Vec::new(), pixels: vec![0; WIDTH * HEIGHT] }
+    }
+
+
+    /**
+    Starts `rom` on a fresh machine with the same quirks.
+    */
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        let mut chip8 = Chip8::new();
+        chip8.quirks = self.runner.chip8.quirks;
+        chip8.load_rom_bytes(rom).map_err(|e| e.to_string())?;
+        self.runner.replace(chip8);
+        self.update_pixels();
+        Ok(())
+    }
+
+
+    /**
+    Emulates a frame with `Runner::step_frame`.
+    */
+    pub fn run_frame(&mut self) {
+        self.runner.step_frame();
+        self.update_pixels();
+    }
+
+
+    fn update_pixels(&mut self) {
+        for (pixel, &lit) in self.pixels.iter_mut().zip(self.runner.chip8.pixel_array.iter().flatten()) {
+            *pixel = lit as u8;
+        }
+    }
+
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl Default for Web {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+thread_local! {
+    /// A page runs a single machine, so the exports work on this one
+    static WEB: RefCell<Web> = RefCell::new(Web::new());
+}
+
+fn with<R>(f: impl FnOnce(&mut Web) -> R) -> R {
+    WEB.with(|web| f(&mut web.borrow_mut()))
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_width() -> u32 {
+    WIDTH as u32
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_height() -> u32 {
+    HEIGHT as u32
+}
+
+
+/**
+A buffer of `length` bytes for the page to copy a ROM into before `octorust_load_rom`.
+*/
+#[no_mangle]
+pub extern "C" fn octorust_rom_buffer(length: usize) -> *mut u8 {
+    with(|web| {
+        web.rom = vec![0; length];
+        web.rom.as_mut_ptr()
+    })
+}
+
+
+/**
+Starts the ROM copied into the buffer.
+
+# Returns
+Returns false if it doesn't fit in memory.
+*/
+#[no_mangle]
+pub extern "C" fn octorust_load_rom() -> bool {
+    with(|web| {
+        let rom = std::mem::take(&mut web.rom);
+        web.load_rom(&rom).is_ok()
+    })
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_reset() {
+    with(|web| web.runner.reset());
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_set_speed(cycles_per_frame: u32) {
+    with(|web| web.runner.cycles_per_frame = cycles_per_frame.max(1));
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_set_key(key: u32, pressed: bool) {
+    with(|web| web.runner.chip8.keypad[key as usize & 0xF] = pressed);
+}
+
+
+#[no_mangle]
+pub extern "C" fn octorust_run_frame() {
+    with(Web::run_frame);
+}
+
+
+/**
+The screen after the last frame, `octorust_width() * octorust_height()` bytes.
+*/
+#[no_mangle]
+pub extern "C" fn octorust_pixels() -> *const u8 {
+    with(|web| web.pixels().as_ptr())
+}
+
+
+/**
+Whether the buzzer should sound.
+*/
+#[no_mangle]
+pub extern "C" fn octorust_sound() -> bool {
+    with(|web| web.runner.chip8.sound_timer > 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws the font's 5 at the top left, sounds the buzzer for 2 frames and clears the
+    /// screen while key 7 is held
+    const ROM: [u8; 18] = [
+        0xA0, 0x69, 0x61, 0x00, 0xD1, 0x15, 0x62, 0x02, 0xF2, 0x18, 0x63, 0x07, 0xE3, 0xA1, 0x00, 0xE0, 0x12, 0x0C,
+    ];
+
+    #[test]
+    fn test_frames() {
+        let mut web = Web::new();
+        web.runner.chip8.quirks.vblank = true;
+        web.load_rom(&ROM).unwrap();
+        assert!(web.runner.chip8.quirks.vblank, "quirks are kept");
+
+        web.run_frame();
+        assert_eq!(web.pixels()[..5], [1, 1, 1, 1, 0]);
+        assert_eq!(web.runner.chip8.pc(), 0x206, "the draw ended the frame");
+        web.run_frame();
+        assert_eq!(web.runner.chip8.sound_timer, 1);
+        web.runner.chip8.keypad[7] = true;
+        web.run_frame();
+        assert!(web.pixels().iter().all(|&pixel| pixel == 0));
+
+        assert!(web.load_rom(&[0; 4096]).is_err());
+
+        // A ROM that returns with an empty stack stops, and the page keeps running frames
+        web.load_rom(&[0x00, 0xEE]).unwrap();
+        web.run_frame();
+        web.run_frame();
+        assert_eq!(web.runner.chip8.pc(), 0x200);
+        assert!(web.runner.chip8.fault().is_some());
+    }
+
+    #[test]
+    fn test_exports() {
+        let buffer = octorust_rom_buffer(ROM.len());
+        unsafe { std::ptr::copy_nonoverlapping(ROM.as_ptr(), buffer, ROM.len()) };
+        assert!(octorust_load_rom());
+        octorust_set_speed(0);
+        octorust_run_frame();
+        with(|web| assert_eq!(web.runner.chip8.pc(), 0x202));
+
+        octorust_set_speed(100);
+        octorust_run_frame();
+        let pixels = unsafe { std::slice::from_raw_parts(octorust_pixels(), (octorust_width() * octorust_height()) as usize) };
+        assert_eq!(pixels[..4], [1, 1, 1, 1]);
+        assert!(octorust_sound());
+
+        octorust_set_key(0x17, true);
+        with(|web| assert!(web.runner.chip8.keypad[7], "keys wrap around"));
+        octorust_reset();
+        with(|web| assert_eq!(web.runner.chip8.pc(), 0x200));
+    }
+}
@@ -0,0 +1,44 @@
+// rl.rs
+//
+// Trains nothing, but drives an environment through the library's public API the way an
+// outside agent would.
+
+use octorust::chip8::chip8::Chip8;
+use octorust::rl::batch::BatchEnv;
+use octorust::rl::env::Env;
+use octorust::rl::task::{ActionSet, Reward, Task};
+
+
+/// Counts the byte at 0x300 up while key 5 is held
+const ROM: [u8; 16] = [
+    0xA3, 0x00, 0x62, 0x05, 0xE2, 0x9E, 0x12, 0x04, 0x70, 0x01, 0xF0, 0x55, 0xA3, 0x00, 0x12, 0x04,
+];
+
+fn env() -> Env {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&ROM).unwrap();
+    let mut task = Task::new();
+    task.actions = ActionSet::keys(&[5]);
+    task.rewards.push(Reward::parse("byte:300").unwrap());
+    task.frame_skip = 1;
+    task.max_frames = 2;
+    Env::new(chip8, 10, task)
+}
+
+#[test]
+fn test_env_through_the_library() {
+    let mut env = env();
+    env.reset();
+    let (_, reward, done) = env.step(0);
+    assert_eq!((reward, done), (0.0, false), "nothing pressed, nothing earned");
+    let (observation, reward, _) = env.step(1);
+    assert!(reward > 0.0);
+    assert_eq!(observation.memory[0x300] as f32, reward);
+
+    let mut batch = BatchEnv::new(vec![env, self::env()], 2);
+    batch.reset();
+    let steps = batch.step(&[1, 0]);
+    assert!(steps[0].1 > 0.0 && steps[1].1 == 0.0);
+    let steps = batch.step(&[1, 1]);
+    assert!(steps[0].2 && steps[1].2, "both ran out of frames");
+}
@@ -0,0 +1,215 @@
+// octorust.js
+//
+// Browser binding for octorust.wasm, the emulator core built for wasm32-unknown-unknown.
+// `Octorust` wraps the module's exports and `play` runs it in a page: the screen in a
+// canvas, the keypad on the keyboard, the buzzer through WebAudio and ROMs from a file
+// input. Nothing here needs a DOM until `play` is called, so tests run it under Node.
+
+/** Frames per second, the rate of the CHIP-8 timers */
+const FRAME_RATE = 60;
+/** Frames caught up at most after the page was in the background */
+const MAX_FRAMES_PER_TICK = 4;
+const BUZZER_FREQUENCY = 440;
+
+/** The "octorust" palette of the desktop build, as [r, g, b] */
+export const BACKGROUND = [0x37, 0x4A, 0x75];
+export const FOREGROUND = [0xCB, 0xD8, 0xF5];
+
+/**
+ * CHIP-8 key of each keyboard key, by position like the desktop build so it works with any
+ * layout: 1234/QWER/ASDF/ZXCV for 123C/456D/789E/A0BF.
+ */
+export const KEYMAP = {
+    Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xC,
+    KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xD,
+    KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xE,
+    KeyZ: 0xA, KeyX: 0x0, KeyC: 0xB, KeyV: 0xF,
+};
+
+
+export class Octorust {
+    /**
+     * Instantiates the module from its URL or its bytes.
+     */
+    static async load(source) {
+        const bytes = typeof source === "string" || source instanceof URL
+            ? await (await fetch(source)).arrayBuffer()
+            : source;
+        const { instance } = await WebAssembly.instantiate(bytes, {});
+        return new Octorust(instance.exports);
+    }
+
+    constructor(exports) {
+        this.exports = exports;
+        this.width = exports.octorust_width();
+        this.height = exports.octorust_height();
+    }
+
+    /**
+     * Starts a ROM, given as bytes, on a fresh machine.
+     */
+    loadRom(rom) {
+        const bytes = rom instanceof Uint8Array ? rom : new Uint8Array(rom);
+        const pointer = this.exports.octorust_rom_buffer(bytes.length);
+        new Uint8Array(this.exports.memory.buffer, pointer, bytes.length).set(bytes);
+        if (!this.exports.octorust_load_rom()) {
+            throw new Error(`A ROM of ${bytes.length} bytes doesn't fit in memory`);
+        }
+    }
+
+    reset() {
+        this.exports.octorust_reset();
+    }
+
+    /**
+     * Sets the instructions run per frame.
+     */
+    setSpeed(cycles) {
+        this.exports.octorust_set_speed(cycles);
+    }
+
+    setKey(key, pressed) {
+        this.exports.octorust_set_key(key, pressed ? 1 : 0);
+    }
+
+    runFrame() {
+        this.exports.octorust_run_frame();
+    }
+
+    /**
+     * The screen after the last frame, a byte per pixel, 1 when lit, row by row. The view
+     * is only valid until the next call into the module, which may move its memory.
+     */
+    pixels() {
+        return new Uint8Array(this.exports.memory.buffer, this.exports.octorust_pixels(), this.width * this.height);
+    }
+
+    /**
+     * Whether the buzzer should sound.
+     */
+    sound() {
+        return this.exports.octorust_sound() !== 0;
+    }
+}
+
+
+/**
+ * Fills `rgba`, the data of an ImageData as large as the screen, from `pixels`.
+ */
+export function paint(pixels, rgba, foreground = FOREGROUND, background = BACKGROUND) {
+    for (let i = 0; i < pixels.length; i++) {
+        const [r, g, b] = pixels[i] ? foreground : background;
+        rgba[i * 4] = r;
+        rgba[i * 4 + 1] = g;
+        rgba[i * 4 + 2] = b;
+        rgba[i * 4 + 3] = 255;
+    }
+}
+
+
+/**
+ * A square wave started on the first user gesture, as browsers only allow audio after one,
+ * and muted while the buzzer is off.
+ */
+class Buzzer {
+    constructor(volume) {
+        this.volume = volume;
+        this.gain = null;
+    }
+
+    start() {
+        if (this.gain) {
+            return;
+        }
+        const context = new AudioContext();
+        const oscillator = context.createOscillator();
+        oscillator.type = "square";
+        oscillator.frequency.value = BUZZER_FREQUENCY;
+        this.gain = context.createGain();
+        this.gain.gain.value = 0;
+        oscillator.connect(this.gain).connect(context.destination);
+        oscillator.start();
+    }
+
+    play(playing) {
+        if (this.gain) {
+            this.gain.gain.value = playing ? this.volume : 0;
+        }
+    }
+}
+
+
+/**
+ * Runs `emulator` in the page at 60 frames per second, drawing into `canvas`, which is
+ * sized to the screen and should be scaled up with CSS (`image-rendering: pixelated`).
+ * Picking a file in `fileInput` loads it as a ROM.
+ *
+ * Options: `rom` to start with (bytes), `foreground` and `background` as [r, g, b],
+ * `volume` from 0 to 1 and `onError`, called with errors loading ROMs.
+ */
+export function play(emulator, canvas, fileInput, options = {}) {
+    const { foreground = FOREGROUND, background = BACKGROUND, volume = 0.15, onError = console.error } = options;
+    canvas.width = emulator.width;
+    canvas.height = emulator.height;
+    const context = canvas.getContext("2d");
+    const image = context.createImageData(emulator.width, emulator.height);
+    const buzzer = new Buzzer(volume);
+    let running = false;
+
+    const load = (rom) => {
+        try {
+            emulator.loadRom(rom);
+            running = true;
+        } catch (error) {
+            onError(error);
+        }
+    };
+    if (options.rom) {
+        load(options.rom);
+    }
+
+    fileInput.addEventListener("change", async () => {
+        buzzer.start();
+        const [file] = fileInput.files;
+        if (file) {
+            load(new Uint8Array(await file.arrayBuffer()));
+            fileInput.blur();
+        }
+    });
+
+    const onKey = (pressed) => (event) => {
+        const key = KEYMAP[event.code];
+        if (key === undefined || event.target === fileInput) {
+            return;
+        }
+        event.preventDefault();
+        buzzer.start();
+        emulator.setKey(key, pressed);
+    };
+    window.addEventListener("keydown", onKey(true));
+    window.addEventListener("keyup", onKey(false));
+
+    const frameDuration = 1000 / FRAME_RATE;
+    let deadline = performance.now();
+    const tick = (now) => {
+        if (running) {
+            let frames = 0;
+            while (now >= deadline && frames < MAX_FRAMES_PER_TICK) {
+                emulator.runFrame();
+                deadline += frameDuration;
+                frames++;
+            }
+            // Don't try to catch up after a long stall, e.g. a background tab
+            if (now - deadline > frameDuration) {
+                deadline = now;
+            }
+            paint(emulator.pixels(), image.data, foreground, background);
+            context.putImageData(image, 0, 0);
+            buzzer.play(emulator.sound());
+        } else {
+            deadline = now;
+        }
+        requestAnimationFrame(tick);
+    };
+    requestAnimationFrame(tick);
+}
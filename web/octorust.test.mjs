@@ -0,0 +1,84 @@
+// octorust.test.mjs
+//
+// Runs the WebAssembly build through the JavaScript binding under Node, no browser needed:
+//
+//     cargo build --release --lib --target wasm32-unknown-unknown --no-default-features
+//     node --test web/
+//
+// OCTORUST_WASM overrides where the module is read from.
+
+import { BACKGROUND, FOREGROUND, KEYMAP, Octorust, paint } from "./octorust.js";
+
+import assert from "node:assert/strict";
+import { readFile } from "node:fs/promises";
+import { test } from "node:test";
+
+const WASM = process.env.OCTORUST_WASM
+    ?? new URL("../target/wasm32-unknown-unknown/release/octorust.wasm", import.meta.url);
+
+/**
+ * Draws the font's 5 at the top left, sounds the buzzer for 2 frames and clears the screen
+ * while key 7 is held.
+ */
+const ROM = [
+    0xA0, 0x69, 0x61, 0x00, 0xD1, 0x15, 0x62, 0x02, 0xF2, 0x18, 0x63, 0x07, 0xE3, 0xA1, 0x00, 0xE0, 0x12, 0x0C,
+];
+
+async function emulator() {
+    return Octorust.load(await readFile(WASM));
+}
+
+
+test("runs frames", async () => {
+    const octorust = await emulator();
+    assert.equal(octorust.width, 64);
+    assert.equal(octorust.height, 32);
+    octorust.loadRom(ROM);
+    octorust.runFrame();
+
+    const pixels = octorust.pixels();
+    assert.equal(pixels.length, 64 * 32);
+    assert.deepEqual([...pixels.subarray(0, 5)], [1, 1, 1, 1, 0]);
+    assert.deepEqual([...pixels.subarray(64, 69)], [1, 0, 0, 0, 0]);
+    assert.ok(octorust.sound());
+    octorust.runFrame();
+    octorust.runFrame();
+    assert.ok(!octorust.sound(), "the sound timer ran out");
+});
+
+test("takes keys", async () => {
+    const octorust = await emulator();
+    octorust.loadRom(new Uint8Array(ROM));
+    octorust.runFrame();
+    octorust.setKey(KEYMAP.KeyA, true);
+    octorust.runFrame();
+    assert.ok(octorust.pixels().every((pixel) => pixel === 0), "key 7 cleared the screen");
+});
+
+test("loads and resets", async () => {
+    const octorust = await emulator();
+    assert.throws(() => octorust.loadRom(new Uint8Array(4096)), /doesn't fit/);
+
+    octorust.loadRom(ROM);
+    octorust.setSpeed(2);
+    octorust.runFrame();
+    assert.ok(octorust.pixels().every((pixel) => pixel === 0), "two instructions don't get to the draw");
+    octorust.setSpeed(3);
+    octorust.runFrame();
+    assert.equal(octorust.pixels()[0], 1);
+    octorust.reset();
+    octorust.setSpeed(2);
+    octorust.runFrame();
+    assert.ok(octorust.pixels().every((pixel) => pixel === 0), "the reset cleared the screen");
+});
+
+test("paints the palette", () => {
+    const rgba = new Uint8ClampedArray(8);
+    paint([1, 0], rgba);
+    assert.deepEqual([...rgba], [...FOREGROUND, 255, ...BACKGROUND, 255]);
+});
+
+test("maps keys by position", () => {
+    const keys = Object.values(KEYMAP).sort((a, b) => a - b);
+    assert.deepEqual(keys, [...Array(16).keys()]);
+});